@@ -0,0 +1,82 @@
+//! An example of extracting an archive with filtering and progress reporting.
+//!
+//! Takes the path of a tarball, a destination directory and optionally a path
+//! prefix as arguments. Every entry whose path starts with the prefix is
+//! extracted into the destination, printing the progress through the archive
+//! as it goes. Entries which fail to extract are reported and skipped rather
+//! than aborting the whole extraction.
+//!
+//! This crate doesn't handle compression itself, so compressed archives are
+//! detected and rejected. A real program would wrap the file in a decompressor
+//! from a crate like `flate2` instead, using `MultiGzDecoder` for gzip so
+//! archives made up of several gzip members are read in full.
+
+extern crate tar;
+
+use std::env::args_os;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tar::Archive;
+
+fn main() {
+    let mut args = args_os().skip(1);
+    let (src, dst) = match (args.next(), args.next()) {
+        (Some(src), Some(dst)) => (PathBuf::from(src), PathBuf::from(dst)),
+        _ => {
+            eprintln!("usage: extract <archive> <destination> [prefix]");
+            process::exit(2);
+        }
+    };
+    let prefix = args.next().map(PathBuf::from).unwrap_or_default();
+
+    if let Err(e) = extract(&src, &dst, &prefix) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn extract(src: &Path, dst: &Path, prefix: &Path) -> io::Result<()> {
+    let mut file = File::open(src)?;
+    let total = file.metadata()?.len();
+
+    let mut magic = [0; 6];
+    let n = file.read(&mut magic)?;
+    if let Some(format) = tar::detect_format(&magic[..n]) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("archive is compressed with {:?}", format),
+        ));
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut ar = Archive::new_with_length(file, total);
+    let mut failed = 0;
+    ar.scan(|entry| {
+        let path = entry.path()?.into_owned();
+        if !path.starts_with(prefix) {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        match entry.unpack_in(dst) {
+            Ok(true) => {}
+            Ok(false) => println!("skipped {} (outside destination)", path.display()),
+            Err(e) => {
+                eprintln!("failed to extract {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+
+        let progress = entry.progress().unwrap_or(0.0);
+        println!("[{:>3.0}%] {}", progress * 100.0, path.display());
+        Ok(ControlFlow::Continue(()))
+    })?;
+
+    if failed > 0 {
+        eprintln!("{} entries failed to extract", failed);
+    }
+    Ok(())
+}
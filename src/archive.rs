@@ -1,16 +1,25 @@
+use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
 use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
 use std::marker;
-use std::path::Path;
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 
-use crate::entry::{EntryFields, EntryIo};
+use filetime::{self, FileTime};
+
+use crate::entry::{open_root, EntryData, EntryFields, EntryIo};
+use crate::entry_type::EntryTypeSet;
 use crate::error::TarError;
+use crate::header::{bytes2path, path2bytes};
 use crate::other;
-use crate::pax::pax_extensions_size;
-use crate::{Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
+use crate::pax::{pax_extensions, pax_extensions_size};
+use crate::{Entry, EntryType, GnuExtSparseHeader, GnuSparseHeader, Header};
 
 /// A top-level representation of an archive file.
 ///
@@ -24,34 +33,375 @@ pub struct ArchiveInner<R: ?Sized> {
     unpack_xattrs: bool,
     preserve_permissions: bool,
     preserve_mtime: bool,
+    preserve_ownerships: bool,
+    numeric_owner: bool,
     overwrite: bool,
+    skip_existing: bool,
     ignore_zeros: bool,
+    unknown_type_policy: UnknownTypePolicy,
+    allowed_entry_types: Option<EntryTypeSet>,
+    skip_disallowed_types: bool,
+    hardlink_fallback: HardlinkFallback,
+    unpack_preallocate: bool,
+    unpack_special_files: bool,
+    secure_extraction: bool,
+    default_file_mode: Option<u32>,
+    default_dir_mode: Option<u32>,
+    mask: u32,
+    windows_name_policy: WindowsNamePolicy,
+    path_encoding: PathEncoding,
+    parentdir_policy: ParentDirPolicy,
+    max_entries: Option<usize>,
+    max_entry_size: Option<u64>,
+    max_total_size: Option<u64>,
+    lenient_numeric_fields: bool,
+    checksum_policy: ChecksumPolicy,
+    checksum_mismatch_handler: Option<RefCell<Box<ChecksumMismatchHandler>>>,
+    unknown_type_handler: Option<RefCell<Box<UnknownTypeHandler>>>,
+    skip_leading_garbage: bool,
+    allow_truncated: bool,
+    resume_from: usize,
+    resume_marker: Option<PathBuf>,
+    unpack_filter: Option<Box<UnpackFilter>>,
+    unpack_hasher: Option<UnpackHasher>,
+    total_len: Option<u64>,
+    decompressors: HashMap<String, Box<Decompressor>>,
+    slice: Option<BorrowedSlice>,
     obj: RefCell<R>,
 }
 
+/// The contents of an archive created with `Archive::from_slice`.
+///
+/// This is a raw pointer as `ArchiveInner` has no lifetime to borrow the
+/// slice for. It's only created from a slice which the archive itself
+/// borrows, so it stays valid for as long as the archive and its entries.
+#[derive(Clone, Copy)]
+struct BorrowedSlice {
+    ptr: *const u8,
+    len: usize,
+}
+
+// Safety: this is only ever a shared borrow of a `[u8]`.
+unsafe impl Send for BorrowedSlice {}
+unsafe impl Sync for BorrowedSlice {}
+
+/// A function wrapping the raw contents of an entry in a decompressor, see
+/// `Archive::register_decompressor`.
+type Decompressor = dyn for<'r> Fn(Box<dyn Read + 'r>) -> Box<dyn Read + 'r> + Send;
+
+/// A function deciding what `Archive::unpack` does with an entry, see
+/// `Archive::set_unpack_filter`.
+type UnpackFilter = dyn for<'e> FnMut(&Path, &Entry<'e, io::Empty>) -> UnpackAction + Send;
+
+/// The functions creating a hasher for each file and receiving its digest,
+/// see `Archive::set_unpack_hasher`.
+struct UnpackHasher {
+    new_hasher: Box<NewHasher>,
+    on_digest: Box<DigestHandler>,
+}
+
+type NewHasher = dyn FnMut() -> Box<dyn EntryHasher> + Send;
+
+type DigestHandler = dyn FnMut(&Path, Vec<u8>) + Send;
+
+/// A function told about headers with a bad checksum, see
+/// `Archive::set_checksum_mismatch_handler`.
+type ChecksumMismatchHandler = dyn FnMut(u64, &Header) + Send;
+
+/// A function told about entries with an unrecognized type flag, see
+/// `Archive::set_unknown_type_handler`.
+type UnknownTypeHandler = dyn FnMut(u64, &Header) + Send;
+
+/// The PAX record naming the algorithm the contents of an entry were
+/// individually compressed with.
+const COMPRESSION_RECORD: &str = "SCHILY.compression";
+
+/// Configures what happens when unpacking an entry whose type can't be
+/// materialized on the filesystem.
+///
+/// This applies to entries with an unrecognized type flag as well as to
+/// special files which this library doesn't know how to create, such as
+/// character/block devices and FIFOs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnknownTypePolicy {
+    /// Skip the entry, leaving nothing behind on the filesystem.
+    Skip,
+    /// Fail the unpacking operation with an error.
+    Error,
+    /// Extract the contents of the entry as a regular file.
+    ///
+    /// This matches the POSIX recommendation of treating unrecognized type
+    /// flags as regular files.
+    ExtractAsFile,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// A hash function computing a digest of the contents of the files written by
+/// `Archive::unpack`, see `Archive::set_unpack_hasher`.
+///
+/// This mirrors the incremental interface of crates like `sha2`, `crc32fast`
+/// or `blake3`, so any of them can be plugged in by forwarding these two
+/// methods.
+pub trait EntryHasher {
+    /// Feeds the next chunk of the contents of the file to the hasher.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consumes the hasher, returning the digest of everything fed to it.
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+/// What `Archive::unpack` does with an entry, as decided by the function
+/// passed to `Archive::set_unpack_filter`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UnpackAction {
+    /// Leave the entry out, unpacking nothing for it.
+    Skip,
+    /// Unpack the entry at its own path.
+    Extract,
+    /// Unpack the entry at this path instead, relative to the destination.
+    ///
+    /// The path is checked the same way as the paths in the archive are, so
+    /// a `..` component in it is handled according to the
+    /// `ParentDirPolicy`: by default the entry is skipped, but it can also
+    /// fail the unpack or have the `..` stripped.
+    RenameTo(PathBuf),
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Configures what happens when reading a header whose checksum doesn't
+/// match its contents.
+///
+/// Checksums computed with signed bytes, as some old implementations of `tar`
+/// did, are accepted as well before a header is considered to have a bad
+/// checksum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChecksumPolicy {
+    /// Fail reading the archive with an error.
+    Strict,
+    /// Read the entry anyway, calling the function passed to
+    /// `Archive::set_checksum_mismatch_handler` if any.
+    Warn,
+    /// Read the entry anyway.
+    Ignore,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Configures what happens when a hard link can't be created while unpacking.
+///
+/// Some filesystems, such as FAT and exFAT, don't support hard links at all,
+/// and hard links can't span filesystems. The fallback is only used for such
+/// errors: a link whose target is missing, for example, is still an error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HardlinkFallback {
+    /// Fail the unpacking operation with an error.
+    Error,
+    /// Create a symlink pointing at the link target instead.
+    ///
+    /// When unpacking into a directory the symlink is relative, so it keeps
+    /// working if the directory is moved.
+    Symlink,
+    /// Copy the contents of the link target instead.
+    Copy,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Configures what happens when unpacking an entry whose path contains a `..`
+/// component.
+///
+/// Such entries could otherwise be written outside of the destination
+/// directory, see e.g. CVE-2001-1267 and CVE-2007-4131.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParentDirPolicy {
+    /// Skip the entry, leaving nothing behind on the filesystem.
+    Skip,
+    /// Fail the unpacking operation with an error naming the path.
+    Error,
+    /// Drop the `..` components and unpack the entry at the rest of its
+    /// path, so `a/../b` is unpacked at `a/b`.
+    Strip,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Configures what happens when unpacking an entry whose path isn't a valid
+/// file name on Windows.
+///
+/// This covers reserved device names such as `CON`, `NUL` or `COM1` (with or
+/// without an extension), names ending in a dot or a space, and names
+/// containing characters such as `:` or `?`. The policy only has an effect
+/// when unpacking on Windows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowsNamePolicy {
+    /// Fail the unpacking operation with an error.
+    Error,
+    /// Rename the offending path component and unpack the entry anyway.
+    ///
+    /// Invalid characters and trailing dots and spaces are percent-encoded
+    /// (`a:b.` becomes `a%3Ab%2E`), as is the last character of the stem of
+    /// reserved device names (`CON.txt` becomes `CO%4E.txt`). A `%` is
+    /// encoded as well, in every name, so no two names are renamed to the
+    /// same one (`a%3F` becomes `a%253F`). Link targets are not renamed.
+    Sanitize,
+    /// Skip the entry, in the same way as entries containing `..`.
+    Skip,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Configures how the path names and link names of entries are decoded from
+/// the bytes stored in the archive.
+///
+/// Archives don't record which encoding their names are in. Most use UTF-8,
+/// but some written by Windows tools use a legacy code page instead. Names in
+/// other encodings can be decoded from `Entry::path_bytes` and unpacked under
+/// the decoded name with `UnpackAction::RenameTo` from an unpack filter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathEncoding {
+    /// Use the bytes as they are on Unix, where paths are arbitrary bytes.
+    ///
+    /// On Windows names have to be valid UTF-8 and reading any other name is
+    /// an error, as they can't be converted to UTF-16.
+    Native,
+    /// Require names to be valid UTF-8 on all platforms, returning an error
+    /// otherwise.
+    Utf8,
+    /// Replace invalid UTF-8 sequences with `U+FFFD REPLACEMENT CHARACTER` on
+    /// all platforms.
+    ///
+    /// This never fails, but different names may be decoded to the same path.
+    Lossy,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
 /// An iterator over the entries of an archive.
 pub struct Entries<'a, R: 'a + Read> {
     fields: EntriesFields<'a>,
     _ignored: marker::PhantomData<&'a Archive<R>>,
 }
 
+/// An iterator over the metadata of the entries of an archive.
+///
+/// This is created by `Archive::list`.
+pub struct List<'a, R: 'a + Read> {
+    fields: EntriesFields<'a>,
+    _ignored: marker::PhantomData<&'a Archive<R>>,
+}
+
+/// The metadata of an entry of an archive, without access to its contents.
+///
+/// This is yielded by the `List` iterator.
+pub struct ListEntry {
+    header: Header,
+    size: u64,
+    path: Vec<u8>,
+    link_name: Option<Vec<u8>>,
+}
+
+/// The location of an entry within a seekable archive, as recorded by
+/// `Archive::index`.
+///
+/// This can be passed to `Archive::entry_at` to read the entry again without
+/// scanning the archive from the start.
+#[derive(Clone, Debug)]
+pub struct EntryIndex {
+    path: Vec<u8>,
+    start: u64,
+    header_offset: u64,
+    data_offset: u64,
+    size: u64,
+    pax_global: Option<Vec<u8>>,
+}
+
+trait SeekRead: Read + Seek {}
+impl<R: Read + Seek> SeekRead for R {}
+
+/// The seekable archive an entry was read from, which the entry seeks in to
+/// implement `Seek`.
+#[derive(Clone, Copy)]
+pub struct SeekableArchive<'a> {
+    archive: &'a Archive<dyn Read + 'a>,
+    seekable: &'a Archive<dyn SeekRead + 'a>,
+}
+
 struct EntriesFields<'a> {
     archive: &'a Archive<dyn Read + 'a>,
+    seekable_archive: Option<&'a Archive<dyn SeekRead + 'a>>,
     next: u64,
+    padding: u64,
+    end_marker: bool,
+    entries: usize,
     done: bool,
     raw: bool,
+    // The records of all global pax extensions seen so far
+    pax_global: Option<Vec<u8>>,
+    // The path in the header of the last entry read, for errors about its
+    // contents
+    last_path: Vec<u8>,
 }
 
 impl<R: Read> Archive<R> {
     /// Create a new archive with the underlying object as the reader.
     pub fn new(obj: R) -> Archive<R> {
+        Archive::_new(obj, None)
+    }
+
+    /// Create a new archive with the underlying object as the reader, which
+    /// is known to yield `total_len` bytes in total.
+    ///
+    /// Archives can't discover their length themselves unless the reader is
+    /// seekable, but the length is often known ahead of time anyway, for
+    /// example from the `Content-Length` of an HTTP response. Declaring it
+    /// here allows `Entry::progress` to report how far through the archive
+    /// each entry is.
+    pub fn new_with_length(obj: R, total_len: u64) -> Archive<R> {
+        Archive::_new(obj, Some(total_len))
+    }
+
+    fn _new(obj: R, total_len: Option<u64>) -> Archive<R> {
         Archive {
             inner: ArchiveInner {
                 unpack_xattrs: false,
                 preserve_permissions: false,
                 preserve_mtime: true,
+                preserve_ownerships: false,
+                numeric_owner: false,
                 overwrite: true,
+                skip_existing: false,
                 ignore_zeros: false,
+                unknown_type_policy: UnknownTypePolicy::Skip,
+                allowed_entry_types: None,
+                skip_disallowed_types: false,
+                hardlink_fallback: HardlinkFallback::Error,
+                unpack_preallocate: false,
+                unpack_special_files: false,
+                secure_extraction: false,
+                default_file_mode: None,
+                default_dir_mode: None,
+                mask: 0,
+                windows_name_policy: WindowsNamePolicy::Error,
+                path_encoding: PathEncoding::Native,
+                parentdir_policy: ParentDirPolicy::Skip,
+                max_entries: None,
+                max_entry_size: None,
+                max_total_size: None,
+                lenient_numeric_fields: false,
+                checksum_policy: ChecksumPolicy::Strict,
+                checksum_mismatch_handler: None,
+                unknown_type_handler: None,
+                skip_leading_garbage: false,
+                allow_truncated: false,
+                resume_from: 0,
+                resume_marker: None,
+                unpack_filter: None,
+                unpack_hasher: None,
+                total_len,
+                decompressors: HashMap::new(),
+                slice: None,
                 obj: RefCell::new(obj),
                 pos: Cell::new(0),
             },
@@ -63,29 +413,811 @@ impl<R: Read> Archive<R> {
         self.inner.obj.into_inner()
     }
 
+    /// Returns the length of the archive declared with `new_with_length`, if
+    /// any.
+    pub fn total_len(&self) -> Option<u64> {
+        self.inner.total_len
+    }
+
     /// Construct an iterator over the entries in this archive.
     ///
-    /// Note that care must be taken to consider each entry within an archive in
-    /// sequence. If entries are processed out of sequence (from what the
-    /// iterator returns), then the contents read for each entry may be
-    /// corrupted.
-    pub fn entries(&mut self) -> io::Result<Entries<R>> {
-        let me: &mut Archive<dyn Read> = self;
-        me._entries().map(|fields| Entries {
-            fields: fields,
+    /// Note that care must be taken to consider each entry within an archive in
+    /// sequence. If entries are processed out of sequence (from what the
+    /// iterator returns), then the contents read for each entry may be
+    /// corrupted.
+    ///
+    /// An archive can only be iterated over once: this returns an error if
+    /// any part of the archive has already been read, for example by a
+    /// previous call to this method.
+    pub fn entries(&mut self) -> io::Result<Entries<R>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._entries(None).map(|fields| Entries {
+            fields: fields,
+            _ignored: marker::PhantomData,
+        })
+    }
+
+    /// Construct an iterator over every entry physically stored in this
+    /// archive, including the extension entries which `entries` applies to
+    /// the entry following them.
+    ///
+    /// GNU long name and long link entries, pax extended headers and global
+    /// pax headers are yielded as entries of their own, with their contents
+    /// readable like any other entry, and nothing they describe is applied
+    /// to the entries after them. This is the same as calling `raw(true)` on
+    /// the iterator returned by `entries`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.entries_raw().unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     println!(
+    ///         "{:?} at offset {}",
+    ///         entry.header().entry_type(),
+    ///         entry.raw_header_position()
+    ///     );
+    /// }
+    /// ```
+    pub fn entries_raw(&mut self) -> io::Result<Entries<R>> {
+        self.entries().map(|entries| entries.raw(true))
+    }
+
+    /// Calls `f` with each entry in this archive, in sequence.
+    ///
+    /// This is an alternative to `entries` which drives the iteration
+    /// internally, which can be easier to use where holding on to the
+    /// borrowing iterator is awkward. The callback can return
+    /// `ControlFlow::Break` to stop early, and any error returned by the
+    /// callback or encountered while reading the archive stops the iteration
+    /// and is returned.
+    ///
+    /// Like `entries`, this can only be called once on an archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    /// use std::ops::ControlFlow;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut contents = String::new();
+    /// ar.scan(|entry| {
+    ///     if entry.path()?.ends_with("Cargo.toml") {
+    ///         entry.read_to_string(&mut contents)?;
+    ///         return Ok(ControlFlow::Break(()));
+    ///     }
+    ///     Ok(ControlFlow::Continue(()))
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn scan<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&mut Entry<R>) -> io::Result<ControlFlow<()>>,
+    {
+        for entry in self.entries()? {
+            if let ControlFlow::Break(()) = f(&mut entry?)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks the contents tarball into the specified `dst`.
+    ///
+    /// This function will iterate over the entire contents of this tarball,
+    /// extracting each file in turn to the location specified by the entry's
+    /// path name.
+    ///
+    /// This operation is relatively sensitive in that it will not write files
+    /// outside of the path specified by `dst`. Files in the archive which have
+    /// a '..' in their path are skipped during the unpacking process.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack(dst.as_ref())
+    }
+
+    /// Reads the contents of the regular files in this archive into memory,
+    /// keyed by their paths, without touching the filesystem.
+    ///
+    /// Paths are sanitized the same way as when unpacking, see
+    /// `Entry::sanitized_relative_path`. Files whose paths would be skipped
+    /// when unpacking are left out, and paths which would fail to unpack,
+    /// for example under `ParentDirPolicy::Error`, are an error here too.
+    /// Directories, links and special files are left out as well. If a path
+    /// appears more than once the last entry wins, as it would when
+    /// unpacking.
+    ///
+    /// If `max_size` is given, an error is returned as soon as the sizes of
+    /// the files read add up to more than it, before their contents are read,
+    /// so an archive can't use up all of the memory of the process. Files
+    /// decompressed by a registered decompressor count with their decompressed
+    /// size, and reading stops as soon as it goes over the limit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::Archive;
+    ///
+    /// let mut data = Vec::new();
+    /// tar::pack_single(&mut data, "hello.txt", b"hello").unwrap();
+    ///
+    /// let mut ar = Archive::new(&data[..]);
+    /// let files = ar.unpack_to_map(Some(1024 * 1024)).unwrap();
+    /// assert_eq!(files[std::path::Path::new("hello.txt")], b"hello");
+    /// ```
+    pub fn unpack_to_map(
+        &mut self,
+        max_size: Option<u64>,
+    ) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_to_map(max_size)
+    }
+
+    /// Indicate whether extended file attributes (xattrs on Unix) are preserved
+    /// when unpacking this archive.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix using xattr support. This may eventually be implemented for
+    /// Windows, however, if other archive implementations are found which do
+    /// this as well.
+    pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) {
+        self.inner.unpack_xattrs = unpack_xattrs;
+    }
+
+    /// Indicate whether extended permissions (like suid on Unix) are preserved
+    /// when unpacking this entry.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_permissions(&mut self, preserve: bool) {
+        self.inner.preserve_permissions = preserve;
+    }
+
+    /// Indicate whether the owner and group of entries are restored when
+    /// unpacking this archive.
+    ///
+    /// Files, directories, symlinks and special files are all changed to be
+    /// owned by the user and group recorded for them, which usually requires
+    /// running as root. The owner is changed before the permissions are set,
+    /// as changing it clears any setuid and setgid bits. How the owner is
+    /// found is controlled by `set_numeric_owner`.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_ownerships(&mut self, preserve: bool) {
+        self.inner.preserve_ownerships = preserve;
+    }
+
+    /// Indicate whether only the numeric uid and gid of entries are used when
+    /// restoring their ownership, see `set_preserve_ownerships`.
+    ///
+    /// When this is disabled the user and group names recorded in the archive
+    /// are looked up on this system first, as GNU tar does, falling back to
+    /// the numeric ids if there's no name or no such user or group. When it's
+    /// enabled the names are ignored. This flag is disabled by default.
+    pub fn set_numeric_owner(&mut self, numeric: bool) {
+        self.inner.numeric_owner = numeric;
+    }
+
+    /// Indicate whether files and symlinks should be overwritten on extraction.
+    ///
+    /// When this is disabled, unpacking an entry whose path already exists
+    /// fails with an error of kind `AlreadyExists`, unless
+    /// `set_skip_existing` is enabled. This flag is enabled by default.
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.inner.overwrite = overwrite;
+    }
+
+    /// Indicate whether entries whose path already exists are skipped when
+    /// overwriting is disabled with `set_overwrite`, rather than failing the
+    /// unpack.
+    ///
+    /// Existing files, symlinks and directories are all left untouched,
+    /// including their permissions and modification times, so local changes
+    /// take precedence over the archive. Symlinks aren't followed when
+    /// checking whether a path exists. This flag is disabled by default.
+    pub fn set_skip_existing(&mut self, skip: bool) {
+        self.inner.skip_existing = skip;
+    }
+
+    /// Indicate whether the modification times of entries are set on the
+    /// files and directories they're unpacked to.
+    ///
+    /// When unpacking the whole archive with `unpack`, the times of
+    /// directories are set once all entries have been unpacked, as creating
+    /// the files inside of a directory would update its modification time
+    /// again. With secure extraction enabled those directories are looked up
+    /// beneath the destination again at that point. Either way a symlink
+    /// found in a directory's place gets the time itself rather than its
+    /// target. This flag is enabled by default.
+    pub fn set_preserve_mtime(&mut self, preserve: bool) {
+        self.inner.preserve_mtime = preserve;
+    }
+
+    /// Configures how entries of an unknown or unsupported type are handled
+    /// when unpacking this archive.
+    ///
+    /// This defaults to `UnknownTypePolicy::Skip`.
+    pub fn set_unknown_type_policy(&mut self, policy: UnknownTypePolicy) {
+        self.inner.unknown_type_policy = policy;
+    }
+
+    /// Restricts the types of entries which may be unpacked from this archive
+    /// to `types`.
+    ///
+    /// Unpacking an entry of any other type fails, or skips the entry if
+    /// `set_skip_disallowed_types` is enabled, before anything is written for
+    /// it. This is useful to reject device nodes, FIFOs and unknown type flags
+    /// in contexts where they'd be suspicious. Entries which are allowed but
+    /// can't be created are still handled by the unknown type policy.
+    ///
+    /// All entry types are allowed by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Archive, EntryType};
+    ///
+    /// let mut ar = Archive::new(&[][..]);
+    /// ar.set_allowed_entry_types(&[
+    ///     EntryType::Regular,
+    ///     EntryType::Directory,
+    ///     EntryType::Symlink,
+    /// ]);
+    /// ```
+    pub fn set_allowed_entry_types(&mut self, types: &[EntryType]) {
+        self.inner.allowed_entry_types = Some(EntryTypeSet::new(types));
+    }
+
+    /// Indicate whether entries whose type isn't allowed by
+    /// `set_allowed_entry_types` are skipped rather than failing the unpack.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_skip_disallowed_types(&mut self, skip: bool) {
+        self.inner.skip_disallowed_types = skip;
+    }
+
+    /// Configures what happens when a hard link entry can't be created as a
+    /// hard link when unpacking this archive.
+    ///
+    /// This defaults to `HardlinkFallback::Error`.
+    pub fn set_hardlink_fallback(&mut self, fallback: HardlinkFallback) {
+        self.inner.hardlink_fallback = fallback;
+    }
+
+    /// Indicate whether space for the contents of files is reserved before
+    /// they're written when unpacking this archive.
+    ///
+    /// When enabled each file is sized to the size of its entry before any
+    /// data is copied into it, using `posix_fallocate` on Linux and
+    /// `File::set_len` elsewhere. This can reduce fragmentation, and on Linux
+    /// a lack of disk space is reported before any data is written. Note that
+    /// this means holes in sparse entries will take up space on disk.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_unpack_preallocate(&mut self, preallocate: bool) {
+        self.inner.unpack_preallocate = preallocate;
+    }
+
+    /// Indicate whether character devices, block devices and FIFOs are
+    /// recreated when unpacking this archive.
+    ///
+    /// When enabled these entries are created with `mknod(2)` using the device
+    /// numbers and mode stored in their headers. Creating device nodes usually
+    /// requires root, so unpacking them fails with a permission error
+    /// otherwise, while FIFOs can be created by anyone. This is only supported
+    /// on Unix, and elsewhere these entries are handled according to
+    /// `set_unknown_type_policy` as they are when this is disabled.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_unpack_special_files(&mut self, unpack: bool) {
+        self.inner.unpack_special_files = unpack;
+    }
+
+    /// Sets the mode files are unpacked with if their archived mode has no
+    /// permission bits set.
+    ///
+    /// Some archives are written with a mode of zero for all entries, which
+    /// would otherwise be unpacked into files nobody can read. GNU tar uses
+    /// the default permissions for such files, and setting a default mode
+    /// such as `0o644` here does the same. Whether special bits in `mode` are
+    /// applied depends on `set_preserve_permissions` as usual.
+    ///
+    /// By default the archived mode is always applied as is.
+    pub fn set_default_file_mode(&mut self, mode: u32) {
+        self.inner.default_file_mode = Some(mode);
+    }
+
+    /// Sets the mode directories are unpacked with if their archived mode has
+    /// no permission bits set, see `set_default_file_mode`.
+    ///
+    /// By default the archived mode is always applied as is.
+    pub fn set_default_dir_mode(&mut self, mode: u32) {
+        self.inner.default_dir_mode = Some(mode);
+    }
+
+    /// Sets permission bits which are cleared from the mode of every file
+    /// and directory unpacked, like a umask.
+    ///
+    /// The mode applied is the archived mode, or the default mode set with
+    /// `set_default_file_mode` or `set_default_dir_mode`, with the bits of
+    /// `mask` removed, as in `mode & !mask`. For example a mask of `0o7022`
+    /// keeps an archive from creating setuid, setgid or sticky files even
+    /// when `set_preserve_permissions` is enabled, and from creating files
+    /// which anyone but the owner can write to.
+    ///
+    /// By default no bits are masked out.
+    pub fn set_mask(&mut self, mask: u32) {
+        self.inner.mask = mask;
+    }
+
+    /// Indicate whether the kernel is used to ensure entries are only written
+    /// inside of the destination directory when unpacking this archive.
+    ///
+    /// By default `unpack` validates the paths it writes to in userspace,
+    /// which leaves a window between checking a path and writing to it in
+    /// which the directories involved could be replaced, for example with a
+    /// symlink by another process. When this is enabled on Linux, the
+    /// destination directory is opened up front and every directory written
+    /// to is looked up relative to it with `openat2` and `RESOLVE_BENEATH`,
+    /// which makes the kernel reject any lookup that leaves the destination,
+    /// and the directories are then used through their file descriptors.
+    /// Symlinks inside the archive which stay within the destination keep
+    /// working, while absolute symlinks can't be traversed at all.
+    ///
+    /// On kernels older than 5.6, when `/proc` isn't mounted, and on other
+    /// platforms this silently falls back to the userspace checks. This flag
+    /// is disabled by default.
+    pub fn set_secure_extraction(&mut self, secure: bool) {
+        self.inner.secure_extraction = secure;
+    }
+
+    /// Configures what happens when an entry's path isn't a valid file name
+    /// on Windows when unpacking this archive with `unpack` or
+    /// `Entry::unpack_in`.
+    ///
+    /// This defaults to `WindowsNamePolicy::Error`.
+    pub fn set_windows_name_policy(&mut self, policy: WindowsNamePolicy) {
+        self.inner.windows_name_policy = policy;
+    }
+
+    /// Configures how the path names and link names of entries are decoded,
+    /// which affects `Entry::path`, `Entry::link_name` and unpacking.
+    ///
+    /// This defaults to `PathEncoding::Native`.
+    pub fn set_path_encoding(&mut self, encoding: PathEncoding) {
+        self.inner.path_encoding = encoding;
+    }
+
+    /// Configures what happens when an entry's path contains a `..`
+    /// component when unpacking this archive with `unpack` or
+    /// `Entry::unpack_in`.
+    ///
+    /// This defaults to `ParentDirPolicy::Skip`.
+    pub fn set_parentdir_policy(&mut self, policy: ParentDirPolicy) {
+        self.inner.parentdir_policy = policy;
+    }
+
+    /// Limits the number of entries that can be read from this archive.
+    ///
+    /// Once more than `max_entries` entries have been encountered iterating
+    /// over the archive returns an error, which also aborts `unpack`. This
+    /// guards against untrusted archives containing huge numbers of small
+    /// files. Extension entries, such as GNU long names or pax headers, don't
+    /// count towards the limit unless the entries are iterated in raw mode.
+    ///
+    /// By default the number of entries is unlimited.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.inner.max_entries = Some(max_entries);
+    }
+
+    /// Limits the size of the contents of any one entry which is unpacked or
+    /// read with `Entry::contents`.
+    ///
+    /// The size is checked against the header before anything is written, so
+    /// an untrusted archive claiming to contain a huge file fails right away
+    /// rather than filling up the disk. Entries decompressed by a registered
+    /// decompressor are also stopped as soon as their decompressed contents
+    /// go over the limit. Entries can still be read with their `Read`
+    /// implementation regardless of their size.
+    ///
+    /// By default the size of entries is unlimited.
+    pub fn set_max_entry_size(&mut self, max_size: u64) {
+        self.inner.max_entry_size = Some(max_size);
+    }
+
+    /// Limits the total size of the contents of all entries unpacked with
+    /// `unpack`.
+    ///
+    /// Unpacking fails once the sizes of the entries unpacked so far add up
+    /// to more than `max_size`, before the contents of the entry which
+    /// crossed the limit are written. Entries decompressed by a registered
+    /// decompressor count with their decompressed size, and unpacking fails
+    /// as soon as writing them goes over the limit. Entries skipped by an
+    /// unpack filter don't count towards the limit.
+    ///
+    /// By default the total size is unlimited.
+    pub fn set_max_total_size(&mut self, max_size: u64) {
+        self.inner.max_total_size = Some(max_size);
+    }
+
+    /// Configures what happens when a header's checksum doesn't match its
+    /// contents.
+    ///
+    /// Relaxing this allows recovering data from slightly damaged archives,
+    /// and `Entry::has_bad_checksum` tells which entries were affected. Note
+    /// that a header whose checksum is wrong may well have other corrupted
+    /// fields too. This defaults to `ChecksumPolicy::Strict`.
+    pub fn set_checksum_policy(&mut self, policy: ChecksumPolicy) {
+        self.inner.checksum_policy = policy;
+    }
+
+    /// Sets a function which is called for every header with a bad checksum
+    /// when the checksum policy is `ChecksumPolicy::Warn`.
+    ///
+    /// The function is passed the position of the header in the archive and
+    /// the header itself.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, ChecksumPolicy};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_checksum_policy(ChecksumPolicy::Warn);
+    /// ar.set_checksum_mismatch_handler(|pos, _header| {
+    ///     eprintln!("bad checksum for header at offset {}", pos);
+    /// });
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn set_checksum_mismatch_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(u64, &Header) + Send + 'static,
+    {
+        self.inner.checksum_mismatch_handler = Some(RefCell::new(Box::new(handler)));
+    }
+
+    /// Sets a function which is called for every entry whose type flag isn't
+    /// one this library recognizes, such as vendor specific extensions.
+    ///
+    /// The function is passed the position of the header in the archive and
+    /// the header itself, whose `entry_type` gives the unrecognized type flag.
+    /// Such entries are still returned when iterating over the archive, and
+    /// what unpacking them does is configured with
+    /// `set_unknown_type_policy`, which skips them by default.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_unknown_type_handler(|pos, header| {
+    ///     eprintln!(
+    ///         "skipping entry of unknown type {:?} at offset {}",
+    ///         header.entry_type(),
+    ///         pos
+    ///     );
+    /// });
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn set_unknown_type_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(u64, &Header) + Send + 'static,
+    {
+        self.inner.unknown_type_handler = Some(RefCell::new(Box::new(handler)));
+    }
+
+    /// Indicate whether numeric header fields which aren't valid octal are
+    /// interpreted as decimal or hexadecimal instead.
+    ///
+    /// Numeric fields in tar headers are octal, but some broken writers have
+    /// emitted other radixes. When enabled, the mode, uid, gid, size and
+    /// mtime fields of headers with such fields are rewritten as octal before
+    /// the entry is returned, guessing hexadecimal if the field has a `0x`
+    /// prefix or contains any of the digits `a-f` and decimal otherwise. Note
+    /// that fields which only use the digits `0-7` are always read as octal.
+    ///
+    /// This flag is disabled by default, in which case such fields produce a
+    /// "non-octal digit in numeric field" error when they're read.
+    pub fn set_lenient_numeric_fields(&mut self, lenient: bool) {
+        self.inner.lenient_numeric_fields = lenient;
+    }
+
+    /// Indicate whether bytes preceding the first header of the archive are
+    /// skipped.
+    ///
+    /// Archives are occasionally prefixed with stray bytes, such as a byte
+    /// order mark or a line of log output, which makes the first header fail
+    /// to validate. When this is enabled and the first header of the archive
+    /// has an invalid checksum, the archive is scanned forward one byte at a
+    /// time for the first block which validates as a header and reading
+    /// starts from there, so the prefix doesn't need to be a multiple of the
+    /// block size. Positions reported by entries still count the skipped
+    /// bytes.
+    ///
+    /// As the reader is consumed a byte at a time during the scan, wrapping
+    /// an unbuffered reader in a `BufReader` is recommended. This flag is
+    /// disabled by default since it can mask real corruption at the start of
+    /// an archive.
+    pub fn set_skip_leading_garbage(&mut self, skip: bool) {
+        self.inner.skip_leading_garbage = skip;
+    }
+
+    /// Indicate whether an archive which ends in the middle of the contents
+    /// of an entry is read as far as possible rather than failing.
+    ///
+    /// When this is enabled, reading or unpacking such an entry produces the
+    /// bytes which are available, `Entry::was_truncated` returns `true` once
+    /// they've been read, and iteration then ends as if the archive had been
+    /// terminated there. This allows salvaging what's left of a damaged
+    /// archive, much like `tar` does.
+    ///
+    /// This flag is disabled by default, in which case unpacking the entry and
+    /// moving on to the next one fail.
+    pub fn set_allow_truncated(&mut self, allow: bool) {
+        self.inner.allow_truncated = allow;
+    }
+
+    /// Starts unpacking this archive at the entry with index `index`, where
+    /// the first entry has index 0.
+    ///
+    /// Earlier entries are still read from the archive to find where the
+    /// entry starts, but they aren't unpacked. This can be used to continue
+    /// an extraction which was interrupted, see also `set_resume_marker`.
+    ///
+    /// This only affects `unpack` and defaults to 0.
+    pub fn set_resume_from(&mut self, index: usize) {
+        self.inner.resume_from = index;
+    }
+
+    /// Sets a function which decides what `unpack` does with each entry.
+    ///
+    /// The function is called with the path the entry would be unpacked at,
+    /// relative to the destination and with GNU long names and pax
+    /// extensions taken into account, along with the entry itself. The path
+    /// is sanitized as described in `Entry::sanitized_relative_path`, so
+    /// `..` components are handled according to the `ParentDirPolicy` first:
+    /// entries which are skipped or fail because of them never reach the
+    /// function, and with `ParentDirPolicy::Strip` it sees the path with the
+    /// `..` removed. Returning `UnpackAction::Skip` leaves the entry
+    /// out and `UnpackAction::RenameTo` unpacks it at another path instead.
+    /// Note that the targets of links aren't renamed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, UnpackAction};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_unpack_filter(|path, _entry| {
+    ///     if path.starts_with("node_modules") {
+    ///         UnpackAction::Skip
+    ///     } else {
+    ///         UnpackAction::Extract
+    ///     }
+    /// });
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn set_unpack_filter<F>(&mut self, filter: F)
+    where
+        F: for<'e> FnMut(&Path, &Entry<'e, io::Empty>) -> UnpackAction + Send + 'static,
+    {
+        self.inner.unpack_filter = Some(Box::new(filter));
+    }
+
+    /// Sets functions which hash the contents of each file written by
+    /// `unpack`, reporting the digests as they're computed.
+    ///
+    /// `new_hasher` is called to create a hasher for every regular file
+    /// which is unpacked, and the contents are fed to it as they're written
+    /// out, so the archive is still only read once. The holes of sparse files
+    /// are hashed as the zeros they read back as. Once the file has been
+    /// written, `on_digest` is called with the path it was unpacked at and
+    /// its digest. Entries which are skipped, and entries which aren't
+    /// regular files, aren't hashed.
+    ///
+    /// This only applies to `unpack`, not to entries unpacked individually.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::sync::{Arc, Mutex};
+    /// use tar::{Archive, EntryHasher};
+    ///
+    /// // A stand-in for a real hash function such as SHA-256
+    /// #[derive(Default)]
+    /// struct Sum(u64);
+    ///
+    /// impl EntryHasher for Sum {
+    ///     fn update(&mut self, data: &[u8]) {
+    ///         self.0 = data.iter().fold(self.0, |sum, &b| sum.wrapping_add(b as u64));
+    ///     }
+    ///
+    ///     fn finish(self: Box<Self>) -> Vec<u8> {
+    ///         self.0.to_be_bytes().to_vec()
+    ///     }
+    /// }
+    ///
+    /// let manifest = Arc::new(Mutex::new(Vec::new()));
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let m = manifest.clone();
+    /// ar.set_unpack_hasher(Sum::default, move |path, digest| {
+    ///     m.lock().unwrap().push((path.to_path_buf(), digest));
+    /// });
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn set_unpack_hasher<F, H, G>(&mut self, mut new_hasher: F, on_digest: G)
+    where
+        F: FnMut() -> H + Send + 'static,
+        H: EntryHasher + 'static,
+        G: FnMut(&Path, Vec<u8>) + Send + 'static,
+    {
+        self.inner.unpack_hasher = Some(UnpackHasher {
+            new_hasher: Box::new(move || Box::new(new_hasher()) as Box<dyn EntryHasher>),
+            on_digest: Box::new(on_digest),
+        });
+    }
+
+    /// Records the progress of `unpack` in the file at `path` so an
+    /// interrupted extraction can be resumed.
+    ///
+    /// After each entry is unpacked the number of entries which have been
+    /// unpacked so far is written to the file. If the file already exists
+    /// when `unpack` starts, that many entries are skipped (or the number
+    /// given to `set_resume_from`, whichever is larger), so running the same
+    /// extraction again picks up where it left off. The file is removed once
+    /// the whole archive has been unpacked.
+    ///
+    /// The marker only records a position in the archive, so it's up to the
+    /// caller to make sure it's used with the same archive and destination.
+    pub fn set_resume_marker<P: AsRef<Path>>(&mut self, path: P) {
+        self.inner.resume_marker = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Registers a decompressor for entries whose contents were compressed
+    /// individually with the algorithm named `key`.
+    ///
+    /// Some backup formats compress each entry separately and record the
+    /// algorithm in a `SCHILY.compression` PAX record. When the value of that
+    /// record matches `key`, `factory` is called with a reader over the raw
+    /// contents of the entry and the reader it returns is used for all reads
+    /// from the entry, including when unpacking it. Entries without the
+    /// record, or with an algorithm nothing is registered for, are read raw.
+    ///
+    /// Note that `Entry::size` and the header of such entries still describe
+    /// the compressed contents. Registering a second decompressor for the
+    /// same `key` replaces the first one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use tar::Archive;
+    ///
+    /// # fn decoder<'a>(r: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> { r }
+    /// let mut ar = Archive::new(&[0; 1024][..]);
+    /// ar.register_decompressor("gzip", |raw| decoder(raw));
+    /// ```
+    pub fn register_decompressor<F>(&mut self, key: &str, factory: F)
+    where
+        F: for<'r> Fn(Box<dyn Read + 'r>) -> Box<dyn Read + 'r> + Send + 'static,
+    {
+        self.inner
+            .decompressors
+            .insert(key.to_string(), Box::new(factory));
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
+    /// entries.
+    ///
+    /// This can be used in case multiple tar archives have been concatenated together,
+    /// or when an archive is followed by more zeroed blocks than its end-of-archive
+    /// marker, like GNU tar's `--ignore-zeros`. Iteration then ends at the end of the
+    /// underlying reader instead.
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.inner.ignore_zeros = ignore_zeros;
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Construct an iterator over the entries in this archive, seeking over
+    /// the contents of entries which aren't read.
+    ///
+    /// This works like `entries`, except that the entries yielded implement
+    /// `Seek` to move around in their contents, and skipping to the next
+    /// entry seeks rather than reading through the rest of the previous one.
+    ///
+    /// Like `entries`, this can only be called once on an archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::{Read, Seek, SeekFrom};
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.entries_with_seek().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     if entry.size() < 16 {
+    ///         continue;
+    ///     }
+    ///     let mut trailer = [0; 16];
+    ///     entry.seek(SeekFrom::End(-16)).unwrap();
+    ///     entry.read_exact(&mut trailer).unwrap();
+    /// }
+    /// ```
+    pub fn entries_with_seek(&mut self) -> io::Result<Entries<R>> {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        me._entries(Some(me_seekable)).map(|fields| Entries {
+            fields,
+            _ignored: marker::PhantomData,
+        })
+    }
+
+    /// Construct an iterator over the metadata of the entries in this archive.
+    ///
+    /// This is a fast path for listing the contents of an archive, like
+    /// `tar -t`. The items yielded don't provide access to the contents of the
+    /// entries, and the contents are always seeked over rather than read.
+    ///
+    /// Like `entries`, this can only be called once on an archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.list().unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     println!("{} ({} bytes)", entry.path().unwrap().display(), entry.size());
+    /// }
+    /// ```
+    pub fn list(&mut self) -> io::Result<List<R>> {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        me._entries(Some(me_seekable)).map(|fields| List {
+            fields,
             _ignored: marker::PhantomData,
         })
     }
 
-    /// Unpacks the contents tarball into the specified `dst`.
+    /// Checks that the structure of this archive is sound without reading the
+    /// contents of its entries.
     ///
-    /// This function will iterate over the entire contents of this tarball,
-    /// extracting each file in turn to the location specified by the entry's
-    /// path name.
+    /// Every header in the archive is visited, seeking over the contents of
+    /// the entries in between, and its checksum is validated. Finally the
+    /// archive has to be terminated by the two zeroed blocks which mark its
+    /// end. Only the contents of extension entries, such as pax headers, are
+    /// read as they may be needed to find the following headers.
     ///
-    /// This operation is relatively sensitive in that it will not write files
-    /// outside of the path specified by `dst`. Files in the archive which have
-    /// a '..' in their path are skipped during the unpacking process.
+    /// Like `entries`, this can only be called once on an archive, and it
+    /// ignores `set_ignore_zeros` and `set_checksum_policy`.
     ///
     /// # Examples
     ///
@@ -94,71 +1226,308 @@ impl<R: Read> Archive<R> {
     /// use tar::Archive;
     ///
     /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
-    /// ar.unpack("foo").unwrap();
+    /// ar.verify_checksums().unwrap();
     /// ```
-    pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
-        let me: &mut Archive<dyn Read> = self;
-        me._unpack(dst.as_ref())
+    pub fn verify_checksums(&mut self) -> io::Result<()> {
+        let ignore_zeros = self.inner.ignore_zeros;
+        let checksum_policy = self.inner.checksum_policy;
+        self.inner.ignore_zeros = false;
+        self.inner.checksum_policy = ChecksumPolicy::Strict;
+        let res = self.list().and_then(|mut list| {
+            for entry in &mut list {
+                entry?;
+            }
+            list.fields.verify_end_marker()
+        });
+        self.inner.ignore_zeros = ignore_zeros;
+        self.inner.checksum_policy = checksum_policy;
+        res
     }
 
-    /// Indicate whether extended file attributes (xattrs on Unix) are preserved
-    /// when unpacking this archive.
+    /// Finds the entry at `path` in this archive, returning it ready to read
+    /// its contents.
     ///
-    /// This flag is disabled by default and is currently only implemented on
-    /// Unix using xattr support. This may eventually be implemented for
-    /// Windows, however, if other archive implementations are found which do
-    /// this as well.
-    pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) {
-        self.inner.unpack_xattrs = unpack_xattrs;
+    /// The headers of the archive are walked in order and the contents of
+    /// the entries before the one found are seeked over rather than read.
+    /// Paths are compared component by component, ignoring `.` components,
+    /// after GNU long names and pax extensions are taken into account, so
+    /// `./foo/bar` finds an entry at `foo/bar`. The first matching entry is
+    /// returned and `Ok(None)` is returned if no entry matches.
+    ///
+    /// The underlying reader is left positioned after the header of the entry
+    /// found, or at the end of the archive, and isn't rewound. Like
+    /// `entries`, this can only be called once on an archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut entry = ar.entry_by_path("foo/bar.txt").unwrap().unwrap();
+    /// let mut contents = String::new();
+    /// entry.read_to_string(&mut contents).unwrap();
+    /// ```
+    pub fn entry_by_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Option<Entry<R>>> {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        let path = path.as_ref();
+        for entry in me._entries(Some(me_seekable))? {
+            let entry = entry?;
+            if same_path(&entry.path()?, path) {
+                return Ok(Some(EntryFields::from(entry).into_entry()));
+            }
+        }
+        Ok(None)
     }
 
-    /// Indicate whether extended permissions (like suid on Unix) are preserved
-    /// when unpacking this entry.
+    /// Builds an index of the entries in this archive, recording where each
+    /// of them is found.
     ///
-    /// This flag is disabled by default and is currently only implemented on
-    /// Unix.
-    pub fn set_preserve_permissions(&mut self, preserve: bool) {
-        self.inner.preserve_permissions = preserve;
+    /// Only the headers of the archive are read, the contents of the entries
+    /// are seeked over. The index can then be used to read individual entries
+    /// with `entry_at` in any order, which is much faster than scanning the
+    /// archive for each of them. The archive is read from its start no matter
+    /// where it was left, and the reader is left positioned at its end.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let index = ar.index().unwrap();
+    /// for item in index.iter().rev() {
+    ///     let mut contents = Vec::new();
+    ///     ar.entry_at(item).unwrap().read_to_end(&mut contents).unwrap();
+    /// }
+    /// ```
+    pub fn index(&self) -> io::Result<Vec<EntryIndex>> {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        me_seekable.seek_to(0)?;
+        let mut fields = me._entries(Some(me_seekable))?;
+        let mut index = Vec::new();
+        loop {
+            let start = fields.next;
+            let pax_global = fields.pax_global.clone();
+            let entry = match fields.next() {
+                Some(entry) => entry?,
+                None => break,
+            };
+            index.push(EntryIndex {
+                path: entry.path_bytes().into_owned(),
+                start,
+                header_offset: entry.raw_header_position(),
+                data_offset: entry.raw_file_position(),
+                size: entry.size(),
+                pax_global,
+            });
+        }
+        Ok(index)
     }
 
-    /// Indicate whether files and symlinks should be overwritten on extraction.
-    pub fn set_overwrite(&mut self, overwrite: bool) {
-        self.inner.overwrite = overwrite;
+    /// Reads the entry recorded in `index`, which was built by calling
+    /// `index` on this archive.
+    ///
+    /// The reader is seeked to the entry, so this can be called any number of
+    /// times in any order. An error is returned if the entry can't be found
+    /// where the index says it is.
+    pub fn entry_at(&mut self, index: &EntryIndex) -> io::Result<Entry<R>> {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        me_seekable.seek_to(index.start)?;
+        let mut fields = EntriesFields {
+            archive: me,
+            seekable_archive: Some(me_seekable),
+            done: false,
+            next: index.start,
+            padding: 0,
+            end_marker: false,
+            entries: 0,
+            raw: false,
+            pax_global: index.pax_global.clone(),
+            last_path: Vec::new(),
+        };
+        match fields.next_entry()? {
+            Some(entry) if entry.raw_header_position() == index.header_offset => {
+                Ok(EntryFields::from(entry).into_entry())
+            }
+            _ => Err(other(&format!(
+                "no entry found at offset {} of the archive",
+                index.header_offset
+            ))),
+        }
     }
 
-    /// Indicate whether access time information is preserved when unpacking
-    /// this entry.
+    /// Returns the number of bytes between the current position of the
+    /// underlying reader and its end.
     ///
-    /// This flag is enabled by default.
-    pub fn set_preserve_mtime(&mut self, preserve: bool) {
-        self.inner.preserve_mtime = preserve;
+    /// This can be used to estimate the progress through an archive. The
+    /// position of the underlying reader is left unchanged. While iterating
+    /// over the archive with `list` the same information is available through
+    /// `List::bytes_remaining`.
+    pub fn bytes_remaining(&self) -> io::Result<u64> {
+        let me: &Archive<dyn SeekRead> = self;
+        me._bytes_remaining()
     }
+}
 
-    /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
-    /// entries.
+/// Compares two paths, ignoring `.` components.
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.components()
+        .filter(|c| *c != Component::CurDir)
+        .eq(b.components().filter(|c| *c != Component::CurDir))
+}
+
+/// Reads an archive containing exactly one entry, returning its path and
+/// contents.
+///
+/// This is a shorthand for the common case of an archive wrapping a single
+/// file, as produced by `pack_single`. An error is returned if the archive is
+/// empty or contains more than one entry.
+///
+/// # Examples
+///
+/// ```
+/// let mut data = Vec::new();
+/// tar::pack_single(&mut data, "hello.txt", b"hello").unwrap();
+///
+/// let (path, contents) = tar::unpack_single(&data[..]).unwrap();
+/// assert_eq!(path.to_str(), Some("hello.txt"));
+/// assert_eq!(contents, b"hello");
+/// ```
+pub fn unpack_single<R: Read>(r: R) -> io::Result<(PathBuf, Vec<u8>)> {
+    let mut ar = Archive::new(r);
+    let mut entries = ar.entries()?;
+    let mut entry = match entries.next() {
+        Some(entry) => entry?,
+        None => return Err(other("archive does not contain any entries")),
+    };
+    let path = entry.path()?.into_owned();
+    let mut contents = Vec::with_capacity(cmp::min(entry.size(), 128 * 1024) as usize);
+    entry.read_to_end(&mut contents)?;
+    if entries.next().is_some() {
+        return Err(other("archive contains more than one entry"));
+    }
+    Ok((path, contents))
+}
+
+impl<'s> Archive<&'s [u8]> {
+    /// Create a new archive reading from a slice holding the whole archive,
+    /// such as a memory mapped file.
     ///
-    /// This can be used in case multiple tar archives have been concatenated together.
-    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
-        self.inner.ignore_zeros = ignore_zeros;
+    /// This behaves like `Archive::new`, except that the contents of entries
+    /// can additionally be borrowed straight from `data` without copying them
+    /// with `Entry::data_slice`, and entries which aren't read are skipped
+    /// over without copying their contents either.
+    ///
+    /// Mapping the archive into memory is left to the caller, as the slice
+    /// must not change while it's borrowed, which a mapped file being written
+    /// to elsewhere would violate. To serve files out of a large archive, the
+    /// archive can be mapped and scanned once, keeping the
+    /// `Entry::raw_file_position` and `Entry::size` of each file for which
+    /// `Entry::data_slice` returns `Some`, after which its contents are
+    /// `&data[pos..pos + size]` with no further reads. This doesn't hold for
+    /// the other entries, such as GNU sparse files and entries read through
+    /// a decompressor, whose stored bytes aren't their contents.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::collections::HashMap;
+    /// use std::fs;
+    /// use tar::Archive;
+    ///
+    /// // A memory mapped file works the same way as this vector
+    /// let data = fs::read("foo.tar").unwrap();
+    /// let mut files = HashMap::new();
+    /// for entry in Archive::from_slice(&data).entries().unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     if let Some(contents) = entry.data_slice() {
+    ///         files.insert(entry.path().unwrap().into_owned(), contents);
+    ///     }
+    /// }
+    /// ```
+    pub fn from_slice(data: &'s [u8]) -> Archive<&'s [u8]> {
+        let mut ar = Archive::new(data);
+        ar.inner.slice = Some(BorrowedSlice {
+            ptr: data.as_ptr(),
+            len: data.len(),
+        });
+        ar
     }
 }
 
 impl<'a> Archive<dyn Read + 'a> {
-    fn _entries(&mut self) -> io::Result<EntriesFields> {
+    fn _entries<'b>(
+        &'b self,
+        seekable_archive: Option<&'b Archive<dyn SeekRead + 'b>>,
+    ) -> io::Result<EntriesFields<'b>> {
         if self.inner.pos.get() != 0 {
             return Err(other(
                 "cannot call entries unless archive is at \
-                 position 0",
+                 position 0: the archive has already been partially \
+                 read and streaming iteration cannot restart, rewind \
+                 the underlying reader and create a new `Archive` to \
+                 iterate over the entries again",
             ));
         }
         Ok(EntriesFields {
             archive: self,
+            seekable_archive,
             done: false,
             next: 0,
+            padding: 0,
+            end_marker: false,
+            entries: 0,
             raw: false,
+            pax_global: None,
+            last_path: Vec::new(),
         })
     }
 
+    fn _unpack_to_map(&mut self, max_size: Option<u64>) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+        let mut files = BTreeMap::new();
+        let mut total: u64 = 0;
+        for entry in self._entries(None)? {
+            let file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            if !file.file_type().is_file() {
+                continue;
+            }
+            let path = match file.relative_unpack_path()? {
+                Some(ref path) if path.as_os_str().is_empty() => continue,
+                Some(path) => path,
+                None => continue,
+            };
+            let before = total;
+            total = total.saturating_add(file.size());
+            if let Some(max_size) = max_size {
+                if total > max_size {
+                    return Err(other(&format!(
+                        "files in the archive are larger than the limit of {} bytes, \
+                         reached at `{}`",
+                        max_size,
+                        path.display()
+                    )));
+                }
+            }
+            let mut fields = EntryFields::from(file);
+            fields.total_size_left = max_size.map(|max| max - before);
+            let contents = fields
+                .contents()
+                .map_err(|e| TarError::new(&format!("failed to read `{}`", path.display()), e))?;
+            // Decompressed contents may differ in size from the header
+            total = before.saturating_add(contents.len() as u64);
+            files.insert(path, contents);
+        }
+        Ok(files)
+    }
+
     fn _unpack(&mut self, dst: &Path) -> io::Result<()> {
         if dst.symlink_metadata().is_err() {
             fs::create_dir_all(&dst)
@@ -172,25 +1541,228 @@ impl<'a> Archive<dyn Read + 'a> {
         // NotFound exception.
         let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
 
-        for entry in self._entries()? {
-            let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
-            file.unpack_in(dst)?;
+        let marker = self.inner.resume_marker.clone();
+        let mut resume_from = self.inner.resume_from;
+        if let Some(ref marker) = marker {
+            resume_from = cmp::max(resume_from, read_resume_marker(marker)?);
+        }
+
+        // The filter and hasher are called while the entries borrow the
+        // archive
+        let mut filter = self.inner.unpack_filter.take();
+        let mut hasher = self.inner.unpack_hasher.take();
+        let res = self.unpack_entries(
+            dst,
+            resume_from,
+            marker.as_deref(),
+            &mut filter,
+            &mut hasher,
+        );
+        self.inner.unpack_filter = filter;
+        self.inner.unpack_hasher = hasher;
+        res?;
+        if let Some(ref marker) = marker {
+            match fs::remove_file(marker) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn unpack_entries(
+        &self,
+        dst: &Path,
+        resume_from: usize,
+        marker: Option<&Path>,
+        filter: &mut Option<Box<UnpackFilter>>,
+        hasher: &mut Option<UnpackHasher>,
+    ) -> io::Result<()> {
+        // Directories are written to while unpacking their contents, so their
+        // modification times are only set at the end
+        let mut dirs = Vec::new();
+        let mut total: u64 = 0;
+        let root = if self.inner.secure_extraction {
+            open_root(dst)?
+        } else {
+            None
+        };
+        for (i, entry) in self._entries(None)?.enumerate() {
+            let file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            if i < resume_from {
+                continue;
+            }
+            let action = match (filter.as_mut(), file.sanitized_relative_path()) {
+                (Some(filter), Some(path)) => filter(&path, &file),
+                _ => UnpackAction::Extract,
+            };
+            let mut fields = EntryFields::from(file);
+            let before = total;
+            if !matches!(action, UnpackAction::Skip) {
+                total = total.saturating_add(fields.size);
+                match self.inner.max_total_size {
+                    Some(max) if total > max => {
+                        return Err(other(&format!(
+                            "entries in the archive are larger than the limit of {} bytes, \
+                             reached at `{}`",
+                            max,
+                            fields.path_lossy()
+                        )));
+                    }
+                    _ => {}
+                }
+                if let Some(ref mut hasher) = *hasher {
+                    fields.hasher = Some((hasher.new_hasher)());
+                }
+                fields.total_size_left = self.inner.max_total_size.map(|max| max - before);
+            }
+            let mut mtime = None;
+            if fields.preserve_mtime && fields.header.entry_type().is_dir() {
+                mtime = fields.mtime_nanos().ok();
+                fields.preserve_mtime = false;
+            }
+            let unpacked = match action {
+                UnpackAction::Skip => None,
+                UnpackAction::RenameTo(path) => {
+                    fields.long_pathname = Some(path2bytes(&path)?.into_owned());
+                    fields.unpack_in_root(dst, root.as_ref())?
+                }
+                _ => fields.unpack_in_root(dst, root.as_ref())?,
+            };
+            if fields.decoded_len > 0 {
+                // Count what was actually written rather than the compressed
+                // size from the header
+                total = before.saturating_add(fields.decoded_len);
+            }
+            if let (Some(hasher), Some(path), Some(digest)) =
+                (hasher.as_mut(), unpacked.as_ref(), fields.digest.take())
+            {
+                (hasher.on_digest)(path, digest);
+            }
+            if let (Some(path), Some(mtime)) = (unpacked, mtime) {
+                dirs.push((path, mtime));
+            }
+            if let Some(ref marker) = marker {
+                fs::write(marker, format!("{}\n", i + 1)).map_err(|e| {
+                    TarError::new(
+                        &format!("failed to write resume marker `{}`", marker.display()),
+                        e,
+                    )
+                })?;
+            }
+        }
+        for (path, (secs, nanos)) in dirs.into_iter().rev() {
+            let mtime = FileTime::from_unix_time(secs as i64, nanos);
+            // The directory is looked up beneath the root again, as anything
+            // along its path could have been replaced since it was unpacked
+            let resolved = match (root.as_ref(), path.strip_prefix(dst)) {
+                (Some(root), Ok(rel)) if rel != Path::new("") => Some(root.resolve(rel)),
+                _ => None,
+            };
+            // Don't follow a symlink which replaced the directory since
+            resolved
+                .transpose()
+                .and_then(|resolved| {
+                    let target = resolved.as_ref().map(|r| r.path());
+                    filetime::set_symlink_file_times(
+                        target.as_deref().unwrap_or(&path),
+                        mtime,
+                        mtime,
+                    )
+                })
+                .map_err(|e| {
+                    TarError::new(&format!("failed to set mtime for `{}`", path.display()), e)
+                })?;
+        }
+        Ok(())
+    }
+
+    fn skip(&self, amt: u64) -> io::Result<()> {
+        let start = self.inner.pos.get();
+        if !self.try_skip(amt)? {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "unexpected EOF during skip, only {} of {} bytes were left",
+                    self.inner.pos.get() - start,
+                    amt
+                ),
+            ));
         }
         Ok(())
     }
 
-    fn skip(&self, mut amt: u64) -> io::Result<()> {
+    /// Skips `amt` bytes, returning `false` if EOF was hit first.
+    fn try_skip(&self, mut amt: u64) -> io::Result<bool> {
+        if let Some(rest) = self.inner.rest() {
+            let n = cmp::min(amt, rest.len() as u64);
+            self.inner.pos.set(self.inner.pos.get() + n);
+            return Ok(n == amt);
+        }
         let mut buf = [0u8; 4096 * 8];
         while amt > 0 {
             let n = cmp::min(amt, buf.len() as u64);
-            let n = (&self.inner).read(&mut buf[..n as usize])?;
+            let n = match (&self.inner).read(&mut buf[..n as usize]) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
             if n == 0 {
-                return Err(other("unexpected EOF during skip"));
+                return Ok(false);
             }
             amt -= n as u64;
         }
+        Ok(true)
+    }
+}
+
+impl<'a> SeekableArchive<'a> {
+    /// Seeks to `pos` relative to the start of the archive.
+    pub fn seek_to(&self, pos: u64) -> io::Result<()> {
+        self.seekable.seek_to(pos)
+    }
+
+    /// Returns a reader for the archive, reading from its current position.
+    pub fn reader(&self) -> &'a ArchiveInner<dyn Read + 'a> {
+        &self.archive.inner
+    }
+}
+
+impl<'a> Archive<dyn SeekRead + 'a> {
+    fn seek_forward(&self, amt: u64) -> io::Result<()> {
+        if amt > i64::MAX as u64 {
+            return Err(other("seek offset too large"));
+        }
+        self.inner
+            .obj
+            .borrow_mut()
+            .seek(SeekFrom::Current(amt as i64))?;
+        self.inner.pos.set(self.inner.pos.get() + amt);
+        Ok(())
+    }
+
+    /// Seeks to `pos` relative to the start of the archive.
+    fn seek_to(&self, pos: u64) -> io::Result<()> {
+        let cur = self.inner.pos.get();
+        if pos > i64::MAX as u64 || cur > i64::MAX as u64 {
+            return Err(other("seek offset too large"));
+        }
+        self.inner
+            .obj
+            .borrow_mut()
+            .seek(SeekFrom::Current(pos as i64 - cur as i64))?;
+        self.inner.pos.set(pos);
         Ok(())
     }
+
+    fn _bytes_remaining(&self) -> io::Result<u64> {
+        let mut obj = self.inner.obj.borrow_mut();
+        let pos = obj.stream_position()?;
+        let end = obj.seek(SeekFrom::End(0))?;
+        obj.seek(SeekFrom::Start(pos))?;
+        Ok(end.saturating_sub(pos))
+    }
 }
 
 impl<'a, R: Read> Entries<'a, R> {
@@ -219,7 +1791,114 @@ impl<'a, R: Read> Iterator for Entries<'a, R> {
     }
 }
 
+impl<'a, R: Read> List<'a, R> {
+    /// Returns the number of bytes between the current position of the
+    /// underlying reader and its end, see `Archive::bytes_remaining`.
+    pub fn bytes_remaining(&self) -> io::Result<u64> {
+        self.fields.seekable_archive.unwrap()._bytes_remaining()
+    }
+}
+
+impl<'a, R: Read> Iterator for List<'a, R> {
+    type Item = io::Result<ListEntry>;
+
+    fn next(&mut self) -> Option<io::Result<ListEntry>> {
+        self.fields.next().map(|result| {
+            result.map(|entry| ListEntry {
+                header: entry.header().clone(),
+                size: entry.size(),
+                path: entry.path_bytes().into_owned(),
+                link_name: entry.link_name_bytes().map(|l| l.into_owned()),
+            })
+        })
+    }
+}
+
+impl ListEntry {
+    /// Returns the path name for this entry.
+    ///
+    /// This takes into account long path names described in separate
+    /// entries, see `Entry::path`.
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.path))
+    }
+
+    /// Returns the raw bytes listed for this entry's path name.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the link name for this entry, if any is found.
+    ///
+    /// This takes into account long link names described in separate
+    /// entries, see `Entry::link_name`.
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+        match self.link_name {
+            Some(ref bytes) => bytes2path(Cow::Borrowed(bytes)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the raw bytes listed for this entry's link name, if any.
+    pub fn link_name_bytes(&self) -> Option<&[u8]> {
+        self.link_name.as_ref().map(|l| &l[..])
+    }
+
+    /// Returns access to the header of this entry in the archive.
+    ///
+    /// The type, mode and modification time of the entry can be found here.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the size of this entry, see `Entry::size`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl EntryIndex {
+    /// Returns the path name of the entry, see `Entry::path`.
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.path))
+    }
+
+    /// Returns the raw bytes listed for the entry's path name.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the offset of the entry's header in the archive, see
+    /// `Entry::raw_header_position`.
+    pub fn header_offset(&self) -> u64 {
+        self.header_offset
+    }
+
+    /// Returns the offset of the entry's contents in the archive, see
+    /// `Entry::raw_file_position`.
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
+
+    /// Returns the size of the entry, see `Entry::size`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
 impl<'a> EntriesFields<'a> {
+    fn verify_end_marker(&mut self) -> io::Result<()> {
+        if !self.end_marker {
+            return Err(other("archive is missing its end-of-archive marker"));
+        }
+        // Only the first of the two zeroed blocks has been read so far
+        let mut block = [0; 512];
+        if !try_read_all(&mut &self.archive.inner, &mut block)? || block.iter().any(|b| *b != 0) {
+            return Err(other("archive has an incomplete end-of-archive marker"));
+        }
+        Ok(())
+    }
+
     fn next_entry_raw(
         &mut self,
         pax_size: Option<u64>,
@@ -227,12 +1906,45 @@ impl<'a> EntriesFields<'a> {
         let mut header = Header::new_old();
         let mut header_pos = self.next;
         loop {
-            // Seek to the start of the next header in the archive
+            // Seek to the start of the next header in the archive. Some
+            // writers don't pad the data of the final entry, so running into
+            // EOF while skipping the padding is treated as the end of the
+            // archive.
             let delta = self.next - self.archive.inner.pos.get();
-            self.archive.skip(delta)?;
+            let padding = cmp::min(self.padding, delta);
+            match self.seekable_archive {
+                Some(archive) => archive.seek_forward(delta)?,
+                None if self.archive.inner.allow_truncated => {
+                    if !self.archive.try_skip(delta)? {
+                        return Ok(None);
+                    }
+                }
+                None => {
+                    self.archive.skip(delta - padding).map_err(|e| {
+                        TarError::new(
+                            &format!(
+                                "archive ended in the contents of `{}`",
+                                String::from_utf8_lossy(&self.last_path)
+                            ),
+                            e,
+                        )
+                    })?;
+                    if !self.archive.try_skip(padding)? {
+                        return Ok(None);
+                    }
+                }
+            }
+            self.padding = 0;
 
             // EOF is an indicator that we are at the end of the archive.
-            if !try_read_all(&mut &self.archive.inner, header.as_mut_bytes())? {
+            let read =
+                try_read_all(&mut &self.archive.inner, header.as_mut_bytes()).map_err(|e| {
+                    TarError::new(
+                        &format!("failed to read the header at offset {}", self.next),
+                        e,
+                    )
+                })?;
+            if !read {
                 return Ok(None);
             }
 
@@ -245,21 +1957,50 @@ impl<'a> EntriesFields<'a> {
             }
 
             if !self.archive.inner.ignore_zeros {
+                self.end_marker = true;
                 return Ok(None);
             }
             self.next += 512;
             header_pos = self.next;
         }
 
+        if self.archive.inner.skip_leading_garbage
+            && header_pos == 0
+            && !checksum_matches(&header).unwrap_or(false)
+        {
+            let skipped = self.skip_leading_garbage(&mut header)?;
+            header_pos += skipped;
+            self.next += skipped;
+        }
+
         // Make sure the checksum is ok
-        let sum = header.as_bytes()[..148]
-            .iter()
-            .chain(&header.as_bytes()[156..])
-            .fold(0, |a, b| a + (*b as u32))
-            + 8 * 32;
-        let cksum = header.cksum()?;
-        if sum != cksum {
-            return Err(other("archive header checksum mismatch"));
+        let mut bad_checksum = false;
+        match self.archive.inner.checksum_policy {
+            ChecksumPolicy::Strict | ChecksumPolicy::__Nonexhaustive => {
+                if !checksum_matches(&header)? {
+                    return Err(other("archive header checksum mismatch"));
+                }
+            }
+            ChecksumPolicy::Warn | ChecksumPolicy::Ignore => {
+                bad_checksum = !checksum_matches(&header).unwrap_or(false);
+            }
+        }
+        if bad_checksum && self.archive.inner.checksum_policy == ChecksumPolicy::Warn {
+            if let Some(ref handler) = self.archive.inner.checksum_mismatch_handler {
+                (handler.borrow_mut())(header_pos, &header);
+            }
+        }
+        if let EntryType::__Nonexhaustive(_) = header.entry_type() {
+            if let Some(ref handler) = self.archive.inner.unknown_type_handler {
+                (handler.borrow_mut())(header_pos, &header);
+            }
+        }
+        let mut raw_header = None;
+        if self.archive.inner.lenient_numeric_fields {
+            let original = *header.as_bytes();
+            if header.repair_numeric_fields() {
+                raw_header = Some(Box::new(original));
+            }
         }
 
         let file_pos = self.next;
@@ -274,20 +2015,55 @@ impl<'a> EntriesFields<'a> {
             header_pos: header_pos,
             file_pos: file_pos,
             data: vec![EntryIo::Data((&self.archive.inner).take(size))],
+            seekable_archive: self.seekable_archive.map(|seekable| SeekableArchive {
+                archive: self.archive,
+                seekable,
+            }),
             header: header,
+            raw_header,
+            bad_checksum,
             long_pathname: None,
             long_linkname: None,
             pax_extensions: None,
             unpack_xattrs: self.archive.inner.unpack_xattrs,
             preserve_permissions: self.archive.inner.preserve_permissions,
             preserve_mtime: self.archive.inner.preserve_mtime,
+            preserve_ownerships: self.archive.inner.preserve_ownerships,
+            numeric_owner: self.archive.inner.numeric_owner,
             overwrite: self.archive.inner.overwrite,
+            skip_existing: self.archive.inner.skip_existing,
+            unknown_type_policy: self.archive.inner.unknown_type_policy,
+            allowed_entry_types: self.archive.inner.allowed_entry_types,
+            skip_disallowed_types: self.archive.inner.skip_disallowed_types,
+            hardlink_fallback: self.archive.inner.hardlink_fallback,
+            unpack_preallocate: self.archive.inner.unpack_preallocate,
+            unpack_special_files: self.archive.inner.unpack_special_files,
+            secure_extraction: self.archive.inner.secure_extraction,
+            default_file_mode: self.archive.inner.default_file_mode,
+            default_dir_mode: self.archive.inner.default_dir_mode,
+            mask: self.archive.inner.mask,
+            windows_name_policy: self.archive.inner.windows_name_policy,
+            path_encoding: self.archive.inner.path_encoding,
+            parentdir_policy: self.archive.inner.parentdir_policy,
+            max_entry_size: self.archive.inner.max_entry_size,
+            total_size_left: None,
+            decoded_len: 0,
+            allow_truncated: self.archive.inner.allow_truncated,
+            truncated: Rc::new(Cell::new(false)),
+            total_len: self.archive.inner.total_len,
+            contents: self.archive.inner.contents(file_pos, size),
+            sparse_map: None,
+            hasher: None,
+            digest: None,
         };
 
+        self.last_path = ret.header.path_bytes().into_owned();
+
         // Store where the next entry is, rounding up by 512 bytes (the size of
         // a header);
-        let size = (size + 511) & !(512 - 1);
-        self.next += size;
+        let padded_size = (size + 511) & !(512 - 1);
+        self.next += padded_size;
+        self.padding = padded_size - size;
 
         Ok(Some(ret.into_entry()))
     }
@@ -301,12 +2077,13 @@ impl<'a> EntriesFields<'a> {
         let mut gnu_longlink = None;
         let mut pax_extensions = None;
         let mut pax_size = None;
-        let mut processed = 0;
         loop {
-            processed += 1;
             let entry = match self.next_entry_raw(pax_size)? {
                 Some(entry) => entry,
-                None if processed > 1 => {
+                None if gnu_longname.is_some()
+                    || gnu_longlink.is_some()
+                    || pax_extensions.is_some() =>
+                {
                     return Err(other(
                         "members found describing a future member \
                          but no future member found",
@@ -349,20 +2126,92 @@ impl<'a> EntriesFields<'a> {
                 }
                 pax_extensions = Some(EntryFields::from(entry).read_all()?);
                 if let Some(pax_extensions_ref) = &pax_extensions {
-                    pax_size = pax_extensions_size(pax_extensions_ref);
+                    pax_size = pax_extensions_size(pax_extensions_ref)?;
                 }
                 continue;
             }
 
+            // Global records apply to every entry which follows until they're
+            // overridden, so later records take precedence when looking up a
+            // key.
+            if is_recognized_header && entry.header().entry_type().is_pax_global_extensions() {
+                let records = EntryFields::from(entry).read_all()?;
+                self.pax_global
+                    .get_or_insert_with(Vec::new)
+                    .extend_from_slice(&records);
+                continue;
+            }
+
+            if let Some(ref global) = self.pax_global {
+                let mut records = global.clone();
+                records.extend_from_slice(pax_extensions.as_deref().unwrap_or(&[]));
+                pax_extensions = Some(records);
+            }
+
             let mut fields = EntryFields::from(entry);
             fields.long_pathname = gnu_longname;
             fields.long_linkname = gnu_longlink;
             fields.pax_extensions = pax_extensions;
+            // Name the entry in errors by its full path from now on
+            self.last_path = fields.path_bytes().into_owned();
             self.parse_sparse_header(&mut fields)?;
+            self.apply_decompressor(&mut fields)?;
             return Ok(Some(fields.into_entry()));
         }
     }
 
+    /// Slides `header` forward through the archive a byte at a time until it
+    /// holds a valid header, returning the number of bytes skipped.
+    fn skip_leading_garbage(&mut self, header: &mut Header) -> io::Result<u64> {
+        let mut skipped = 0;
+        loop {
+            let bytes = header.as_mut_bytes();
+            let mut byte = [0];
+            if !try_read_all(&mut &self.archive.inner, &mut byte)? {
+                return Err(other(
+                    "no valid header found while skipping leading garbage",
+                ));
+            }
+            bytes.copy_within(1.., 0);
+            bytes[511] = byte[0];
+            skipped += 1;
+
+            if !header.as_bytes().iter().all(|i| *i == 0)
+                && checksum_matches(header).unwrap_or(false)
+            {
+                return Ok(skipped);
+            }
+        }
+    }
+
+    fn apply_decompressor(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
+        let decompressors = &self.archive.inner.decompressors;
+        if decompressors.is_empty() {
+            return Ok(());
+        }
+        let pax = match entry.pax_extensions {
+            Some(ref pax) => pax,
+            None => return Ok(()),
+        };
+        let mut decompressor = None;
+        for extension in pax_extensions(pax) {
+            let extension = extension?;
+            if extension.key_bytes() == COMPRESSION_RECORD.as_bytes() {
+                decompressor = extension
+                    .value()
+                    .ok()
+                    .and_then(|algorithm| decompressors.get(algorithm));
+            }
+        }
+        if let Some(decompressor) = decompressor {
+            let (raw, remaining) = EntryData::take(entry);
+            let decoded = decompressor(Box::new(raw));
+            entry.data.push(EntryIo::Decoded(decoded, remaining));
+            entry.contents = None;
+        }
+        Ok(())
+    }
+
     fn parse_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
         if !entry.header.entry_type().is_gnu_sparse() {
             return Ok(());
@@ -392,11 +2241,14 @@ impl<'a> EntriesFields<'a> {
         // well as the amount of data read equals the size of the entry
         // (`Header::entry_size`).
         entry.data.truncate(0);
+        entry.contents = None;
 
         let mut cur = 0;
         let mut remaining = entry.size;
+        let mut map = Vec::new();
         {
             let data = &mut entry.data;
+            let map = &mut map;
             let reader = &self.archive.inner;
             let size = entry.size;
             let mut add_block = |block: &GnuSparseHeader| -> io::Result<_> {
@@ -430,6 +2282,9 @@ impl<'a> EntriesFields<'a> {
                     )
                 })?;
                 data.push(EntryIo::Data(reader.take(len)));
+                if len > 0 {
+                    map.push((off, len));
+                }
                 Ok(())
             };
             for block in gnu.sparse.iter() {
@@ -444,6 +2299,7 @@ impl<'a> EntriesFields<'a> {
                     }
 
                     self.next += 512;
+                    entry.file_pos += 512;
                     for block in ext.sparse.iter() {
                         add_block(block)?;
                     }
@@ -457,6 +2313,7 @@ impl<'a> EntriesFields<'a> {
             ));
         }
         entry.size = cur;
+        entry.sparse_map = Some(map);
         if remaining > 0 {
             return Err(other(
                 "mismatch in sparse file chunks and \
@@ -475,7 +2332,19 @@ impl<'a> Iterator for EntriesFields<'a> {
             None
         } else {
             match self.next_entry() {
-                Ok(Some(e)) => Some(Ok(e)),
+                Ok(Some(e)) => {
+                    self.entries += 1;
+                    match self.archive.inner.max_entries {
+                        Some(max) if self.entries > max => {
+                            self.done = true;
+                            Some(Err(other(&format!(
+                                "archive contains more than {} entries",
+                                max
+                            ))))
+                        }
+                        _ => Some(Ok(e)),
+                    }
+                }
                 Ok(None) => {
                     self.done = true;
                     None
@@ -489,8 +2358,40 @@ impl<'a> Iterator for EntriesFields<'a> {
     }
 }
 
+impl<R: ?Sized + Read> ArchiveInner<R> {
+    /// Borrows `len` bytes at `pos` of the slice the archive was created
+    /// from, if any.
+    fn contents(&self, pos: u64, len: u64) -> Option<&[u8]> {
+        use std::convert::TryFrom;
+
+        let slice = self.slice?;
+        // Safety: the slice is borrowed by the archive for longer than `self`
+        let data = unsafe { std::slice::from_raw_parts(slice.ptr, slice.len) };
+        let start = usize::try_from(pos).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        data.get(start..end)
+    }
+
+    /// Borrows the rest of the slice the archive was created from, if any,
+    /// starting at the current position.
+    fn rest(&self) -> Option<&[u8]> {
+        let slice = self.slice?;
+        let pos = cmp::min(self.pos.get(), slice.len as u64) as usize;
+        // Safety: the slice is borrowed by the archive for longer than `self`
+        let data = unsafe { std::slice::from_raw_parts(slice.ptr, slice.len) };
+        Some(&data[pos..])
+    }
+}
+
 impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        // Archives created from a slice read from it directly, leaving the
+        // reader itself untouched.
+        if let Some(mut rest) = self.rest() {
+            let n = rest.read(into)?;
+            self.pos.set(self.pos.get() + n as u64);
+            return Ok(n);
+        }
         self.obj.borrow_mut().read(into).map(|i| {
             self.pos.set(self.pos.get() + i as u64);
             i
@@ -498,20 +2399,66 @@ impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
     }
 }
 
+/// Checks the checksum of a header.
+///
+/// The checksum is the sum of all bytes of the header with the checksum
+/// field itself taken to be spaces. Some old implementations summed the
+/// bytes as signed values, so either variant is accepted.
+fn checksum_matches(header: &Header) -> io::Result<bool> {
+    let cksum = header.cksum()?;
+    let bytes = header.as_bytes()[..148]
+        .iter()
+        .chain(&header.as_bytes()[156..]);
+    let unsigned = bytes.clone().fold(0, |a, b| a + (*b as u32)) + 8 * 32;
+    if unsigned == cksum {
+        return Ok(true);
+    }
+    let signed = bytes.fold(0, |a, b| a + (*b as i8 as i32)) + 8 * 32;
+    Ok(signed == cksum as i32)
+}
+
+/// Reads the number of entries already unpacked from a resume marker, which
+/// is 0 if the marker doesn't exist.
+fn read_resume_marker(path: &Path) -> io::Result<usize> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    contents.trim().parse().map_err(|_| {
+        other(&format!(
+            "resume marker `{}` doesn't contain an entry count",
+            path.display()
+        ))
+    })
+}
+
 /// Try to fill the buffer from the reader.
 ///
 /// If the reader reaches its end before filling the buffer at all, returns `false`.
 /// Otherwise returns `true`.
+///
+/// Reads which are interrupted are retried, but any other error is returned
+/// immediately so that readers which time out aren't retried forever.
 fn try_read_all<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
     let mut read = 0;
     while read < buf.len() {
-        match r.read(&mut buf[read..])? {
+        let n = match r.read(&mut buf[read..]) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        match n {
             0 => {
                 if read == 0 {
                     return Ok(false);
                 }
 
-                return Err(other("failed to read entire block"));
+                return Err(other(&format!(
+                    "failed to read entire block, only {} of {} bytes were left",
+                    read,
+                    buf.len()
+                )));
             }
             n => read += n,
         }
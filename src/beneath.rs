@@ -0,0 +1,244 @@
+//! Resolution of paths beneath an extraction directory which is enforced by
+//! the kernel, used by `Entry::unpack_in` when secure extraction is enabled.
+//!
+//! On Linux this is implemented with `openat2(2)` and `RESOLVE_BENEATH`, which
+//! fails any lookup that would leave the directory, including through
+//! symlinks or `..` components. The directories found this way are then
+//! referred to through `/proc/self/fd`, so swapping out a directory after it
+//! has been resolved can't redirect writes elsewhere. Only the last name of
+//! a path isn't resolved this way, so directories unpacked there are opened
+//! without following symlinks before their metadata is set.
+//!
+//! `Archive::unpack` opens the `Root` once and resolves all of its entries
+//! beneath it. Everywhere else, and on kernels without `openat2`,
+//! `Root::open` returns `None` and the userspace checks of `unpack_in` are
+//! used instead.
+
+pub use self::imp::{Resolved, Root};
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::{CString, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::mem;
+    use std::os::unix::prelude::*;
+    use std::path::{Component, Path, PathBuf};
+
+    use crate::other;
+
+    /// How many times a lookup which raced with a rename is retried.
+    const MAX_RETRIES: u32 = 32;
+
+    /// A directory which paths are resolved beneath.
+    pub struct Root {
+        dir: File,
+    }
+
+    /// A name inside of a directory which was resolved beneath a `Root`.
+    pub struct Resolved {
+        dir: File,
+        name: OsString,
+    }
+
+    impl Root {
+        /// Opens `dst` as the root, returning `None` if the kernel doesn't
+        /// support resolving paths beneath it.
+        pub fn open(dst: &Path) -> io::Result<Option<Root>> {
+            let path = CString::new(dst.as_os_str().as_bytes())?;
+            let fd = unsafe {
+                libc::open(
+                    path.as_ptr(),
+                    libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let root = Root {
+                dir: unsafe { File::from_raw_fd(fd) },
+            };
+
+            // Kernels older than 5.6 don't have `openat2`, and seccomp
+            // filters commonly reject syscalls they don't know about. The
+            // directories resolved are also only reachable through `/proc`.
+            match root.open_beneath(Path::new("."), libc::O_DIRECTORY) {
+                Ok(_) => {}
+                Err(ref e)
+                    if e.raw_os_error() == Some(libc::ENOSYS)
+                        || e.raw_os_error() == Some(libc::EPERM) =>
+                {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            }
+            if !Path::new("/proc/self/fd").is_dir() {
+                return Ok(None);
+            }
+            Ok(Some(root))
+        }
+
+        /// Checks that `path`, including any symlink it ends in, refers to
+        /// something beneath the root.
+        pub fn check(&self, path: &Path) -> io::Result<()> {
+            self.open_beneath(path, 0).map(drop)
+        }
+
+        /// Resolves the parent directory of `path` beneath the root.
+        pub fn resolve(&self, path: &Path) -> io::Result<Resolved> {
+            let name = match path.file_name() {
+                Some(name) => name.to_owned(),
+                None => return Err(other(&format!("`{}` has no file name", path.display()))),
+            };
+            let parent = match path.parent() {
+                Some(parent) if parent != Path::new("") => parent,
+                _ => Path::new("."),
+            };
+            let dir = self.open_beneath(parent, libc::O_DIRECTORY)?;
+            Ok(Resolved { dir, name })
+        }
+
+        /// Resolves the parent directory of `path` beneath the root like
+        /// `resolve`, creating any missing directories along the way.
+        pub fn create_parents(&self, path: &Path) -> io::Result<Resolved> {
+            let mut cur = PathBuf::from(".");
+            for part in path.parent().into_iter().flat_map(|p| p.components()) {
+                let part = match part {
+                    Component::Normal(part) => part,
+                    Component::CurDir => continue,
+                    _ => return Err(other(&format!("invalid path `{}`", path.display()))),
+                };
+                let dir = self.open_beneath(&cur, libc::O_DIRECTORY)?;
+                let name = CString::new(part.as_bytes())?;
+                if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) } < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(err);
+                    }
+                }
+                cur.push(part);
+            }
+            self.resolve(path)
+        }
+
+        fn open_beneath(&self, path: &Path, flags: libc::c_int) -> io::Result<File> {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let mut how: libc::open_how = unsafe { mem::zeroed() };
+            how.flags = (libc::O_PATH | libc::O_CLOEXEC | flags) as u64;
+            how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+            let mut retries = 0;
+            loop {
+                let fd = unsafe {
+                    libc::syscall(
+                        libc::SYS_openat2,
+                        self.dir.as_raw_fd(),
+                        c_path.as_ptr(),
+                        &how as *const libc::open_how,
+                        mem::size_of::<libc::open_how>(),
+                    )
+                };
+                if fd >= 0 {
+                    return Ok(unsafe { File::from_raw_fd(fd as RawFd) });
+                }
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // The kernel gives up on lookups containing `..` which
+                    // race with renames, leaving it to us to retry. Another
+                    // process renaming directories in a loop could make that
+                    // go on forever though.
+                    Some(libc::EAGAIN) if retries < MAX_RETRIES => {
+                        retries += 1;
+                        continue;
+                    }
+                    Some(libc::EXDEV) | Some(libc::ELOOP) => {
+                        return Err(other(&format!(
+                            "`{}` resolves outside of the destination directory",
+                            path.display()
+                        )));
+                    }
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+
+    impl Resolved {
+        /// Returns a path naming the resolved location.
+        ///
+        /// The directory is named through its file descriptor, so the path
+        /// stays inside of it even if the directory is moved or replaced.
+        pub fn path(&self) -> PathBuf {
+            Path::new("/proc/self/fd")
+                .join(self.dir.as_raw_fd().to_string())
+                .join(&self.name)
+        }
+
+        /// Opens the resolved name as a directory, failing rather than
+        /// following it if it's a symlink.
+        ///
+        /// Only the directory the name was resolved in is checked to be
+        /// beneath the root, so an existing symlink in its place could
+        /// point anywhere.
+        pub fn open_dir(&self) -> io::Result<File> {
+            let name = CString::new(self.name.as_bytes())?;
+            let fd = unsafe {
+                libc::openat(
+                    self.dir.as_raw_fd(),
+                    name.as_ptr(),
+                    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() == Some(libc::ELOOP) {
+                    return Err(other(&format!(
+                        "`{}` is a symlink rather than a directory",
+                        Path::new(&self.name).display()
+                    )));
+                }
+                return Err(err);
+            }
+            Ok(unsafe { File::from_raw_fd(fd) })
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::fs::File;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    pub enum Root {}
+
+    pub enum Resolved {}
+
+    impl Root {
+        pub fn open(_dst: &Path) -> io::Result<Option<Root>> {
+            Ok(None)
+        }
+
+        pub fn check(&self, _path: &Path) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn resolve(&self, _path: &Path) -> io::Result<Resolved> {
+            match *self {}
+        }
+
+        pub fn create_parents(&self, _path: &Path) -> io::Result<Resolved> {
+            match *self {}
+        }
+    }
+
+    impl Resolved {
+        pub fn path(&self) -> PathBuf {
+            match *self {}
+        }
+
+        pub fn open_dir(&self) -> io::Result<File> {
+            match *self {}
+        }
+    }
+}
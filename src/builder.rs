@@ -1,23 +1,101 @@
 use std::borrow::Cow;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
 use std::fs;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::UNIX_EPOCH;
 
+use crate::entry::EntryFields;
 use crate::header::{bytes2path, path2bytes, HeaderMode};
-use crate::{other, EntryType, Header};
+use crate::pax::{pax_extensions, pax_extensions_size};
+use crate::{other, Archive, Entry, EntryType, Header};
 
 /// A structure for building archives
 ///
 /// This structure has methods for building up an archive from scratch into any
 /// arbitrary writer.
+///
+/// Entries are written in exactly the order the `append*` methods are called,
+/// and `append_dir_all` adds the contents of each directory sorted by name, so
+/// the same calls on the same files always produce the same archive. To check
+/// that entries are appended in the order of an existing archive, for example
+/// when reproducing it, use `Builder::from_listing`.
+///
+/// The end of the archive, two blocks of zeros, is only written by `finish`
+/// and `into_inner`. A builder which is dropped without either having been
+/// called, for example when returning early after an error, or which is
+/// abandoned with `abort` leaves the archive without it, so an archive whose
+/// construction failed doesn't look complete. Finishing the archive when the
+/// builder is dropped can be turned on with `set_finish_on_drop`.
 pub struct Builder<W: Write> {
     mode: HeaderMode,
     follow: bool,
+    long_names: bool,
+    strict_size: bool,
+    preserve_typeflag: bool,
+    subsecond_mtimes: bool,
+    owner: Option<Owner>,
+    dedup: Option<ContentIndex>,
+    listing: Option<Listing>,
+    finish_on_drop: bool,
     finished: bool,
     obj: Option<W>,
 }
 
+/// A writer for the contents of an entry being added to an archive, created
+/// by `Builder::append_writer`.
+///
+/// The entry is completed with `finish`, which reports whether all of its
+/// contents were written. Dropping the writer completes it as well, filling
+/// in any contents which weren't written with zeros, unless it's dropped
+/// while panicking.
+pub struct EntryWriter<'a, W: Write> {
+    obj: &'a mut W,
+    path: Vec<u8>,
+    size: u64,
+    written: u64,
+    overflowed: bool,
+    finished: bool,
+}
+
+#[derive(Clone)]
+struct Owner {
+    uid: u64,
+    gid: u64,
+    username: String,
+    groupname: String,
+}
+
+/// Options for appending entries based on filesystem metadata.
+struct FsOptions<'a> {
+    mode: HeaderMode,
+    long_names: bool,
+    subsecond_mtimes: bool,
+    owner: Option<Owner>,
+    dedup: Option<&'a mut ContentIndex>,
+    listing: Option<&'a mut Listing>,
+}
+
+/// The paths expected to be appended to a builder, in order.
+struct Listing {
+    paths: Vec<PathBuf>,
+    next: usize,
+}
+
+/// The regular files appended so far, indexed by a hash of their contents.
+struct ContentIndex {
+    // Two independently keyed hashers give a 128-bit digest, which together
+    // with the file size makes accidental collisions vanishingly unlikely.
+    keys: (RandomState, RandomState),
+    seen: HashMap<(u64, u64, u64), PathBuf>,
+}
+
 impl<W: Write> Builder<W> {
     /// Create a new archive builder with the underlying object as the
     /// destination of all data written. The builder will use
@@ -26,11 +104,71 @@ impl<W: Write> Builder<W> {
         Builder {
             mode: HeaderMode::Complete,
             follow: true,
+            long_names: true,
+            strict_size: false,
+            preserve_typeflag: true,
+            subsecond_mtimes: false,
+            owner: None,
+            dedup: None,
+            listing: None,
+            finish_on_drop: false,
             finished: false,
             obj: Some(obj),
         }
     }
 
+    /// Create a new archive builder which checks that entries are appended
+    /// in the order given by `order`.
+    ///
+    /// This is useful when rebuilding an existing archive byte for byte,
+    /// where accidentally appending entries in a different order would break
+    /// reproducibility. Every entry appended must have the next path of
+    /// `order`, otherwise the append method returns an error without writing
+    /// anything. Paths are compared after resolving `.` and `..` components,
+    /// and extension entries, like GNU long names or pax extended headers,
+    /// aren't counted. Finishing the archive before all of `order` has been
+    /// appended is an error as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use tar::{Builder, Header};
+    ///
+    /// let order = [PathBuf::from("a"), PathBuf::from("b")];
+    /// let mut ar = Builder::from_listing(Vec::new(), &order);
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_size(0);
+    /// assert!(ar.append_data(&mut header, "b", &[][..]).is_err());
+    /// ar.append_data(&mut header, "a", &[][..]).unwrap();
+    /// ar.append_data(&mut header, "b", &[][..]).unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn from_listing(obj: W, order: &[PathBuf]) -> Builder<W> {
+        let mut ar = Builder::new(obj);
+        ar.listing = Some(Listing {
+            paths: order.to_vec(),
+            next: 0,
+        });
+        ar
+    }
+
+    fn check_order(&self, path: &Path) -> io::Result<()> {
+        match self.listing {
+            Some(ref listing) => listing.check(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves on to the next path of the listing, once the entry checked with
+    /// `check_order` has been written.
+    fn advance_order(&mut self) {
+        if let Some(ref mut listing) = self.listing {
+            listing.next += 1;
+        }
+    }
+
     /// Changes the HeaderMode that will be used when reading fs Metadata for
     /// methods that implicitly read metadata for an input Path. Notably, this
     /// does _not_ apply to `append(Header)`.
@@ -40,11 +178,153 @@ impl<W: Write> Builder<W> {
 
     /// Follow symlinks, archiving the contents of the file they point to rather
     /// than adding a symlink to the archive. Defaults to true.
+    ///
+    /// When following symlinks, `append_dir_all` returns an error for a
+    /// symlink pointing to one of the directories containing it rather than
+    /// recursing forever.
     pub fn follow_symlinks(&mut self, follow: bool) {
         self.follow = follow;
     }
 
+    /// Use GNU long name and long link extension entries for paths which are
+    /// too long to fit in a header. Defaults to true.
+    ///
+    /// When disabled, appending an entry whose path or link name doesn't fit
+    /// in the header will return an error instead.
+    pub fn long_names(&mut self, long_names: bool) {
+        self.long_names = long_names;
+    }
+
+    /// Error out of `append` and `append_data` if the data stream contains
+    /// more bytes than the size declared in the header. Defaults to false.
+    ///
+    /// Regardless of this setting, exactly the declared number of bytes are
+    /// written for each entry, any extra bytes are otherwise ignored, and a
+    /// stream that ends early is always an error.
+    pub fn set_strict_size(&mut self, strict_size: bool) {
+        self.strict_size = strict_size;
+    }
+
+    /// Write the end of the archive when this builder is dropped without
+    /// `finish` or `into_inner` having been called. Defaults to false.
+    ///
+    /// Even when enabled, the end of the archive isn't written if the builder
+    /// is dropped while panicking or abandoned with `abort`.
+    pub fn set_finish_on_drop(&mut self, finish: bool) {
+        self.finish_on_drop = finish;
+    }
+
+    /// Keep the exact type flag byte of entries added with `append_entry`.
+    /// Defaults to true.
+    ///
+    /// Some type flags have several spellings, for example old archives mark
+    /// regular files with a nul byte rather than `'0'`. When this is disabled
+    /// such type flags are rewritten to their canonical form, otherwise they
+    /// are copied verbatim so an archive can be reproduced byte for byte.
+    pub fn set_preserve_typeflag(&mut self, preserve: bool) {
+        self.preserve_typeflag = preserve;
+    }
+
+    /// Record the modification times of entries added from the filesystem
+    /// with sub-second precision. Defaults to false.
+    ///
+    /// Headers only have room for whole seconds, so when this is enabled an
+    /// entry whose modification time has a fraction of a second is preceded
+    /// by a pax extended header with an `mtime` record such as
+    /// `1700000000.123456789`. This only applies with `HeaderMode::Complete`,
+    /// and the fraction is restored by `Entry::unpack` and reported by
+    /// `Entry::modified`.
+    pub fn set_subsecond_mtimes(&mut self, subsecond: bool) {
+        self.subsecond_mtimes = subsecond;
+    }
+
+    /// Overrides the ownership recorded for entries added from the filesystem.
+    ///
+    /// All subsequent calls to `append_path`, `append_path_with_name`,
+    /// `append_file`, `append_dir` and `append_dir_all` record the given user
+    /// and group instead of the owner of the source files, regardless of the
+    /// `HeaderMode`. This is useful for producing archives whose contents are
+    /// owned by a specific user, such as root, independently of who builds
+    /// them. Use `clear_owner` to go back to recording the actual owners.
+    ///
+    /// Headers passed to `append` and `append_data` are never modified.
+    pub fn set_owner(&mut self, uid: u64, gid: u64, username: &str, groupname: &str) {
+        self.owner = Some(Owner {
+            uid,
+            gid,
+            username: username.to_string(),
+            groupname: groupname.to_string(),
+        });
+    }
+
+    /// Stops overriding the ownership of entries added from the filesystem,
+    /// see `set_owner`.
+    pub fn clear_owner(&mut self) {
+        self.owner = None;
+    }
+
+    /// Store regular files whose contents are identical to a file appended
+    /// earlier as hard links to that file. Defaults to false.
+    ///
+    /// This applies to files added with `append_path`,
+    /// `append_path_with_name`, `append_file` and `append_dir_all`, and
+    /// catches duplicates which are separate files on disk, not just hard
+    /// links to the same inode. Headers passed to `append` and `append_data`
+    /// are never deduplicated, and neither are empty files.
+    ///
+    /// Note that every file has to be read twice when this is enabled, once
+    /// to hash its contents and again to write it to the archive, and that
+    /// the hashes of all files appended are kept in memory. The link entries
+    /// written refer to the path of the first copy inside the archive, which
+    /// must therefore be extracted too for the duplicates to be restored.
+    ///
+    /// When extracted, the duplicates are hard links sharing the first
+    /// copy's inode, so they also share its metadata: their own permissions,
+    /// modification time and owner aren't restored, only those of the first
+    /// copy. Only enable this if that doesn't matter, for example for
+    /// archives where all files have the same mode and owner.
+    ///
+    /// Disabling this forgets about the files appended so far.
+    pub fn set_content_dedup(&mut self, dedup: bool) {
+        if !dedup {
+            self.dedup = None;
+        } else if self.dedup.is_none() {
+            self.dedup = Some(ContentIndex {
+                keys: (RandomState::new(), RandomState::new()),
+                seen: HashMap::new(),
+            });
+        }
+    }
+
+    fn fs_parts(&mut self) -> io::Result<(&mut W, FsOptions<'_>)> {
+        self.writer()?;
+        let opts = FsOptions {
+            mode: self.mode,
+            long_names: self.long_names,
+            subsecond_mtimes: self.subsecond_mtimes,
+            owner: self.owner.clone(),
+            dedup: self.dedup.as_mut(),
+            listing: self.listing.as_mut(),
+        };
+        Ok((self.obj.as_mut().unwrap(), opts))
+    }
+
+    /// Returns the underlying writer for appending entries, which fails once
+    /// the archive has been finished.
+    fn writer(&mut self) -> io::Result<&mut W> {
+        if self.finished {
+            return Err(other(
+                "can't append to an archive which is already finished",
+            ));
+        }
+        Ok(self.get_mut())
+    }
+
     /// Gets shared reference to the underlying object.
+    ///
+    /// Every method which appends an entry writes all of it before returning,
+    /// so in between calls the object holds the archive up to the end of the
+    /// last entry, without the trailer until the archive is finished.
     pub fn get_ref(&self) -> &W {
         self.obj.as_ref().unwrap()
     }
@@ -55,6 +335,12 @@ impl<W: Write> Builder<W> {
     /// object. But, e.g. `get_mut().flush()` is claimed to be safe and
     /// useful in the situations when one needs to be ensured that
     /// tar entry was flushed to the disk.
+    ///
+    /// As appending methods borrow the builder mutably, this can't be called
+    /// while an entry is partially written. Anything written through the
+    /// returned reference ends up in the archive between two entries though,
+    /// so it must be whole 512-byte blocks forming valid entries to keep the
+    /// archive readable.
     pub fn get_mut(&mut self) -> &mut W {
         self.obj.as_mut().unwrap()
     }
@@ -63,7 +349,8 @@ impl<W: Write> Builder<W> {
     ///
     /// This function will finish writing the archive if the `finish` function
     /// hasn't yet been called, returning any I/O error which happens during
-    /// that operation.
+    /// that operation. The returned object therefore always ends with the
+    /// trailer. It isn't flushed though, which is up to the caller.
     pub fn into_inner(mut self) -> io::Result<W> {
         if !self.finished {
             self.finish()?;
@@ -71,13 +358,51 @@ impl<W: Write> Builder<W> {
         Ok(self.obj.take().unwrap())
     }
 
+    /// Abandons this archive, returning the underlying object without
+    /// writing the end of the archive.
+    ///
+    /// This is meant for giving up on an archive after an error, such as when
+    /// writing to a temporary file which is only renamed into place once the
+    /// archive is complete. The entries appended so far are left in the
+    /// object as they are, and it's up to the caller to discard them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new(File::create("foo.tar.tmp").unwrap());
+    /// match ar.append_dir_all("foo", "path/to/foo") {
+    ///     Ok(()) => {
+    ///         ar.into_inner().unwrap();
+    ///         fs::rename("foo.tar.tmp", "foo.tar").unwrap();
+    ///     }
+    ///     Err(e) => {
+    ///         drop(ar.abort());
+    ///         fs::remove_file("foo.tar.tmp").unwrap();
+    ///         eprintln!("failed to create archive: {}", e);
+    ///     }
+    /// }
+    /// ```
+    pub fn abort(mut self) -> W {
+        self.finished = true;
+        self.obj.take().unwrap()
+    }
+
     /// Adds a new entry to this archive.
     ///
     /// This function will append the header specified, followed by contents of
-    /// the stream specified by `data`. To produce a valid archive the `size`
-    /// field of `header` must be the same as the length of the stream that's
-    /// being written. Additionally the checksum for the header should have been
-    /// set via the `set_cksum` method.
+    /// the stream specified by `data`. Exactly as many bytes as declared by the
+    /// `size` field of `header` are copied from `data`; any further bytes are
+    /// left unread unless `set_strict_size` is enabled, in which case they are
+    /// an error.
+    ///
+    /// The checksum for the header should have been set via the `set_cksum`
+    /// method. If it doesn't match the rest of the header, for example because
+    /// a field was changed after `set_cksum` was called, it's recomputed for
+    /// the header which is written. Use `append_raw_header` to write a header
+    /// exactly as given instead.
     ///
     /// Note that this will not attempt to seek the archive to a valid position,
     /// so if the archive is in the middle of a read or some other similar
@@ -89,7 +414,9 @@ impl<W: Write> Builder<W> {
     /// # Errors
     ///
     /// This function will return an error for any intermittent I/O error which
-    /// occurs when either reading or writing.
+    /// occurs when either reading or writing, or if `data` is shorter than the
+    /// size declared in `header`. The rest of the contents are filled in with
+    /// zeros in that case, so the archive stays readable.
     ///
     /// # Examples
     ///
@@ -107,8 +434,135 @@ impl<W: Write> Builder<W> {
     /// ar.append(&header, data).unwrap();
     /// let data = ar.into_inner().unwrap();
     /// ```
-    pub fn append<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
-        append(self.get_mut(), header, &mut data)
+    pub fn append<R: Read>(&mut self, header: &Header, data: R) -> io::Result<()> {
+        let counted = self.check_header_order(header)?;
+        self.write_header_and_data(header, data)?;
+        if counted {
+            self.advance_order();
+        }
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive preceded by a pax extended header
+    /// with the given key/value records.
+    ///
+    /// This is for entries with metadata which doesn't fit in `header`, such
+    /// as paths over 100 bytes, sizes of 8 GiB or more, or modification times
+    /// with sub-second precision, as well as any other records readers should
+    /// see for this entry. The records are formatted as with
+    /// `append_pax_extensions` and override the corresponding fields of
+    /// `header` when the archive is read. Otherwise this is the same as
+    /// `append`, with a `path` record taking the place of the header's path
+    /// when checking the order of a listing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let path = "a/".repeat(60) + "foo";
+    /// let mut header = Header::new_ustar();
+    /// header.set_path("foo").unwrap();
+    /// header.set_size(4);
+    /// header.set_cksum();
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_pax(&header, &[("path", path.as_bytes())], &[1, 2, 3, 4][..])
+    ///     .unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_pax<R: Read>(
+        &mut self,
+        header: &Header,
+        extensions: &[(&str, &[u8])],
+        data: R,
+    ) -> io::Result<()> {
+        let counted = match extensions.iter().rev().find(|&&(key, _)| key == "path") {
+            Some(&(_, path)) => {
+                self.check_order(&bytes2path(Cow::Borrowed(path))?)?;
+                true
+            }
+            None => self.check_header_order(header)?,
+        };
+        self.append_pax_extensions(extensions.iter().cloned())?;
+        self.write_header_and_data(header, data)?;
+        if counted {
+            self.advance_order();
+        }
+        Ok(())
+    }
+
+    fn write_header_and_data<R: Read>(&mut self, header: &Header, data: R) -> io::Result<()> {
+        // Fields changed after the checksum was computed would otherwise
+        // leave a header behind which fails to validate when it's read.
+        if header.cksum_is_stale() {
+            let mut header = header.clone();
+            header.set_cksum();
+            return self.write_entry(&header, data);
+        }
+        self.write_entry(header, data)
+    }
+
+    /// Checks the order of the entry `header` belongs to, returning whether
+    /// it counts towards the listing, which extension entries don't.
+    fn check_header_order(&self, header: &Header) -> io::Result<bool> {
+        let entry_type = header.entry_type();
+        let is_extension = entry_type.is_gnu_longname()
+            || entry_type.is_gnu_longlink()
+            || entry_type.is_pax_local_extensions()
+            || entry_type.is_pax_global_extensions();
+        if !is_extension {
+            self.check_order(&header.path()?)?;
+        }
+        Ok(!is_extension)
+    }
+
+    /// Adds a new entry to this archive whose header is the 512-byte block
+    /// `header`, written out exactly as given.
+    ///
+    /// Unlike the other methods of this builder nothing about the header is
+    /// filled in or corrected, including its checksum, so this is meant for
+    /// copying entries verbatim from another archive with the bytes returned
+    /// by `Entry::header_raw_bytes`. The entry's contents are read from `data`
+    /// like with `append`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use tar::{Archive, Builder};
+    ///
+    /// # let mut src = Builder::new(Vec::new());
+    /// # let mut header = tar::Header::new_gnu();
+    /// # header.set_size(3);
+    /// # src.append_data(&mut header, "foo", &b"foo"[..]).unwrap();
+    /// # let original = src.into_inner().unwrap();
+    /// let mut ar = Archive::new(&original[..]);
+    /// let mut copy = Builder::new(Vec::new());
+    /// for entry in ar.entries().unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     let raw = *entry.header_raw_bytes();
+    ///     copy.append_raw_header(&raw, entry).unwrap();
+    /// }
+    /// assert_eq!(copy.into_inner().unwrap(), original);
+    /// ```
+    pub fn append_raw_header<R: Read>(&mut self, header: &[u8; 512], data: R) -> io::Result<()> {
+        let header = Header::from_byte_slice(header);
+        let counted = self.check_header_order(header)?;
+        self.write_entry(header, data)?;
+        if counted {
+            self.advance_order();
+        }
+        Ok(())
+    }
+
+    fn write_entry<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
+        let strict_size = self.strict_size;
+        append(self.writer()?, header, &mut data)?;
+        if strict_size {
+            check_no_trailing_data(header, &mut data)?;
+        }
+        Ok(())
     }
 
     /// Adds a new entry to this archive with the specified path.
@@ -117,7 +571,9 @@ impl<W: Write> Builder<W> {
     /// require appending a GNU long-name extension entry to the archive first.
     /// The checksum for the header will be automatically updated via the
     /// `set_cksum` method after setting the path. No other metadata in the
-    /// header will be modified.
+    /// header will be modified. Any `.` and `..` components in `path` are
+    /// resolved lexically, and an error is returned if `path` would escape
+    /// the root of the archive.
     ///
     /// Then it will append the header, followed by contents of the stream
     /// specified by `data`. To produce a valid archive the `size` field of
@@ -157,9 +613,326 @@ impl<W: Write> Builder<W> {
         path: P,
         data: R,
     ) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path.as_ref())?;
+        let long_names = self.long_names;
+        self.check_order(path.as_ref())?;
+        prepare_header_path(self.writer()?, header, path.as_ref(), long_names)?;
+        header.set_cksum();
+        self.write_entry(header, data)?;
+        self.advance_order();
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive with the specified path and the
+    /// contents of a byte slice.
+    ///
+    /// This is like `append_data`, except that the size in `header` is set to
+    /// the length of `data` first, so there's no need to set it by hand or to
+    /// wrap the contents in a reader. Everything else, like the mode and the
+    /// entry type, is taken from `header` as is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_mode(0o644);
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_bytes(&mut header, "hello.txt", b"hello").unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_bytes<P: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        data: &[u8],
+    ) -> io::Result<()> {
+        header.set_size(data.len() as u64);
+        self.append_data(header, path, data)
+    }
+
+    /// Adds a new entry to this archive whose contents are written to the
+    /// returned writer, rather than read from a reader.
+    ///
+    /// The path and size of the entry need to be set in `header` beforehand.
+    /// Its checksum is updated and it's written out straight away, after
+    /// which exactly as many bytes as its size declares have to be written to
+    /// the `EntryWriter`. Writing more than that is an error, and
+    /// `EntryWriter::finish` returns an error if more or less was written.
+    /// Either way the archive stays readable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// let mut header = Header::new_gnu();
+    /// header.set_path("log.txt").unwrap();
+    /// header.set_size(12);
+    /// let mut entry = ar.append_writer(&mut header).unwrap();
+    /// entry.write_all(b"hello ").unwrap();
+    /// entry.write_all(b"world\n").unwrap();
+    /// entry.finish().unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_writer(&mut self, header: &mut Header) -> io::Result<EntryWriter<'_, W>> {
+        let counted = self.check_header_order(header)?;
+        header.set_cksum();
+        let size = header.entry_size()?;
+        self.writer()?.write_all(header.as_bytes())?;
+        if counted {
+            self.advance_order();
+        }
+        let obj = self.get_mut();
+        Ok(EntryWriter {
+            obj,
+            path: header.path_bytes().into_owned(),
+            size,
+            written: 0,
+            overflowed: false,
+            finished: false,
+        })
+    }
+
+    /// Adds a new link entry, such as a symlink or hard link, to this archive
+    /// with the specified path and link target.
+    ///
+    /// This is like `append_data`, except that the link target is set in
+    /// `header` as well. Paths and targets which are too long to fit in the
+    /// header are written to GNU long name and long link extension entries
+    /// preceding the entry, unless `long_names` is disabled, in which case an
+    /// error is returned without writing anything. The entry type and any
+    /// other metadata need to be set in `header` beforehand, while the size
+    /// is set to zero and the checksum is updated automatically.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, EntryType, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_entry_type(EntryType::Symlink);
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_link(&mut header, "link", "really/long/path/to/target").unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_link<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        target: T,
+    ) -> io::Result<()> {
+        let long_names = self.long_names;
+        self.check_order(path.as_ref())?;
+        self.writer()?;
+        // Any extension entries are only written once both the path and the
+        // target are known to fit
+        let mut extensions = Vec::new();
+        prepare_header_path(&mut extensions, header, path.as_ref(), long_names)?;
+        prepare_header_link(&mut extensions, header, target.as_ref(), long_names)?;
+        header.set_size(0);
         header.set_cksum();
-        self.append(&header, data)
+        self.writer()?.write_all(&extensions)?;
+        self.write_entry(header, io::empty())?;
+        self.advance_order();
+        Ok(())
+    }
+
+    /// Adds an entry read from another archive to this archive.
+    ///
+    /// This is the building block for merging several archives into one. The
+    /// header of `entry` is copied (with its checksum recomputed) and then the
+    /// contents of the entry are streamed into this archive. Any extension
+    /// data which described the entry in its original archive, such as GNU
+    /// long name/long link members or pax extended headers, are re-emitted
+    /// in front of it so that the path and other metadata are preserved.
+    ///
+    /// Sparse entries are written out as regular files with their holes
+    /// filled in with zeros. The type flag of other entries is copied as-is
+    /// unless `set_preserve_typeflag` is disabled.
+    ///
+    /// Note that this will not attempt to seek the archive to a valid position,
+    /// so if the archive is in the middle of a read or some other similar
+    /// operation then this may corrupt the archive.
+    ///
+    /// Also note that after all entries have been written to an archive the
+    /// `finish` function needs to be called to finish writing the archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    ///
+    /// for name in &["foo.tar", "bar.tar"] {
+    ///     let mut src = Archive::new(File::open(name).unwrap());
+    ///     for entry in src.entries().unwrap() {
+    ///         ar.append_entry(entry.unwrap()).unwrap();
+    ///     }
+    /// }
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_entry<R: Read>(&mut self, entry: Entry<R>) -> io::Result<()> {
+        let preserve_typeflag = self.preserve_typeflag;
+        self.check_order(&entry.path()?)?;
+        append_entry(self.writer()?, EntryFields::from(entry), preserve_typeflag)?;
+        self.advance_order();
+        Ok(())
+    }
+
+    /// Adds all of the entries of the archive `ar` to this archive, copying
+    /// them verbatim.
+    ///
+    /// Unlike `append_entry`, every header is copied byte for byte as it was
+    /// read, including the GNU long name/long link members and pax extended
+    /// headers in front of an entry, and the contents are copied without
+    /// being interpreted. This makes merging archives cheap and keeps their
+    /// checksums intact. The end of archive marker of `ar` isn't copied, so
+    /// entries of several archives can be appended one after another. Global
+    /// pax headers are copied too, and so also apply to the entries appended
+    /// after them.
+    ///
+    /// An error is returned for entries whose layout isn't described by their
+    /// own header, namely entries with a pax `size` record overriding the
+    /// header's size and old GNU sparse entries with extended sparse headers.
+    /// The members describing such an entry are left out when that happens.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let mut ar = Builder::new(File::create("merged.tar").unwrap());
+    /// for name in &["foo.tar", "bar.tar"] {
+    ///     let mut src = Archive::new(File::open(name).unwrap());
+    ///     ar.append_archive(&mut src).unwrap();
+    /// }
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_archive<R: Read>(&mut self, ar: &mut Archive<R>) -> io::Result<()> {
+        // Members describing the next entry are held back until it's found,
+        // so nothing is written for them if it can't be copied.
+        let mut pending: Vec<([u8; 512], Vec<u8>)> = Vec::new();
+        let mut long_name = None;
+        let mut pax = None;
+        for entry in ar.entries()?.raw(true) {
+            let mut entry = entry?;
+            let raw = *entry.header_raw_bytes();
+            let header = Header::from_byte_slice(&raw);
+            let entry_type = header.entry_type();
+
+            if entry_type.is_gnu_longname()
+                || entry_type.is_gnu_longlink()
+                || entry_type.is_pax_local_extensions()
+            {
+                let data = entry.contents()?;
+                if entry_type.is_gnu_longname() {
+                    long_name = Some(data.clone());
+                } else if entry_type.is_pax_local_extensions() {
+                    pax = Some(data.clone());
+                }
+                pending.push((raw, data));
+                continue;
+            }
+            if entry_type.is_pax_global_extensions() {
+                self.write_entry(header, entry)?;
+                continue;
+            }
+
+            let mut path = match long_name.take() {
+                Some(mut name) => {
+                    if name.last() == Some(&0) {
+                        name.pop();
+                    }
+                    Cow::Owned(name)
+                }
+                None => header.path_bytes(),
+            };
+            if let Some(ref pax) = pax {
+                if let Some(size) = pax_extensions_size(pax)? {
+                    if size != header.entry_size()? {
+                        return Err(other(&format!(
+                            "can't copy `{}` verbatim as its size is only \
+                             listed in a pax extended header",
+                            String::from_utf8_lossy(&path)
+                        )));
+                    }
+                }
+                for ext in pax_extensions(pax) {
+                    let ext = ext?;
+                    if ext.key_bytes() == b"path" {
+                        path = Cow::Owned(ext.value_bytes().to_vec());
+                    }
+                }
+            }
+            pax = None;
+            if header.as_gnu().is_some_and(|gnu| gnu.is_extended()) {
+                return Err(other(&format!(
+                    "can't copy the sparse file `{}` verbatim as it has \
+                     extended sparse headers",
+                    String::from_utf8_lossy(&path)
+                )));
+            }
+            self.check_order(&bytes2path(path)?)?;
+
+            for (raw, data) in pending.drain(..) {
+                self.write_entry(Header::from_byte_slice(&raw), &data[..])?;
+            }
+            self.write_entry(header, entry)?;
+            self.advance_order();
+        }
+        if !pending.is_empty() {
+            return Err(other(
+                "members found describing a future member \
+                 but no future member found",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds a pax extended header entry with the given key/value records to
+    /// this archive.
+    ///
+    /// The records apply to the next entry appended to the archive. Keys are
+    /// written as-is, so any namespace can be used, including the ones that
+    /// POSIX reserves for implementations such as `security.*` and
+    /// `realtime.*`. Nothing is written if `records` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_pax_extensions(vec![
+    ///     ("security.selinux", &b"system_u:object_r:etc_t:s0"[..]),
+    /// ]).unwrap();
+    ///
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(0);
+    /// header.set_cksum();
+    /// ar.append_data(&mut header, "foo", &[][..]).unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_pax_extensions<'key, 'value, I>(&mut self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'key str, &'value [u8])>,
+    {
+        let mut data = Vec::new();
+        for (key, value) in records {
+            push_pax_record(&mut data, key, value);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        append_pax_extensions(self.writer()?, &data)
     }
 
     /// Adds a file on the local filesystem to this archive.
@@ -168,7 +941,8 @@ impl<W: Write> Builder<W> {
     /// into the archive with the appropriate metadata set, returning any I/O
     /// error which occurs while writing. The path name for the file inside of
     /// this archive will be the same as `path`, and it is required that the
-    /// path is a relative path.
+    /// path is a relative path. Any `.` and `..` components in it are resolved
+    /// lexically.
     ///
     /// Note that this will not attempt to seek the archive to a valid position,
     /// so if the archive is in the middle of a read or some other similar
@@ -187,9 +961,9 @@ impl<W: Write> Builder<W> {
     /// ar.append_path("foo/bar.txt").unwrap();
     /// ```
     pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let mode = self.mode.clone();
         let follow = self.follow;
-        append_path_with_name(self.get_mut(), path.as_ref(), None, mode, follow)
+        let (dst, mut opts) = self.fs_parts()?;
+        append_path_with_name(dst, path.as_ref(), None, follow, &mut opts)
     }
 
     /// Adds a file on the local filesystem to this archive under another name.
@@ -225,15 +999,9 @@ impl<W: Write> Builder<W> {
         path: P,
         name: N,
     ) -> io::Result<()> {
-        let mode = self.mode.clone();
         let follow = self.follow;
-        append_path_with_name(
-            self.get_mut(),
-            path.as_ref(),
-            Some(name.as_ref()),
-            mode,
-            follow,
-        )
+        let (dst, mut opts) = self.fs_parts()?;
+        append_path_with_name(dst, path.as_ref(), Some(name.as_ref()), follow, &mut opts)
     }
 
     /// Adds a file to this archive with the given path as the name of the file
@@ -263,15 +1031,17 @@ impl<W: Write> Builder<W> {
     /// ar.append_file("bar/baz.txt", &mut f).unwrap();
     /// ```
     pub fn append_file<P: AsRef<Path>>(&mut self, path: P, file: &mut fs::File) -> io::Result<()> {
-        let mode = self.mode.clone();
-        append_file(self.get_mut(), path.as_ref(), file, mode)
+        let (dst, mut opts) = self.fs_parts()?;
+        append_file(dst, path.as_ref(), file, &mut opts)
     }
 
     /// Adds a directory to this archive with the given path as the name of the
     /// directory in the archive.
     ///
     /// This will use `stat` to populate a `Header`, and it will then append the
-    /// directory to the archive with the name `path`.
+    /// directory to the archive with the name `path`. The name stored in the
+    /// archive always ends in a single `/`, so `foo`, `foo/` and `foo//` are
+    /// all archived as `foo/`.
     ///
     /// Note that this will not attempt to seek the archive to a valid position,
     /// so if the archive is in the middle of a read or some other similar
@@ -300,13 +1070,19 @@ impl<W: Write> Builder<W> {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        let mode = self.mode.clone();
-        append_dir(self.get_mut(), path.as_ref(), src_path.as_ref(), mode)
+        let (dst, mut opts) = self.fs_parts()?;
+        append_dir(dst, path.as_ref(), src_path.as_ref(), &mut opts)
     }
 
     /// Adds a directory and all of its contents (recursively) to this archive
     /// with the given path as the name of the directory in the archive.
     ///
+    /// The contents of each directory are added in order of their names,
+    /// right after the directory itself, so archiving the same tree always
+    /// produces the same archive (see also `mode` for the metadata which is
+    /// recorded). Symlinks are archived as symlinks or followed depending on
+    /// `follow_symlinks`.
+    ///
     /// Note that this will not attempt to seek the archive to a valid position,
     /// so if the archive is in the middle of a read or some other similar
     /// operation then this may corrupt the archive.
@@ -331,15 +1107,9 @@ impl<W: Write> Builder<W> {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        let mode = self.mode.clone();
         let follow = self.follow;
-        append_dir_all(
-            self.get_mut(),
-            path.as_ref(),
-            src_path.as_ref(),
-            mode,
-            follow,
-        )
+        let (dst, mut opts) = self.fs_parts()?;
+        append_dir_all(dst, path.as_ref(), src_path.as_ref(), follow, &mut opts)
     }
 
     /// Finish writing this archive, emitting the termination sections.
@@ -348,19 +1118,139 @@ impl<W: Write> Builder<W> {
     /// entirely and if an I/O error happens the underlying object still needs
     /// to be acquired.
     ///
+    /// Calling this more than once has no effect, the end of the archive is
+    /// only written the first time. Appending entries to the archive after
+    /// it's been finished fails.
+    ///
     /// In most situations the `into_inner` method should be preferred.
     pub fn finish(&mut self) -> io::Result<()> {
         if self.finished {
             return Ok(());
         }
+        if let Some(ref listing) = self.listing {
+            if let Some(path) = listing.paths.get(listing.next) {
+                return Err(other(&format!(
+                    "archive finished before `{}`, entry {} of the listing, \
+                     was appended",
+                    path.display(),
+                    listing.next
+                )));
+            }
+        }
         self.finished = true;
         self.get_mut().write_all(&[0; 1024])
     }
 }
 
-fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) -> io::Result<()> {
+impl<W: Write + Seek> Builder<W> {
+    /// Adds a new entry to this archive with the specified path, without
+    /// knowing the size of its contents up front.
+    ///
+    /// This works like `append_data`, except that the `size` field of
+    /// `header` is ignored. Instead a placeholder header is written, all of
+    /// `data` is streamed into the archive, and then the underlying writer is
+    /// seeked back to fill in the size and checksum of the header before
+    /// seeking forward again. When this returns `header` contains the header
+    /// as it was written to the archive.
+    ///
+    /// This avoids having to spool the contents to a temporary file just to
+    /// learn their size, at the cost of requiring a seekable writer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_mode(0o644);
+    ///
+    /// let mut data: &[u8] = &[1, 2, 3, 4];
+    ///
+    /// let mut ar = Builder::new(Cursor::new(Vec::new()));
+    /// ar.append_streaming(&mut header, "foo", data).unwrap();
+    /// assert_eq!(header.size().unwrap(), 4);
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_streaming<P: AsRef<Path>, R: Read>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        mut data: R,
+    ) -> io::Result<()> {
+        let long_names = self.long_names;
+        self.check_order(path.as_ref())?;
+        prepare_header_path(self.writer()?, header, path.as_ref(), long_names)?;
+        append_streaming(self.writer()?, header, &mut data)?;
+        self.advance_order();
+        Ok(())
+    }
+}
+
+/// Writes an archive containing a single file named `path` with the given
+/// contents.
+///
+/// This is a shorthand for creating a `Builder`, appending one entry and
+/// finishing the archive. The entry is a regular file with mode `0o644`, a
+/// modification time of zero and no owner, so the output only depends on the
+/// arguments. Use `unpack_single` to read such an archive back.
+///
+/// # Examples
+///
+/// ```
+/// let mut data = Vec::new();
+/// tar::pack_single(&mut data, "hello.txt", b"hello").unwrap();
+/// assert_eq!(data.len(), 512 * 4);
+/// ```
+pub fn pack_single<W: Write>(w: W, path: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_entry_type(EntryType::Regular);
+    let mut ar = Builder::new(w);
+    ar.append_bytes(&mut header, path, data)?;
+    ar.finish()
+}
+
+trait SeekWrite: Write + Seek {}
+impl<W: Write + Seek> SeekWrite for W {}
+
+fn append(mut dst: &mut dyn Write, header: &Header, data: &mut dyn Read) -> io::Result<()> {
+    let size = header.entry_size()?;
     dst.write_all(header.as_bytes())?;
-    let len = io::copy(&mut data, &mut dst)?;
+    let len = io::copy(&mut data.take(size), &mut dst)?;
+    // The header is already written, so fill in the missing contents with
+    // zeros to keep the following entries readable
+    io::copy(&mut io::repeat(0).take(size - len), &mut dst)?;
+
+    // Pad with zeros if necessary.
+    let buf = [0; 512];
+    let remaining = 512 - (size % 512);
+    if remaining < 512 {
+        dst.write_all(&buf[..remaining as usize])?;
+    }
+
+    if len < size {
+        return Err(other(&format!(
+            "data for `{}` ended after {} bytes but the header declares {} bytes",
+            String::from_utf8_lossy(&header.path_bytes()),
+            len,
+            size
+        )));
+    }
+    Ok(())
+}
+
+fn append_streaming(
+    dst: &mut dyn SeekWrite,
+    header: &mut Header,
+    mut data: &mut dyn Read,
+) -> io::Result<()> {
+    let header_pos = dst.stream_position()?;
+    header.set_size(0);
+    header.set_cksum();
+    dst.write_all(header.as_bytes())?;
+    let len = io::copy(&mut data, dst)?;
 
     // Pad with zeros if necessary.
     let buf = [0; 512];
@@ -369,15 +1259,103 @@ fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) ->
         dst.write_all(&buf[..remaining as usize])?;
     }
 
+    let end_pos = dst.stream_position()?;
+    header.set_size(len);
+    header.set_cksum();
+    dst.seek(io::SeekFrom::Start(header_pos))?;
+    dst.write_all(header.as_bytes())?;
+    dst.seek(io::SeekFrom::Start(end_pos))?;
     Ok(())
 }
 
+fn check_no_trailing_data(header: &Header, data: &mut dyn Read) -> io::Result<()> {
+    let mut buf = [0];
+    loop {
+        match data.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                return Err(other(&format!(
+                    "data for `{}` is longer than the {} bytes declared in the header",
+                    String::from_utf8_lossy(&header.path_bytes()),
+                    header.entry_size()?
+                )))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn append_entry(
+    dst: &mut dyn Write,
+    mut fields: EntryFields,
+    preserve_typeflag: bool,
+) -> io::Result<()> {
+    if let Some(ref name) = fields.long_pathname {
+        append_long_name(dst, name, b'L')?;
+    }
+    if let Some(ref name) = fields.long_linkname {
+        append_long_name(dst, name, b'K')?;
+    }
+    if let Some(ref pax) = fields.pax_extensions {
+        append_pax_extensions(dst, pax)?;
+    }
+
+    let mut header = fields.header.clone();
+    if header.entry_type().is_gnu_sparse() {
+        // The sparse map isn't preserved, the holes are instead read back
+        // as zeros so the entry needs to describe the full file.
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(fields.size);
+    } else if !preserve_typeflag {
+        let entry_type = header.entry_type();
+        header.set_entry_type(entry_type);
+    }
+    header.set_cksum();
+    append(dst, &header, &mut fields)
+}
+
+/// Formats a pax extended header record and appends it to `data`.
+fn push_pax_record(data: &mut Vec<u8>, key: &str, value: &[u8]) {
+    // The length prefix counts itself, so find the number of digits it needs
+    // to have.
+    let rest = key.len() + value.len() + 3;
+    let mut digits = 1;
+    while rest + digits >= 10usize.pow(digits as u32) {
+        digits += 1;
+    }
+    data.extend_from_slice(format!("{} {}=", rest + digits, key).as_bytes());
+    data.extend_from_slice(value);
+    data.push(b'\n');
+}
+
+fn append_pax_extensions(dst: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_ustar();
+    let name = b"././@PaxHeader";
+    header.as_ustar_mut().unwrap().name[..name.len()].clone_from_slice(&name[..]);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_entry_type(EntryType::XHeader);
+    header.set_cksum();
+    append(dst, &header, &mut &data[..])
+}
+
+fn append_long_name(dst: &mut dyn Write, name: &[u8], entry_type: u8) -> io::Result<()> {
+    let name = match name.last() {
+        Some(&0) => &name[..name.len() - 1],
+        _ => name,
+    };
+    let header = prepare_header(name.len() as u64, entry_type);
+    let mut data = name.chain(io::repeat(0).take(1));
+    append(dst, &header, &mut data)
+}
+
 fn append_path_with_name(
     dst: &mut dyn Write,
     path: &Path,
     name: Option<&Path>,
-    mode: HeaderMode,
     follow: bool,
+    opts: &mut FsOptions<'_>,
 ) -> io::Result<()> {
     let stat = if follow {
         fs::metadata(path).map_err(|err| {
@@ -396,9 +1374,9 @@ fn append_path_with_name(
     };
     let ar_name = name.unwrap_or(path);
     if stat.is_file() {
-        append_fs(dst, ar_name, &stat, &mut fs::File::open(path)?, mode, None)
+        append_regular_file(dst, ar_name, &stat, &mut fs::File::open(path)?, opts)
     } else if stat.is_dir() {
-        append_fs(dst, ar_name, &stat, &mut io::empty(), mode, None)
+        append_fs(dst, ar_name, &stat, &mut io::empty(), None, opts)
     } else if stat.file_type().is_symlink() {
         let link_name = fs::read_link(path)?;
         append_fs(
@@ -406,8 +1384,8 @@ fn append_path_with_name(
             ar_name,
             &stat,
             &mut io::empty(),
-            mode,
             Some(&link_name),
+            opts,
         )
     } else {
         Err(other(&format!("{} has unknown file type", path.display())))
@@ -418,20 +1396,99 @@ fn append_file(
     dst: &mut dyn Write,
     path: &Path,
     file: &mut fs::File,
-    mode: HeaderMode,
+    opts: &mut FsOptions<'_>,
 ) -> io::Result<()> {
     let stat = file.metadata()?;
-    append_fs(dst, path, &stat, file, mode, None)
+    if stat.is_file() {
+        append_regular_file(dst, path, &stat, file, opts)
+    } else {
+        append_fs(dst, path, &stat, file, None, opts)
+    }
+}
+
+fn append_regular_file(
+    dst: &mut dyn Write,
+    path: &Path,
+    stat: &fs::Metadata,
+    file: &mut fs::File,
+    opts: &mut FsOptions<'_>,
+) -> io::Result<()> {
+    let index = match opts.dedup {
+        Some(ref mut index) if stat.len() > 0 => index,
+        _ => return append_fs(dst, path, stat, file, None, opts),
+    };
+
+    let start = file.stream_position()?;
+    let mut hashers = (index.keys.0.build_hasher(), index.keys.1.build_hasher());
+    let mut buf = [0; 8 * 1024];
+    let mut len = 0;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hashers.0.write(&buf[..n]);
+        hashers.1.write(&buf[..n]);
+        len += n as u64;
+    }
+    file.seek(SeekFrom::Start(start))?;
+
+    let key = (len, hashers.0.finish(), hashers.1.finish());
+    if let Some(first) = index.seen.get(&key) {
+        let first = first.clone();
+        if let Some(ref listing) = opts.listing {
+            listing.check(path)?;
+        }
+        let mut header = Header::new_gnu();
+        prepare_header_path(dst, &mut header, path, opts.long_names)?;
+        header.set_metadata_in_mode(stat, opts.mode);
+        set_owner(&mut header, opts)?;
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        prepare_header_link(dst, &mut header, &first, opts.long_names)?;
+        header.set_cksum();
+        append(dst, &header, &mut io::empty())?;
+        if let Some(ref mut listing) = opts.listing {
+            listing.next += 1;
+        }
+        return Ok(());
+    }
+    let first = normalize_path(path)?.into_owned();
+    append_fs(dst, path, stat, file, None, opts)?;
+    // Later copies can only link to the file once it's in the archive
+    if let Some(ref mut index) = opts.dedup {
+        index.seen.insert(key, first);
+    }
+    Ok(())
 }
 
 fn append_dir(
     dst: &mut dyn Write,
     path: &Path,
     src_path: &Path,
-    mode: HeaderMode,
+    opts: &mut FsOptions<'_>,
 ) -> io::Result<()> {
     let stat = fs::metadata(src_path)?;
-    append_fs(dst, path, &stat, &mut io::empty(), mode, None)
+    let path = dir_path(path)?;
+    append_fs(dst, &path, &stat, &mut io::empty(), None, opts)
+}
+
+/// Gives a directory's path exactly one trailing slash, which is how GNU tar
+/// names directory entries.
+fn dir_path(path: &Path) -> io::Result<Cow<'_, Path>> {
+    let bytes = path2bytes(path)?;
+    let trimmed = match bytes.iter().rposition(|b| *b != b'/') {
+        Some(i) => &bytes[..i + 1],
+        None => return Ok(Cow::Borrowed(path)),
+    };
+    if trimmed.len() + 1 == bytes.len() {
+        return Ok(Cow::Borrowed(path));
+    }
+    let mut bytes = trimmed.to_vec();
+    bytes.push(b'/');
+    Ok(Cow::Owned(bytes2path(Cow::Owned(bytes))?.into_owned()))
 }
 
 fn prepare_header(size: u64, entry_type: u8) -> Header {
@@ -449,7 +1506,14 @@ fn prepare_header(size: u64, entry_type: u8) -> Header {
     header
 }
 
-fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) -> io::Result<()> {
+fn prepare_header_path(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+    long_names: bool,
+) -> io::Result<()> {
+    let path = &*normalize_path(path)?;
+
     // Try to encode the path directly in the header, but if it ends up not
     // working (probably because it's too long) then try to use the GNU-specific
     // long name extension by emitting an entry which indicates that it's the
@@ -462,6 +1526,14 @@ fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) ->
         if data.len() < max {
             return Err(e);
         }
+        if !long_names {
+            return Err(other(&format!(
+                "path `{}` is {} bytes long and doesn't fit in the header \
+                 without a long name extension",
+                path.display(),
+                data.len()
+            )));
+        }
         let header2 = prepare_header(data.len() as u64, b'L');
         // null-terminated string
         let mut data2 = data.chain(io::repeat(0).take(1));
@@ -474,10 +1546,59 @@ fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) ->
     Ok(())
 }
 
+/// Lexically resolves `.` and `..` components in a path destined for the
+/// archive, so entries aren't written which would be skipped on extraction.
+///
+/// Returns an error if a `..` component would escape the root of the archive.
+fn normalize_path(path: &Path) -> io::Result<Cow<'_, Path>> {
+    let needs_normalizing = path
+        .components()
+        .any(|c| c == Component::CurDir || c == Component::ParentDir);
+    if !needs_normalizing {
+        return Ok(Cow::Borrowed(path));
+    }
+
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(Component::Normal(_)) => {
+                    parts.pop();
+                }
+                _ => {
+                    return Err(other(&format!(
+                        "path `{}` escapes the root of the archive",
+                        path.display()
+                    )));
+                }
+            },
+            c => parts.push(c),
+        }
+    }
+    // Leave paths like "./" alone, they're handled when setting the path
+    if parts.is_empty() {
+        return Ok(Cow::Borrowed(path));
+    }
+
+    let mut bytes = Vec::new();
+    for part in parts {
+        if !bytes.is_empty() && !bytes.ends_with(b"/") {
+            bytes.push(b'/');
+        }
+        bytes.extend_from_slice(&path2bytes(Path::new(part.as_os_str()))?);
+    }
+    if path2bytes(path)?.ends_with(b"/") {
+        bytes.push(b'/');
+    }
+    Ok(Cow::Owned(bytes2path(Cow::Owned(bytes))?.into_owned()))
+}
+
 fn prepare_header_link(
     dst: &mut dyn Write,
     header: &mut Header,
     link_name: &Path,
+    long_names: bool,
 ) -> io::Result<()> {
     // Same as previous function but for linkname
     if let Err(e) = header.set_link_name(&link_name) {
@@ -485,6 +1606,14 @@ fn prepare_header_link(
         if data.len() < header.as_old().linkname.len() {
             return Err(e);
         }
+        if !long_names {
+            return Err(other(&format!(
+                "link name `{}` is {} bytes long and doesn't fit in the \
+                 header without a long link extension",
+                link_name.display(),
+                data.len()
+            )));
+        }
         let header2 = prepare_header(data.len() as u64, b'K');
         let mut data2 = data.chain(io::repeat(0).take(1));
         append(dst, &header2, &mut data2)?;
@@ -497,53 +1626,236 @@ fn append_fs(
     path: &Path,
     meta: &fs::Metadata,
     read: &mut dyn Read,
-    mode: HeaderMode,
     link_name: Option<&Path>,
+    opts: &mut FsOptions<'_>,
 ) -> io::Result<()> {
+    if let Some(ref listing) = opts.listing {
+        listing.check(path)?;
+    }
+    if opts.subsecond_mtimes && opts.mode == HeaderMode::Complete {
+        append_subsecond_mtime(dst, meta)?;
+    }
     let mut header = Header::new_gnu();
 
-    prepare_header_path(dst, &mut header, path)?;
-    header.set_metadata_in_mode(meta, mode);
+    prepare_header_path(dst, &mut header, path, opts.long_names)?;
+    header.set_metadata_in_mode(meta, opts.mode);
+    set_owner(&mut header, opts)?;
     if let Some(link_name) = link_name {
-        prepare_header_link(dst, &mut header, link_name)?;
+        prepare_header_link(dst, &mut header, link_name, opts.long_names)?;
     }
     header.set_cksum();
-    append(dst, &header, read)
+    append(dst, &header, read)?;
+    if let Some(ref mut listing) = opts.listing {
+        listing.next += 1;
+    }
+    Ok(())
+}
+
+/// Writes a pax extended header with the modification time of `meta` if it
+/// has a fraction of a second, which the header itself can't store.
+fn append_subsecond_mtime(dst: &mut dyn Write, meta: &fs::Metadata) -> io::Result<()> {
+    let mtime = match meta.modified() {
+        Ok(mtime) => mtime,
+        Err(_) => return Ok(()),
+    };
+    let since_epoch = match mtime.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) if since_epoch.subsec_nanos() != 0 => since_epoch,
+        _ => return Ok(()),
+    };
+    let value = format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    );
+    let mut data = Vec::new();
+    push_pax_record(&mut data, "mtime", value.trim_end_matches('0').as_bytes());
+    append_pax_extensions(dst, &data)
+}
+
+impl Listing {
+    /// Checks that `path` is the next path of the listing. The listing only
+    /// moves on once the entry has been written, so it stays in sync with
+    /// the archive when appending fails.
+    fn check(&self, path: &Path) -> io::Result<()> {
+        let path = normalize_path(path)?;
+        let expected = match self.paths.get(self.next) {
+            Some(expected) => expected,
+            None => {
+                return Err(other(&format!(
+                    "`{}` was appended after all {} entries of the listing",
+                    path.display(),
+                    self.paths.len()
+                )));
+            }
+        };
+        let matches = match normalize_path(expected) {
+            Ok(expected) => expected == path,
+            Err(_) => false,
+        };
+        if !matches {
+            return Err(other(&format!(
+                "`{}` was appended as entry {} but the listing expects `{}`",
+                path.display(),
+                self.next,
+                expected.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn set_owner(header: &mut Header, opts: &FsOptions<'_>) -> io::Result<()> {
+    if let Some(ref owner) = opts.owner {
+        header.set_uid(owner.uid);
+        header.set_gid(owner.gid);
+        header.set_username(&owner.username)?;
+        header.set_groupname(&owner.groupname)?;
+    }
+    Ok(())
 }
 
 fn append_dir_all(
     dst: &mut dyn Write,
     path: &Path,
     src_path: &Path,
-    mode: HeaderMode,
     follow: bool,
+    opts: &mut FsOptions<'_>,
 ) -> io::Result<()> {
     let mut stack = vec![(src_path.to_path_buf(), true, false)];
     while let Some((src, is_dir, is_symlink)) = stack.pop() {
         let dest = path.join(src.strip_prefix(&src_path).unwrap());
         // In case of a symlink pointing to a directory, is_dir is false, but src.is_dir() will return true
         if is_dir || (is_symlink && follow && src.is_dir()) {
+            if is_symlink {
+                check_symlink_loop(&src, src_path)?;
+            }
+            // Directories are listed in an arbitrary order, sort them so the
+            // same tree always produces the same archive
+            let mut children = Vec::new();
             for entry in fs::read_dir(&src)? {
                 let entry = entry?;
                 let file_type = entry.file_type()?;
-                stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
+                children.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
             }
+            children.sort_by(|a, b| b.0.cmp(&a.0));
+            stack.extend(children);
             if dest != Path::new("") {
-                append_dir(dst, &dest, &src, mode)?;
+                append_dir(dst, &dest, &src, opts)?;
             }
         } else if !follow && is_symlink {
             let stat = fs::symlink_metadata(&src)?;
             let link_name = fs::read_link(&src)?;
-            append_fs(dst, &dest, &stat, &mut io::empty(), mode, Some(&link_name))?;
+            append_fs(dst, &dest, &stat, &mut io::empty(), Some(&link_name), opts)?;
         } else {
-            append_file(dst, &dest, &mut fs::File::open(src)?, mode)?;
+            append_file(dst, &dest, &mut fs::File::open(src)?, opts)?;
         }
     }
     Ok(())
 }
 
+/// Returns an error if the symlink `src` found while walking `root` points to
+/// a directory which contains it, which would otherwise be archived forever.
+fn check_symlink_loop(src: &Path, root: &Path) -> io::Result<()> {
+    let target = fs::canonicalize(src)?;
+    let mut looped = fs::canonicalize(root)?.starts_with(&target);
+    for ancestor in src.ancestors().skip(1) {
+        if looped || ancestor == root {
+            break;
+        }
+        looped = fs::canonicalize(ancestor)? == target;
+    }
+    if looped {
+        return Err(other(&format!(
+            "symlink loop found at `{}`, which points to `{}`",
+            src.display(),
+            target.display()
+        )));
+    }
+    Ok(())
+}
+
+impl<'a, W: Write> EntryWriter<'a, W> {
+    /// Returns the number of bytes which still have to be written.
+    pub fn remaining(&self) -> u64 {
+        self.size - self.written
+    }
+
+    /// Completes the entry, padding its contents to a whole block.
+    ///
+    /// An error is returned if fewer bytes were written than the size of the
+    /// entry declares. The rest of the contents are filled in with zeros in
+    /// that case, so the following entries can still be read. It's also an
+    /// error if writing more than the declared size was attempted, in which
+    /// case the extra bytes were left out.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finished = true;
+        let missing = self.remaining();
+        self.complete()?;
+        if self.overflowed {
+            return Err(other(&format!(
+                "more than the {} bytes declared in the header were written for `{}`",
+                self.size,
+                String::from_utf8_lossy(&self.path)
+            )));
+        }
+        if missing > 0 {
+            return Err(other(&format!(
+                "only {} of the {} bytes declared in the header were written for `{}`",
+                self.written,
+                self.size,
+                String::from_utf8_lossy(&self.path)
+            )));
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self) -> io::Result<()> {
+        let missing = self.remaining();
+        io::copy(&mut io::repeat(0).take(missing), self.obj)?;
+        let remaining = 512 - (self.size % 512);
+        if remaining < 512 {
+            self.obj.write_all(&[0; 512][..remaining as usize])?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for EntryWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.remaining();
+        if remaining == 0 && !buf.is_empty() {
+            self.overflowed = true;
+            return Err(other(&format!(
+                "data for `{}` is longer than the {} bytes declared in the header",
+                String::from_utf8_lossy(&self.path),
+                self.size
+            )));
+        }
+        let len = cmp::min(buf.len() as u64, remaining) as usize;
+        let n = self.obj.write(&buf[..len])?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.obj.flush()
+    }
+}
+
+impl<'a, W: Write> Drop for EntryWriter<'a, W> {
+    fn drop(&mut self) {
+        if !self.finished && !thread::panicking() {
+            let _ = self.complete();
+        }
+    }
+}
+
 impl<W: Write> Drop for Builder<W> {
     fn drop(&mut self) {
-        let _ = self.finish();
+        // An archive left behind by a panic is likely incomplete, so it isn't
+        // made to look finished.
+        if self.finish_on_drop && self.obj.is_some() && !thread::panicking() {
+            let _ = self.finish();
+        }
     }
 }
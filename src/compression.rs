@@ -0,0 +1,119 @@
+#[cfg(feature = "flate2")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "flate2")]
+use flate2::read::MultiGzDecoder;
+#[cfg(feature = "flate2")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "flate2")]
+use flate2::Compression;
+
+#[cfg(feature = "flate2")]
+use crate::{Archive, Builder};
+
+/// The compression format that an archive stream appears to be wrapped in.
+///
+/// This library does not decompress archives itself, but `detect_format` can
+/// be used to figure out which decompressor should be placed in front of an
+/// `Archive` before reading it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionFormat {
+    /// gzip, as produced by `tar -z`
+    ///
+    /// A gzip file may contain several members back to back, which `pigz`
+    /// and concatenated archives produce, so it should be read with a decoder
+    /// which continues past the end of the first member. Otherwise the
+    /// archive appears to end early.
+    Gzip,
+    /// bzip2, as produced by `tar -j`
+    Bzip2,
+    /// xz, as produced by `tar -J`
+    Xz,
+    /// Zstandard, as produced by `tar --zstd`
+    Zstd,
+    /// Hints that destructuring should not be exhaustive.
+    ///
+    /// This enum may grow additional variants, so this makes sure clients
+    /// don't count on exhaustive matching. (Otherwise, adding a new variant
+    /// could break existing code.)
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Inspects the leading bytes of a stream and classifies its compression
+/// format.
+///
+/// Returns `None` if the bytes don't start with a known magic number, which
+/// typically means the stream is an uncompressed tar archive (or not an
+/// archive at all). Only the first few bytes are looked at, so it's enough to
+/// pass in the first block read from the input.
+///
+/// # Examples
+///
+/// ```
+/// use tar::CompressionFormat;
+///
+/// assert_eq!(tar::detect_format(&[0x1f, 0x8b, 0x08]), Some(CompressionFormat::Gzip));
+/// assert_eq!(tar::detect_format(b"foo"), None);
+/// ```
+pub fn detect_format(bytes: &[u8]) -> Option<CompressionFormat> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        Some(CompressionFormat::Gzip)
+    } else if bytes.starts_with(b"BZh") {
+        Some(CompressionFormat::Bzip2)
+    } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(CompressionFormat::Xz)
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(CompressionFormat::Zstd)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl<R: Read> Archive<MultiGzDecoder<R>> {
+    /// Create a new archive reading a gzip compressed archive, such as a
+    /// `.tar.gz` file, from `obj`.
+    ///
+    /// Archives made up of several gzip members are read in full. As the
+    /// decompressed stream can't be seeked, the archive is read sequentially
+    /// with `entries` or `unpack`. This is only available with the `flate2`
+    /// feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new_gz(File::open("foo.tar.gz").unwrap());
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn new_gz(obj: R) -> Archive<MultiGzDecoder<R>> {
+        Archive::new(MultiGzDecoder::new(obj))
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl<W: Write> Builder<GzEncoder<W>> {
+    /// Create a new archive builder writing a gzip compressed archive to
+    /// `obj`, using the default compression level.
+    ///
+    /// The gzip stream is only complete once the encoder returned by
+    /// `into_inner` has been finished with `GzEncoder::finish`, which also
+    /// returns `obj`. This is only available with the `flate2` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new_gz(File::create("foo.tar.gz").unwrap());
+    /// ar.append_dir_all("bar", "path/to/bar").unwrap();
+    /// ar.into_inner().unwrap().finish().unwrap();
+    /// ```
+    pub fn new_gz(obj: W) -> Builder<GzEncoder<W>> {
+        Builder::new(GzEncoder::new(obj, Compression::default()))
+    }
+}
@@ -1,26 +1,41 @@
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::cmp;
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::marker;
 use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use filetime::{self, FileTime};
 
-use crate::archive::ArchiveInner;
+use crate::archive::{ArchiveInner, EntryHasher, SeekableArchive};
+use crate::beneath;
+use crate::entry_type::EntryTypeSet;
 use crate::error::TarError;
 use crate::header::bytes2path;
 use crate::other;
-use crate::pax::pax_extensions;
-use crate::{Archive, Header, PaxExtensions};
+use crate::pax::{pax_extensions, pax_nanos, pax_number};
+use crate::{
+    Archive, EntryType, FileType, HardlinkFallback, Header, ParentDirPolicy, PathEncoding,
+    PaxExtensions, UnknownTypePolicy, WindowsNamePolicy,
+};
 
 /// A read-only view into an entry of an archive.
 ///
 /// This structure is a window into a portion of a borrowed archive which can
 /// be inspected. It acts as a file handle by implementing the Reader trait. An
 /// entry cannot be rewritten once inserted into an archive.
+///
+/// Reads are limited to the contents of the entry, returning end of file once
+/// `size` bytes have been read no matter how large the buffer passed in, so
+/// the entry can be read to the end with `io::copy` without reading into the
+/// entries which follow it.
 pub struct Entry<'a, R: 'a + Read> {
     fields: EntryFields<'a>,
     _ignored: marker::PhantomData<&'a Archive<R>>,
@@ -33,19 +48,82 @@ pub struct EntryFields<'a> {
     pub long_linkname: Option<Vec<u8>>,
     pub pax_extensions: Option<Vec<u8>>,
     pub header: Header,
+    // The header as it was read, if it differs from `header`
+    pub raw_header: Option<Box<[u8; 512]>>,
+    pub bad_checksum: bool,
     pub size: u64,
     pub header_pos: u64,
     pub file_pos: u64,
     pub data: Vec<EntryIo<'a>>,
+    pub seekable_archive: Option<SeekableArchive<'a>>,
     pub unpack_xattrs: bool,
     pub preserve_permissions: bool,
     pub preserve_mtime: bool,
+    pub preserve_ownerships: bool,
+    pub numeric_owner: bool,
     pub overwrite: bool,
+    pub skip_existing: bool,
+    pub unknown_type_policy: UnknownTypePolicy,
+    pub allowed_entry_types: Option<EntryTypeSet>,
+    pub skip_disallowed_types: bool,
+    pub hardlink_fallback: HardlinkFallback,
+    pub unpack_preallocate: bool,
+    pub unpack_special_files: bool,
+    pub secure_extraction: bool,
+    pub default_file_mode: Option<u32>,
+    pub default_dir_mode: Option<u32>,
+    pub mask: u32,
+    pub windows_name_policy: WindowsNamePolicy,
+    pub path_encoding: PathEncoding,
+    pub parentdir_policy: ParentDirPolicy,
+    pub max_entry_size: Option<u64>,
+    // What's left of a limit on the total size of the unpacked entries
+    pub total_size_left: Option<u64>,
+    // How many bytes decompressing the contents produced
+    pub decoded_len: u64,
+    pub allow_truncated: bool,
+    pub truncated: Rc<Cell<bool>>,
+    pub total_len: Option<u64>,
+    pub contents: Option<&'a [u8]>,
+    // The (offset, length) of each block of data of a sparse file
+    pub sparse_map: Option<Vec<(u64, u64)>>,
+    // Hashes the contents as they're unpacked, leaving the result in `digest`
+    pub hasher: Option<Box<dyn EntryHasher>>,
+    pub digest: Option<Vec<u8>>,
+}
+
+/// A read-only view of the lower-level state of an `Entry`.
+///
+/// This is returned by `Entry::metadata` and exposes the raw data the crate
+/// uses to interpret an entry, such as the contents of the extension entries
+/// which preceded it, for tools which need more than the higher-level
+/// accessors on `Entry` provide.
+pub struct EntryMetadata<'b> {
+    header: &'b Header,
+    size: u64,
+    position: u64,
+    header_pos: u64,
+    file_pos: u64,
+    long_pathname: Option<&'b [u8]>,
+    long_linkname: Option<&'b [u8]>,
+    pax_extensions: Option<&'b [u8]>,
 }
 
 pub enum EntryIo<'a> {
     Pad(io::Take<io::Repeat>),
     Data(io::Take<&'a ArchiveInner<dyn Read + 'a>>),
+    // The number of raw bytes left is shared with the `EntryData` being
+    // decompressed so the position in the entry can still be reported.
+    Decoded(Box<dyn Read + 'a>, Rc<Cell<u64>>),
+}
+
+/// The raw contents of an entry, handed to a decompressor registered with
+/// `Archive::register_decompressor`.
+pub struct EntryData<'a> {
+    data: Vec<EntryIo<'a>>,
+    remaining: Rc<Cell<u64>>,
+    allow_truncated: bool,
+    truncated: Rc<Cell<bool>>,
 }
 
 /// When unpacking items the unpacked thing is returned to allow custom
@@ -64,7 +142,8 @@ impl<'a, R: Read> Entry<'a, R> {
     /// Returns the path name for this entry.
     ///
     /// This method may fail if the pathname is not valid Unicode and this is
-    /// called on a Windows platform.
+    /// called on a Windows platform, or on any platform with
+    /// `PathEncoding::Utf8`, see `Archive::set_path_encoding`.
     ///
     /// Note that this function will convert any `\` characters to directory
     /// separators, and it will not always return the same value as
@@ -87,6 +166,29 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.path_bytes()
     }
 
+    /// Returns the path of this entry in a form that's safe to join onto a
+    /// destination directory.
+    ///
+    /// This applies the same rules as `unpack_in`: root, prefix and `.`
+    /// components are dropped, and `None` is returned if the path contains a
+    /// `..` component, unless `ParentDirPolicy::Strip` is used in which case
+    /// they're dropped too. On Windows, names which aren't valid there are
+    /// handled according to the `WindowsNamePolicy`. `None` is also returned
+    /// if the path can't be decoded, see `path`, or if unpacking the entry
+    /// would fail because of its path. Note that the returned path may be
+    /// empty, for example if the path of the entry is just `/`.
+    ///
+    /// Like `path` this takes GNU long names and pax extensions into account.
+    pub fn sanitized_relative_path(&self) -> Option<PathBuf> {
+        self.fields.sanitized_relative_path()
+    }
+
+    /// Like `sanitized_relative_path`, but returns the error `unpack_in`
+    /// would fail with instead of `None`.
+    pub(crate) fn relative_unpack_path(&self) -> io::Result<Option<PathBuf>> {
+        self.fields.relative_unpack_path()
+    }
+
     /// Returns the link name for this entry, if any is found.
     ///
     /// This method may fail if the pathname is not valid Unicode and this is
@@ -126,8 +228,15 @@ impl<'a, R: Read> Entry<'a, R> {
     /// contains extensions, or if there were no previous extensions describing
     /// it.
     ///
-    /// Note that global pax extensions are intended to be applied to all
-    /// archive entries.
+    /// Records are yielded verbatim regardless of their keyword, so records in
+    /// namespaces this crate doesn't interpret (such as `security.*` or
+    /// `realtime.*`) aren't lost.
+    ///
+    /// Records of the global pax extensions which preceded this entry in the
+    /// archive are included ahead of the entry's own records, as global
+    /// extensions apply to all following entries. The `path`, `linkpath` and
+    /// `mtime` records are taken into account by `path`, `link_name` and when
+    /// unpacking.
     ///
     /// Also note that this function will read the entire entry if the entry
     /// itself is a list of extensions.
@@ -142,6 +251,48 @@ impl<'a, R: Read> Entry<'a, R> {
         &self.fields.header
     }
 
+    /// Returns mutable access to the header of this entry.
+    ///
+    /// This allows rewriting fields of the header, such as the mtime or
+    /// owner, before handing the entry to `Builder::append`, which fixes up
+    /// the checksum. How the entry is read is decided when it's first
+    /// returned from the archive, so changing the header has no effect on
+    /// its contents, including their size, and `path` and `link_name` still
+    /// prefer names from extension entries. `header_raw_bytes` keeps
+    /// returning the header as it was read.
+    pub fn header_mut(&mut self) -> &mut Header {
+        if self.fields.raw_header.is_none() {
+            self.fields.raw_header = Some(Box::new(*self.fields.header.as_bytes()));
+        }
+        &mut self.fields.header
+    }
+
+    /// Returns the header block of this entry exactly as it was read from the
+    /// archive.
+    ///
+    /// This is the same as the bytes of `header` unless the header was
+    /// repaired because of `Archive::set_lenient_numeric_fields` or changed
+    /// through `header_mut`. Together
+    /// with `Builder::append_raw_header` this allows an entry to be copied
+    /// into another archive byte for byte. Note that only the header of the
+    /// entry itself is returned, any extension entries which preceded it are
+    /// available through `metadata`.
+    pub fn header_raw_bytes(&self) -> &[u8; 512] {
+        match self.fields.raw_header {
+            Some(ref raw) => raw,
+            None => self.fields.header.as_bytes(),
+        }
+    }
+
+    /// Returns the type of file this entry describes.
+    ///
+    /// This is derived from the entry type in the header, but also takes into
+    /// account conventions such as old-style archives marking directories
+    /// with a trailing slash in their name.
+    pub fn file_type(&self) -> FileType {
+        self.fields.file_type()
+    }
+
     /// Returns access to the size of this entry in the archive.
     ///
     /// In the event the size is stored in a pax extension, that size value
@@ -150,12 +301,29 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.size
     }
 
+    /// Returns the modification time of this entry.
+    ///
+    /// Unlike `Header::mtime`, which only has whole seconds, this takes a pax
+    /// `mtime` record into account, including any fraction of a second it
+    /// has.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        let (secs, nanos) = self.fields.mtime_nanos()?;
+        UNIX_EPOCH
+            .checked_add(Duration::new(secs, nanos))
+            .ok_or_else(|| other(&format!("modification time {} is out of range", secs)))
+    }
+
     /// Returns the starting position, in bytes, of the header of this entry in
     /// the archive.
     ///
     /// The header is always a contiguous section of 512 bytes, so if the
     /// underlying reader implements `Seek`, then the slice from `header_pos` to
     /// `header_pos + 512` contains the raw header bytes.
+    ///
+    /// Any GNU long name or PAX extension entries describing this entry come
+    /// before this position. To seek back to an entry later, including those,
+    /// build an index of the archive with `Archive::index` and read entries
+    /// from it with `Archive::entry_at`.
     pub fn raw_header_position(&self) -> u64 {
         self.fields.header_pos
     }
@@ -170,6 +338,102 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.file_pos
     }
 
+    /// Returns whether the checksum of this entry's header didn't match its
+    /// contents.
+    ///
+    /// This can only be `true` if the archive was configured to read such
+    /// entries anyway, see `Archive::set_checksum_policy`.
+    pub fn has_bad_checksum(&self) -> bool {
+        self.fields.bad_checksum
+    }
+
+    /// Returns whether this entry is a GNU sparse file.
+    ///
+    /// The contents of such an entry only contain the blocks of data listed
+    /// by `sparse_map`, the holes in between are read back as zeros and
+    /// skipped over when unpacking.
+    pub fn is_sparse(&self) -> bool {
+        self.fields.sparse_map.is_some()
+    }
+
+    /// Returns the blocks of data stored for a sparse file as `(offset,
+    /// length)` pairs, in order of their offset in the file.
+    ///
+    /// `None` is returned for entries which aren't sparse files.
+    pub fn sparse_map(&self) -> Option<&[(u64, u64)]> {
+        self.fields.sparse_map.as_deref()
+    }
+
+    /// Borrows the contents of this entry from the slice the archive is
+    /// reading from, without copying them.
+    ///
+    /// This is only possible for archives created with `Archive::from_slice`,
+    /// `None` is returned for all other archives. It's also `None` for sparse
+    /// entries and entries read through a decompressor, whose contents aren't
+    /// stored contiguously in the archive. Reading from the entry is
+    /// unaffected by this.
+    pub fn data_slice(&self) -> Option<&'a [u8]> {
+        self.fields.contents
+    }
+
+    /// Reads the rest of the contents of this entry into a new vector.
+    ///
+    /// The vector starts out with room for the contents, up to 128 KiB, and
+    /// grows as larger contents are actually read, so a header declaring a
+    /// huge size can't make it allocate more than the archive holds. An
+    /// error is returned if the archive ends before all of the contents have
+    /// been read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    ///
+    /// for file in ar.entries().unwrap() {
+    ///     let mut file = file.unwrap();
+    ///     let contents = file.contents().unwrap();
+    ///     println!("{} bytes", contents.len());
+    /// }
+    /// ```
+    pub fn contents(&mut self) -> io::Result<Vec<u8>> {
+        self.fields.contents()
+    }
+
+    /// Returns whether the archive ended before all of the contents of this
+    /// entry could be read.
+    ///
+    /// This is only known once the contents have been read, either directly
+    /// or by unpacking the entry, and truncated archives are only read this
+    /// far if `Archive::set_allow_truncated` is enabled.
+    pub fn was_truncated(&self) -> bool {
+        self.fields.truncated.get()
+    }
+
+    /// Returns the fraction of the archive, between 0 and 1, which will have
+    /// been read once the contents of this entry have been read.
+    ///
+    /// This is computed from `raw_file_position` and `size`, and requires the
+    /// length of the archive to have been declared with
+    /// `Archive::new_with_length`, otherwise `None` is returned.
+    pub fn progress(&self) -> Option<f64> {
+        let total = self.fields.total_len?;
+        if total == 0 {
+            return Some(1.0);
+        }
+        let done = self.fields.file_pos + self.fields.size;
+        Some((done as f64 / total as f64).min(1.0))
+    }
+
+    /// Returns a view of the lower-level state of this entry.
+    ///
+    /// See `EntryMetadata` for more information.
+    pub fn metadata(&self) -> EntryMetadata<'_> {
+        self.fields.metadata()
+    }
+
     /// Writes this file to the specified location.
     ///
     /// This function will write the entire contents of this file into the
@@ -200,7 +464,7 @@ impl<'a, R: Read> Entry<'a, R> {
     /// }
     /// ```
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<Unpacked> {
-        self.fields.unpack(None, dst.as_ref())
+        self.fields.unpack(None, None, dst.as_ref())
     }
 
     /// Extracts this file under the specified path, avoiding security issues.
@@ -212,7 +476,8 @@ impl<'a, R: Read> Entry<'a, R> {
     /// `dst` will be overwritten.
     ///
     /// This function carefully avoids writing outside of `dst`. If the file has
-    /// a '..' in its path, this function will skip it and return false.
+    /// a '..' in its path, this function will skip it and return false, see
+    /// `Archive::set_parentdir_policy`.
     ///
     /// # Examples
     ///
@@ -231,6 +496,33 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_in(dst.as_ref())
     }
 
+    /// Extracts this file under the specified path like `unpack_in`,
+    /// returning the path it was unpacked at.
+    ///
+    /// The path is `dst` joined with the path of the entry after root, prefix
+    /// and `.` components are removed, which is what `Archive::unpack` does
+    /// with every entry. `None` is returned if the entry was skipped, for
+    /// example because its path contains a '..' component.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    ///
+    /// for file in ar.entries().unwrap() {
+    ///     let mut file = file.unwrap();
+    ///     if let Some(path) = file.unpack_in_path("target").unwrap() {
+    ///         println!("unpacked {}", path.display());
+    ///     }
+    /// }
+    /// ```
+    pub fn unpack_in_path<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<Option<PathBuf>> {
+        self.fields.unpack_in_path(dst.as_ref())
+    }
+
     /// Indicate whether extended file attributes (xattrs on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -251,13 +543,145 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.preserve_permissions = preserve;
     }
 
-    /// Indicate whether access time information is preserved when unpacking
-    /// this entry.
+    /// Indicate whether the owner and group of this entry are restored when
+    /// unpacking it, see `Archive::set_preserve_ownerships`.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_ownerships(&mut self, preserve: bool) {
+        self.fields.preserve_ownerships = preserve;
+    }
+
+    /// Indicate whether only the numeric uid and gid of this entry are used
+    /// when restoring its ownership, see `Archive::set_numeric_owner`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_numeric_owner(&mut self, numeric: bool) {
+        self.fields.numeric_owner = numeric;
+    }
+
+    /// Indicate whether the modification time of this entry is set on the
+    /// file or directory it's unpacked to.
     ///
     /// This flag is enabled by default.
     pub fn set_preserve_mtime(&mut self, preserve: bool) {
         self.fields.preserve_mtime = preserve;
     }
+
+    /// Configures how this entry is handled when unpacking if its type is
+    /// unknown or unsupported.
+    ///
+    /// This defaults to `UnknownTypePolicy::Skip`.
+    pub fn set_unknown_type_policy(&mut self, policy: UnknownTypePolicy) {
+        self.fields.unknown_type_policy = policy;
+    }
+
+    /// Restricts the types of entries this entry may be unpacked as, see
+    /// `Archive::set_allowed_entry_types`.
+    pub fn set_allowed_entry_types(&mut self, types: &[EntryType]) {
+        self.fields.allowed_entry_types = Some(EntryTypeSet::new(types));
+    }
+
+    /// Indicate whether this entry is skipped if its path already exists, see
+    /// `Archive::set_skip_existing`.
+    ///
+    /// This only has an effect when overwriting is disabled for the archive.
+    /// This flag is disabled by default.
+    pub fn set_skip_existing(&mut self, skip: bool) {
+        self.fields.skip_existing = skip;
+    }
+
+    /// Indicate whether this entry is skipped rather than failing to unpack if
+    /// its type isn't allowed, see `Archive::set_skip_disallowed_types`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_skip_disallowed_types(&mut self, skip: bool) {
+        self.fields.skip_disallowed_types = skip;
+    }
+
+    /// Configures what happens when unpacking this entry as a hard link fails.
+    ///
+    /// This defaults to `HardlinkFallback::Error`.
+    pub fn set_hardlink_fallback(&mut self, fallback: HardlinkFallback) {
+        self.fields.hardlink_fallback = fallback;
+    }
+
+    /// Indicate whether space for the contents of this entry is reserved
+    /// before it's written when unpacking it, see
+    /// `Archive::set_unpack_preallocate`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_unpack_preallocate(&mut self, preallocate: bool) {
+        self.fields.unpack_preallocate = preallocate;
+    }
+
+    /// Indicate whether this entry is recreated when unpacking it if it's a
+    /// character device, block device or FIFO, see
+    /// `Archive::set_unpack_special_files`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_unpack_special_files(&mut self, unpack: bool) {
+        self.fields.unpack_special_files = unpack;
+    }
+
+    /// Sets the mode this entry is unpacked with if it's a file whose
+    /// archived mode has no permission bits set, see
+    /// `Archive::set_default_file_mode`.
+    pub fn set_default_file_mode(&mut self, mode: u32) {
+        self.fields.default_file_mode = Some(mode);
+    }
+
+    /// Sets the mode this entry is unpacked with if it's a directory whose
+    /// archived mode has no permission bits set, see
+    /// `Archive::set_default_dir_mode`.
+    pub fn set_default_dir_mode(&mut self, mode: u32) {
+        self.fields.default_dir_mode = Some(mode);
+    }
+
+    /// Sets permission bits which are cleared from the mode this entry is
+    /// unpacked with, see `Archive::set_mask`.
+    pub fn set_mask(&mut self, mask: u32) {
+        self.fields.mask = mask;
+    }
+
+    /// Indicate whether the kernel is used to ensure this entry is only
+    /// written inside of the destination directory by `unpack_in`, see
+    /// `Archive::set_secure_extraction`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_secure_extraction(&mut self, secure: bool) {
+        self.fields.secure_extraction = secure;
+    }
+
+    /// Configures what happens when this entry's path isn't a valid file name
+    /// on Windows when unpacking it with `unpack_in`.
+    ///
+    /// This defaults to `WindowsNamePolicy::Error`.
+    pub fn set_windows_name_policy(&mut self, policy: WindowsNamePolicy) {
+        self.fields.windows_name_policy = policy;
+    }
+
+    /// Configures how the path name and link name of this entry are decoded,
+    /// see `Archive::set_path_encoding`.
+    ///
+    /// This defaults to `PathEncoding::Native`.
+    pub fn set_path_encoding(&mut self, encoding: PathEncoding) {
+        self.fields.path_encoding = encoding;
+    }
+
+    /// Configures what happens when this entry's path contains a `..`
+    /// component when unpacking it with `unpack_in`.
+    ///
+    /// This defaults to `ParentDirPolicy::Skip`.
+    pub fn set_parentdir_policy(&mut self, policy: ParentDirPolicy) {
+        self.fields.parentdir_policy = policy;
+    }
+
+    /// Limits the size of the contents of this entry when unpacking it or
+    /// reading it with `contents`, see `Archive::set_max_entry_size`.
+    pub fn set_max_entry_size(&mut self, max_size: u64) {
+        self.fields.max_entry_size = Some(max_size);
+    }
 }
 
 impl<'a, R: Read> Read for Entry<'a, R> {
@@ -266,6 +690,85 @@ impl<'a, R: Read> Read for Entry<'a, R> {
     }
 }
 
+/// Seeks within the contents of the entry.
+///
+/// This is only possible for entries read with `Archive::entries_with_seek`,
+/// `Archive::entry_at` or `Archive::entry_by_path`, others return an error.
+/// Positions are relative to the contents of the entry, so seeking to the
+/// start goes to its first byte rather than the start of the archive, and
+/// seeking past the end is clamped to the end. Sparse entries can be seeked
+/// within as well, but entries read through a decompressor can't.
+impl<'a, R: Read + Seek> Seek for Entry<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.fields.seek(pos)
+    }
+}
+
+impl<'b> EntryMetadata<'b> {
+    /// Returns the header of this entry, see `Entry::header`.
+    pub fn header(&self) -> &'b Header {
+        self.header
+    }
+
+    /// Returns the size of this entry, see `Entry::size`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the number of bytes of this entry's contents which have been
+    /// read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the starting position of the header of this entry in the
+    /// archive, see `Entry::raw_header_position`.
+    pub fn raw_header_position(&self) -> u64 {
+        self.header_pos
+    }
+
+    /// Returns the starting position of the file of this entry in the
+    /// archive, see `Entry::raw_file_position`.
+    pub fn raw_file_position(&self) -> u64 {
+        self.file_pos
+    }
+
+    /// Returns the raw contents of the GNU long name entry which preceded this
+    /// entry, if any.
+    ///
+    /// Unlike `Entry::path_bytes` this may include a trailing nul byte.
+    pub fn long_pathname(&self) -> Option<&'b [u8]> {
+        self.long_pathname
+    }
+
+    /// Returns the raw contents of the GNU long link name entry which preceded
+    /// this entry, if any.
+    ///
+    /// Unlike `Entry::link_name_bytes` this may include a trailing nul byte.
+    pub fn long_linkname(&self) -> Option<&'b [u8]> {
+        self.long_linkname
+    }
+
+    /// Returns the raw contents of the pax extensions entry which preceded
+    /// this entry, if any.
+    ///
+    /// Records from global pax extensions earlier in the archive come first,
+    /// followed by those of the entry's own extensions.
+    ///
+    /// Unlike `Entry::pax_extensions` this never reads from the archive, so
+    /// if this entry is itself a pax extensions entry its own contents are
+    /// only returned once they have been read.
+    pub fn pax_extensions_bytes(&self) -> Option<&'b [u8]> {
+        self.pax_extensions
+    }
+
+    /// Returns an iterator over the pax extensions in
+    /// `pax_extensions_bytes`, if any.
+    pub fn pax_extensions(&self) -> Option<PaxExtensions<'b>> {
+        self.pax_extensions.map(pax_extensions)
+    }
+}
+
 impl<'a> EntryFields<'a> {
     pub fn from<R: Read>(entry: Entry<R>) -> EntryFields {
         entry.fields
@@ -278,6 +781,50 @@ impl<'a> EntryFields<'a> {
         }
     }
 
+    fn metadata(&self) -> EntryMetadata<'_> {
+        let remaining = remaining(&self.data);
+        EntryMetadata {
+            header: &self.header,
+            size: self.size,
+            position: self.size.saturating_sub(remaining),
+            header_pos: self.header_pos,
+            file_pos: self.file_pos,
+            long_pathname: self.long_pathname.as_ref().map(|v| &v[..]),
+            long_linkname: self.long_linkname.as_ref().map(|v| &v[..]),
+            pax_extensions: self.pax_extensions.as_ref().map(|v| &v[..]),
+        }
+    }
+
+    pub fn contents(&mut self) -> io::Result<Vec<u8>> {
+        self.check_size()?;
+        // The size comes from the header, so only preallocate a bit and let
+        // the buffer grow as the contents are actually read
+        let cap = cmp::min(remaining(&self.data), 128 * 1024);
+        let mut v = Vec::with_capacity(cap as usize);
+        let limit = self.decoded_limit();
+        let read = match limit {
+            Some(max) => Read::take(&mut *self, max.saturating_add(1)).read_to_end(&mut v),
+            None => self.read_to_end(&mut v),
+        };
+        self.decoded_len = v.len() as u64;
+        self.check_decoded_len()?;
+        let eof = match read {
+            Ok(_) => self.truncated.get(),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => true,
+            Err(e) => return Err(e),
+        };
+        if eof {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "archive ended before the end of the contents of `{}`",
+                    self.path_lossy()
+                ),
+            ));
+        }
+        Ok(v)
+    }
+
     pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
         // Preallocate some data but don't let ourselves get too crazy now.
         let cap = cmp::min(self.size, 128 * 1024);
@@ -285,11 +832,89 @@ impl<'a> EntryFields<'a> {
         self.read_to_end(&mut v).map(|_| v)
     }
 
+    /// Seeks within the contents of the entry, see `Entry`'s `Seek`
+    /// implementation.
+    pub fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let archive = match self.seekable_archive {
+            Some(archive) => archive,
+            None => {
+                return Err(other(&format!(
+                    "can't seek in `{}` as it wasn't read from an archive \
+                     with `Archive::entries_with_seek` or `Archive::entry_at`",
+                    self.path_lossy()
+                )))
+            }
+        };
+        if self
+            .data
+            .iter()
+            .any(|io| matches!(*io, EntryIo::Decoded(..)))
+        {
+            return Err(other(&format!(
+                "can't seek in the decompressed contents of `{}`",
+                self.path_lossy()
+            )));
+        }
+
+        let cur = self.size - remaining(&self.data);
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.size.checked_add_signed(n),
+            SeekFrom::Current(n) => cur.checked_add_signed(n),
+        };
+        let target = match target {
+            Some(target) => cmp::min(target, self.size),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                ))
+            }
+        };
+
+        // The blocks of a sparse file are stored one after another in the
+        // archive, with the holes between them read back as zeros.
+        let whole = [(0, self.size)];
+        let blocks = match self.sparse_map {
+            Some(ref map) => &map[..],
+            None => &whole[..],
+        };
+        let mut data = Vec::new();
+        let mut start = None;
+        let mut pos = target;
+        let mut stored = 0;
+        for &(off, len) in blocks {
+            if off + len <= pos {
+                stored += len;
+                continue;
+            }
+            if pos < off {
+                data.push(EntryIo::Pad(io::repeat(0).take(off - pos)));
+                pos = off;
+            }
+            let skip = pos - off;
+            if start.is_none() {
+                start = Some(self.file_pos + stored + skip);
+            }
+            data.push(EntryIo::Data(archive.reader().take(len - skip)));
+            pos = off + len;
+            stored += len;
+        }
+        if pos < self.size {
+            data.push(EntryIo::Pad(io::repeat(0).take(self.size - pos)));
+        }
+        if let Some(start) = start {
+            archive.seek_to(start)?;
+        }
+        self.data = data;
+        Ok(target)
+    }
+
     fn path(&self) -> io::Result<Cow<Path>> {
-        bytes2path(self.path_bytes())
+        self.decode_path(self.path_bytes())
     }
 
-    fn path_bytes(&self) -> Cow<[u8]> {
+    pub fn path_bytes(&self) -> Cow<[u8]> {
         match self.long_pathname {
             Some(ref bytes) => {
                 if let Some(&0) = bytes.last() {
@@ -298,33 +923,43 @@ impl<'a> EntryFields<'a> {
                     Cow::Borrowed(bytes)
                 }
             }
-            None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = pax_extensions(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"path")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Cow::Borrowed(field);
-                    }
-                }
-                self.header.path_bytes()
-            }
+            None => match self.pax_record(b"path") {
+                Some(field) => Cow::Borrowed(field),
+                None => self.header.path_bytes(),
+            },
         }
     }
 
     /// Gets the path in a "lossy" way, used for error reporting ONLY.
-    fn path_lossy(&self) -> String {
+    pub fn path_lossy(&self) -> String {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
     fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
         match self.link_name_bytes() {
-            Some(bytes) => bytes2path(bytes).map(Some),
+            Some(bytes) => self.decode_path(bytes).map(Some),
             None => Ok(None),
         }
     }
 
+    /// Decodes a path name or link name of this entry according to the
+    /// configured `PathEncoding`.
+    fn decode_path<'b>(&self, bytes: Cow<'b, [u8]>) -> io::Result<Cow<'b, Path>> {
+        if str::from_utf8(&bytes).is_ok() {
+            return bytes2path(bytes);
+        }
+        match self.path_encoding {
+            PathEncoding::Utf8 => Err(other(&format!(
+                "path `{}` is not valid UTF-8",
+                String::from_utf8_lossy(&bytes)
+            ))),
+            PathEncoding::Lossy => Ok(Cow::Owned(PathBuf::from(
+                String::from_utf8_lossy(&bytes).into_owned(),
+            ))),
+            PathEncoding::Native | PathEncoding::__Nonexhaustive => bytes2path(bytes),
+        }
+    }
+
     fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
         match self.long_linkname {
             Some(ref bytes) => {
@@ -334,10 +969,86 @@ impl<'a> EntryFields<'a> {
                     Some(Cow::Borrowed(bytes))
                 }
             }
-            None => self.header.link_name_bytes(),
+            None => match self.pax_record(b"linkpath") {
+                Some(field) => Some(Cow::Borrowed(field)),
+                None => self.header.link_name_bytes(),
+            },
         }
     }
 
+    /// Returns the value of the last pax record for `key` which applies to
+    /// this entry, so local records override global ones.
+    fn pax_record(&self, key: &[u8]) -> Option<&[u8]> {
+        let pax = self.pax_extensions.as_ref()?;
+        pax_extensions(pax)
+            .filter_map(|f| f.ok())
+            .filter(|f| f.key_bytes() == key)
+            .last()
+            .map(|f| f.value_bytes())
+    }
+
+    /// Returns the modification time of this entry as whole seconds and the
+    /// nanoseconds past them, taking a pax `mtime` record into account.
+    pub fn mtime_nanos(&self) -> io::Result<(u64, u32)> {
+        let pax = self.pax_record(b"mtime").and_then(|v| {
+            let mut parts = v.splitn(2, |b| *b == b'.');
+            let secs = pax_number(parts.next()?).ok()?;
+            let nanos = parts.next().and_then(|f| pax_nanos(f).ok());
+            Some((secs, nanos.unwrap_or(0)))
+        });
+        match pax {
+            Some(mtime) => Ok(mtime),
+            None => Ok((self.header.mtime()?, 0)),
+        }
+    }
+
+    /// Returns the uid and gid this entry is owned by once unpacked, see
+    /// `Archive::set_preserve_ownerships`.
+    fn owner(&self) -> io::Result<(u64, u64)> {
+        let uid = match self.pax_record(b"uid") {
+            Some(uid) => pax_number(uid)?,
+            None => self.header.uid()?,
+        };
+        let gid = match self.pax_record(b"gid") {
+            Some(gid) => pax_number(gid)?,
+            None => self.header.gid()?,
+        };
+        if self.numeric_owner {
+            return Ok((uid, gid));
+        }
+        let uname = self
+            .pax_record(b"uname")
+            .or_else(|| self.header.username_bytes());
+        let gname = self
+            .pax_record(b"gname")
+            .or_else(|| self.header.groupname_bytes());
+        Ok((
+            uname
+                .filter(|name| !name.is_empty())
+                .and_then(lookup_uid)
+                .unwrap_or(uid),
+            gname
+                .filter(|name| !name.is_empty())
+                .and_then(lookup_gid)
+                .unwrap_or(gid),
+        ))
+    }
+
+    fn file_type(&self) -> FileType {
+        if self.is_old_style_dir() {
+            FileType::new(EntryType::Directory)
+        } else {
+            FileType::new(self.header.entry_type())
+        }
+    }
+
+    // Old BSD-tar compatibility.
+    // Names that have a trailing slash should be treated as a directory.
+    // Only applies to old headers.
+    fn is_old_style_dir(&self) -> bool {
+        self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/")
+    }
+
     fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions>> {
         if self.pax_extensions.is_none() {
             if !self.header.entry_type().is_pax_global_extensions()
@@ -350,7 +1061,14 @@ impl<'a> EntryFields<'a> {
         Ok(Some(pax_extensions(self.pax_extensions.as_ref().unwrap())))
     }
 
-    fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+    fn sanitized_relative_path(&self) -> Option<PathBuf> {
+        self.relative_unpack_path().ok().flatten()
+    }
+
+    /// Returns the path this entry is unpacked at relative to the
+    /// destination, or `None` if it's skipped, according to the configured
+    /// policies for `..` components and names which are invalid on Windows.
+    pub fn relative_unpack_path(&self) -> io::Result<Option<PathBuf>> {
         // Notes regarding bsdtar 2.8.3 / libarchive 2.8.3:
         // * Leading '/'s are trimmed. For example, `///test` is treated as
         //   `test`.
@@ -363,43 +1081,129 @@ impl<'a> EntryFields<'a> {
         //
         // Most of this is handled by the `path` module of the standard
         // library, but we specially handle a few cases here as well.
+        let mut relative = PathBuf::new();
+        let path = self.path().map_err(|e| {
+            TarError::new(
+                &format!("invalid path in entry header: {}", self.path_lossy()),
+                e,
+            )
+        })?;
+        for part in path.components() {
+            match part {
+                // Leading '/' characters, root paths, and '.'
+                // components are just ignored and treated as "empty
+                // components"
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+
+                // If any part of the filename is '..', then by default
+                // skip over unpacking the file to prevent directory traversal
+                // security issues.  See, e.g.: CVE-2001-1267,
+                // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
+                Component::ParentDir => match self.parentdir_policy {
+                    ParentDirPolicy::Strip => continue,
+                    ParentDirPolicy::Skip => return Ok(None),
+                    ParentDirPolicy::Error | ParentDirPolicy::__Nonexhaustive => {
+                        return Err(other(&format!(
+                            "`{}` contains a `..` component",
+                            path.display()
+                        )));
+                    }
+                },
+
+                Component::Normal(part) if cfg!(windows) && !is_valid_windows_name(part) => {
+                    match self.windows_name_policy {
+                        WindowsNamePolicy::Sanitize => relative.push(sanitize_windows_name(part)),
+                        WindowsNamePolicy::Skip => return Ok(None),
+                        WindowsNamePolicy::Error | WindowsNamePolicy::__Nonexhaustive => {
+                            return Err(other(&format!(
+                                "`{}` is not a valid file name on Windows",
+                                path.display()
+                            )));
+                        }
+                    }
+                }
 
-        let mut file_dst = dst.to_path_buf();
-        {
-            let path = self.path().map_err(|e| {
-                TarError::new(
-                    &format!("invalid path in entry header: {}", self.path_lossy()),
-                    e,
-                )
-            })?;
-            for part in path.components() {
-                match part {
-                    // Leading '/' characters, root paths, and '.'
-                    // components are just ignored and treated as "empty
-                    // components"
-                    Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
-
-                    // If any part of the filename is '..', then skip over
-                    // unpacking the file to prevent directory traversal
-                    // security issues.  See, e.g.: CVE-2001-1267,
-                    // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
-                    Component::ParentDir => return Ok(false),
-
-                    Component::Normal(part) => file_dst.push(part),
+                // Names which are valid but contain a `%` are escaped too, so
+                // they can't collide with a sanitized name
+                Component::Normal(part)
+                    if cfg!(windows)
+                        && self.windows_name_policy == WindowsNamePolicy::Sanitize
+                        && part.to_string_lossy().contains('%') =>
+                {
+                    relative.push(sanitize_windows_name(part))
                 }
+
+                Component::Normal(part) => relative.push(part),
             }
         }
+        Ok(Some(relative))
+    }
+
+    pub fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+        self.unpack_in_path(dst).map(|path| path.is_some())
+    }
+
+    /// Like `unpack_in`, returning where the entry was unpacked or `None` if
+    /// it was skipped.
+    pub fn unpack_in_path(&mut self, dst: &Path) -> io::Result<Option<PathBuf>> {
+        let root = if self.secure_extraction {
+            open_root(dst)?
+        } else {
+            None
+        };
+        self.unpack_in_root(dst, root.as_ref())
+    }
+
+    /// Like `unpack_in_path`, resolving paths beneath `root` if it's given,
+    /// which has to have been opened at `dst`.
+    pub fn unpack_in_root(
+        &mut self,
+        dst: &Path,
+        root: Option<&beneath::Root>,
+    ) -> io::Result<Option<PathBuf>> {
+        if !self.is_type_allowed()? {
+            return Ok(None);
+        }
+
+        let file_dst = match self.relative_unpack_path()? {
+            Some(path) => dst.join(path),
+            None => return Ok(None),
+        };
 
         // Skip cases where only slashes or '.' parts were seen, because
         // this is effectively an empty filename.
         if *dst == *file_dst {
-            return Ok(true);
+            return Ok(Some(file_dst));
+        }
+
+        if self.skips_existing(&file_dst) {
+            return Ok(None);
+        }
+
+        if self.secure_extraction {
+            if let Some(root) = root {
+                let target = file_dst
+                    .strip_prefix(dst)
+                    .map_err(|_| other("entry path is not inside the destination"))
+                    .and_then(|path| root.create_parents(path))
+                    .map_err(|e| {
+                        TarError::new(
+                            &format!("failed to create parents of `{}`", file_dst.display()),
+                            e,
+                        )
+                    })?;
+                self.unpack(Some(dst), Some((root, &target)), &target.path())
+                    .map_err(|e| {
+                        TarError::new(&format!("failed to unpack `{}`", file_dst.display()), e)
+                    })?;
+                return Ok(Some(file_dst));
+            }
         }
 
         // Skip entries without a parent (i.e. outside of FS root)
         let parent = match file_dst.parent() {
             Some(p) => p,
-            None => return Ok(false),
+            None => return Ok(None),
         };
 
         if parent.symlink_metadata().is_err() {
@@ -410,10 +1214,16 @@ impl<'a> EntryFields<'a> {
 
         let canon_target = self.validate_inside_dst(&dst, parent)?;
 
-        self.unpack(Some(&canon_target), &file_dst)
+        self.unpack(Some(&canon_target), None, &file_dst)
             .map_err(|e| TarError::new(&format!("failed to unpack `{}`", file_dst.display()), e))?;
 
-        Ok(true)
+        Ok(Some(file_dst))
+    }
+
+    /// Returns whether unpacking to `dst` is skipped as something already
+    /// exists there, see `Archive::set_skip_existing`.
+    fn skips_existing(&self, dst: &Path) -> bool {
+        !self.overwrite && self.skip_existing && dst.symlink_metadata().is_ok()
     }
 
     /// Unpack as destination directory `dst`.
@@ -433,14 +1243,62 @@ impl<'a> EntryFields<'a> {
         })
     }
 
+    /// Opens a directory which was just unpacked beneath a root, so its
+    /// metadata is set through the handle rather than its path.
+    ///
+    /// Its name could be an existing symlink pointing outside of the root,
+    /// which `unpack_dir` accepts, so that's an error here.
+    fn open_unpacked_dir(
+        &self,
+        beneath: Option<(&beneath::Root, &beneath::Resolved)>,
+    ) -> io::Result<Option<std::fs::File>> {
+        match beneath {
+            Some((_, resolved)) => resolved.open_dir().map(Some),
+            None => Ok(None),
+        }
+    }
+
     /// Returns access to the header of this entry in the archive.
-    fn unpack(&mut self, target_base: Option<&Path>, dst: &Path) -> io::Result<Unpacked> {
+    fn unpack(
+        &mut self,
+        target_base: Option<&Path>,
+        beneath: Option<(&beneath::Root, &beneath::Resolved)>,
+        dst: &Path,
+    ) -> io::Result<Unpacked> {
+        let root = beneath.map(|(root, _)| root);
+        if !self.is_type_allowed()? {
+            return Ok(Unpacked::__Nonexhaustive);
+        }
+        if self.skips_existing(dst) {
+            return Ok(Unpacked::__Nonexhaustive);
+        }
+        self.check_size()?;
         let kind = self.header.entry_type();
 
         if kind.is_dir() {
             self.unpack_dir(dst)?;
+            let mut dir = self.open_unpacked_dir(beneath)?;
+            if self.preserve_mtime {
+                if let Ok((secs, nanos)) = self.mtime_nanos() {
+                    let mtime = FileTime::from_unix_time(secs as i64, nanos);
+                    match dir {
+                        Some(ref dir) => {
+                            filetime::set_file_handle_times(dir, Some(mtime), Some(mtime))
+                        }
+                        None => filetime::set_symlink_file_times(dst, mtime, mtime),
+                    }
+                    .map_err(|e| {
+                        TarError::new(&format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
+                }
+            }
+            if self.preserve_ownerships {
+                let (uid, gid) = self.owner()?;
+                set_ownerships(dst, dir.as_ref(), uid, gid)?;
+            }
             if let Ok(mode) = self.header.mode() {
-                set_perms(dst, None, mode, self.preserve_permissions)?;
+                let mode = self.unpack_mode(mode, true);
+                set_perms(dst, dir.as_mut(), mode, self.preserve_permissions)?;
             }
             return Ok(Unpacked::__Nonexhaustive);
         } else if kind.is_hard_link() || kind.is_symlink() {
@@ -462,7 +1320,16 @@ impl<'a> EntryFields<'a> {
             }
 
             if kind.is_hard_link() {
-                let link_src = match target_base {
+                // Keeps the directory of the link source open while it's used
+                let resolved;
+                let link_src = match (target_base, root) {
+                    // With a root the kernel checks that the link source is
+                    // inside of it, symlinks included
+                    (_, Some(root)) => {
+                        root.check(&src)?;
+                        resolved = root.resolve(&src)?;
+                        resolved.path()
+                    }
                     // If we're unpacking within a directory then ensure that
                     // the destination of this hard link is both present and
                     // inside our own directory. This is needed because we want
@@ -474,25 +1341,58 @@ impl<'a> EntryFields<'a> {
                     // use canonicalization to ensure this guarantee. For hard
                     // links though they're canonicalized to their existing path
                     // so we need to validate at this time.
-                    Some(ref p) => {
-                        let link_src = p.join(src);
+                    (Some(ref p), None) => {
+                        let link_src = p.join(&src);
+                        if let Err(err) = link_src.symlink_metadata() {
+                            if err.kind() == ErrorKind::NotFound {
+                                return Err(Error::new(
+                                    err.kind(),
+                                    format!(
+                                        "hard link target `{}` of `{}` hasn't been unpacked, \
+                                         it needs to come before the link in the archive",
+                                        src.display(),
+                                        self.path_lossy()
+                                    ),
+                                ));
+                            }
+                        }
                         self.validate_inside_dst(p, &link_src)?;
                         link_src
                     }
-                    None => src.into_owned(),
+                    (None, None) => src.clone().into_owned(),
                 };
-                fs::hard_link(&link_src, dst).map_err(|err| {
-                    Error::new(
-                        err.kind(),
-                        format!(
-                            "{} when hard linking {} to {}",
-                            err,
-                            link_src.display(),
-                            dst.display()
-                        ),
-                    )
-                })?;
+                fs::hard_link(&link_src, dst)
+                    .or_else(|err| match self.hardlink_fallback {
+                        // Only fall back when the filesystem can't create the
+                        // link, a missing target or an existing file is still
+                        // an error
+                        _ if !hardlink_unsupported(&err) => Err(err),
+                        HardlinkFallback::Symlink => {
+                            let target =
+                                self.hardlink_symlink_target(&src, &link_src, target_base)?;
+                            symlink(&target, dst)
+                        }
+                        HardlinkFallback::Copy => fs::copy(&link_src, dst).map(|_| ()),
+                        HardlinkFallback::Error | HardlinkFallback::__Nonexhaustive => Err(err),
+                    })
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when hard linking {} to {}",
+                                err,
+                                link_src.display(),
+                                dst.display()
+                            ),
+                        )
+                    })?;
             } else {
+                // Unlike entry paths, symlink targets are created verbatim,
+                // including any `..` components. A relative target such as
+                // `../lib/libfoo.so` is perfectly legitimate, and a target
+                // pointing outside of `dst` can't be used to write outside of
+                // it as `unpack_in` validates the canonicalized parent of
+                // every entry before unpacking it.
                 symlink(&src, dst)
                     .or_else(|err_io| {
                         if err_io.kind() == io::ErrorKind::AlreadyExists && self.overwrite {
@@ -513,6 +1413,10 @@ impl<'a> EntryFields<'a> {
                             ),
                         )
                     })?;
+                if self.preserve_ownerships {
+                    let (uid, gid) = self.owner()?;
+                    set_ownerships(dst, None, uid, gid)?;
+                }
             };
             return Ok(Unpacked::__Nonexhaustive);
 
@@ -539,13 +1443,62 @@ impl<'a> EntryFields<'a> {
             return Ok(Unpacked::__Nonexhaustive);
         };
 
-        // Old BSD-tar compatibility.
-        // Names that have a trailing slash should be treated as a directory.
-        // Only applies to old headers.
-        if self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/") {
+        if self.is_old_style_dir() {
             self.unpack_dir(dst)?;
+            let mut dir = self.open_unpacked_dir(beneath)?;
+            if self.preserve_ownerships {
+                let (uid, gid) = self.owner()?;
+                set_ownerships(dst, dir.as_ref(), uid, gid)?;
+            }
             if let Ok(mode) = self.header.mode() {
-                set_perms(dst, None, mode, self.preserve_permissions)?;
+                let mode = self.unpack_mode(mode, true);
+                set_perms(dst, dir.as_mut(), mode, self.preserve_permissions)?;
+            }
+            return Ok(Unpacked::__Nonexhaustive);
+        }
+
+        if self.unpack_special_files
+            && cfg!(unix)
+            && (kind.is_character_special() || kind.is_block_special() || kind.is_fifo())
+        {
+            let (major, minor) = if kind.is_fifo() {
+                (0, 0)
+            } else {
+                (
+                    self.header.device_major()?.unwrap_or(0),
+                    self.header.device_minor()?.unwrap_or(0),
+                )
+            };
+            let mode = self.header.mode().unwrap_or(0o644);
+            let mode = self.unpack_mode(mode, false);
+            mknod(dst, kind, major, minor)
+                .or_else(|err| {
+                    if err.kind() == ErrorKind::AlreadyExists && self.overwrite {
+                        fs::remove_file(dst)?;
+                        mknod(dst, kind, major, minor)
+                    } else {
+                        Err(err)
+                    }
+                })
+                .map_err(|e| {
+                    TarError::new(
+                        &format!("failed to create {:?} entry `{}`", kind, dst.display()),
+                        e,
+                    )
+                })?;
+            if self.preserve_ownerships {
+                let (uid, gid) = self.owner()?;
+                set_ownerships(dst, None, uid, gid)?;
+            }
+            set_perms(dst, None, mode, self.preserve_permissions)?;
+            if self.preserve_mtime {
+                if let Ok((secs, nanos)) = self.mtime_nanos() {
+                    let mtime = FileTime::from_unix_time(secs as i64, nanos);
+                    // Opening a FIFO to set its times would block
+                    filetime::set_symlink_file_times(dst, mtime, mtime).map_err(|e| {
+                        TarError::new(&format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
+                }
             }
             return Ok(Unpacked::__Nonexhaustive);
         }
@@ -556,8 +1509,23 @@ impl<'a> EntryFields<'a> {
         // > A POSIX-compliant implementation must treat any unrecognized
         // > typeflag value as a regular file.
         //
-        // As a result if we don't recognize the kind we just write out the file
-        // as we would normally.
+        // Writing out device nodes or FIFOs as regular files is rarely what's
+        // wanted though, so anything we can't create is handled according to
+        // the configured policy, one of which is to write out the file as we
+        // would normally.
+        if !kind.is_file() && !kind.is_contiguous() && !kind.is_gnu_sparse() {
+            match self.unknown_type_policy {
+                UnknownTypePolicy::Skip => return Ok(Unpacked::__Nonexhaustive),
+                UnknownTypePolicy::Error => {
+                    return Err(other(&format!(
+                        "unsupported entry type {:?} for `{}`",
+                        kind,
+                        self.path_lossy()
+                    )));
+                }
+                UnknownTypePolicy::ExtractAsFile | UnknownTypePolicy::__Nonexhaustive => {}
+            }
+        }
 
         // Ensure we write a new file rather than overwriting in-place which
         // is attackable; if an existing file is found unlink it.
@@ -578,15 +1546,54 @@ impl<'a> EntryFields<'a> {
                     Err(err)
                 }
             })?;
+            // The size of decompressed contents isn't known up front
+            let decoded = self
+                .data
+                .iter()
+                .any(|io| matches!(io, EntryIo::Decoded(..)));
+            if self.unpack_preallocate && self.size > 0 && !decoded {
+                preallocate(&f, self.size)?;
+            }
+            let mut hasher = self.hasher.take();
+            let decoded_limit = self.decoded_limit();
             for io in self.data.drain(..) {
+                let mut out = HashWriter {
+                    inner: &mut f,
+                    hasher: hasher.as_mut(),
+                };
                 match io {
                     EntryIo::Data(mut d) => {
                         let expected = d.limit();
-                        if io::copy(&mut d, &mut f)? != expected {
-                            return Err(other("failed to write entire file"));
+                        let written = io::copy(&mut d, &mut out)?;
+                        if written != expected {
+                            if !self.allow_truncated {
+                                return Err(Error::new(
+                                    ErrorKind::UnexpectedEof,
+                                    format!(
+                                        "failed to write entire file, only {} of {} bytes \
+                                         were left in the archive",
+                                        written, expected
+                                    ),
+                                ));
+                            }
+                            // Don't leave behind space reserved for the
+                            // contents which were never written
+                            self.truncated.set(true);
+                            let len = f.stream_position()?;
+                            f.set_len(len)?;
+                            break;
                         }
                     }
+                    EntryIo::Decoded(d, _) => {
+                        // Only the header's size was checked so far, so stop
+                        // just past the limit and fail below
+                        let limit = decoded_limit.map_or(u64::MAX, |max| max + 1);
+                        self.decoded_len += io::copy(&mut d.take(limit), &mut out)?;
+                    }
                     EntryIo::Pad(d) => {
+                        if let Some(ref mut hasher) = hasher {
+                            hash_zeros(&mut **hasher, d.limit());
+                        }
                         // TODO: checked cast to i64
                         let to = SeekFrom::Current(d.limit() as i64);
                         let size = f.seek(to)?;
@@ -594,6 +1601,8 @@ impl<'a> EntryFields<'a> {
                     }
                 }
             }
+            self.check_decoded_len()?;
+            self.digest = hasher.map(|h| h.finish());
             Ok(f)
         })()
         .map_err(|e| {
@@ -609,14 +1618,19 @@ impl<'a> EntryFields<'a> {
         })?;
 
         if self.preserve_mtime {
-            if let Ok(mtime) = self.header.mtime() {
-                let mtime = FileTime::from_unix_time(mtime as i64, 0);
+            if let Ok((secs, nanos)) = self.mtime_nanos() {
+                let mtime = FileTime::from_unix_time(secs as i64, nanos);
                 filetime::set_file_handle_times(&f, Some(mtime), Some(mtime)).map_err(|e| {
                     TarError::new(&format!("failed to set mtime for `{}`", dst.display()), e)
                 })?;
             }
         }
+        if self.preserve_ownerships {
+            let (uid, gid) = self.owner()?;
+            set_ownerships(dst, Some(&f), uid, gid)?;
+        }
         if let Ok(mode) = self.header.mode() {
+            let mode = self.unpack_mode(mode, false);
             set_perms(dst, Some(&mut f), mode, self.preserve_permissions)?;
         }
         if self.unpack_xattrs {
@@ -695,6 +1709,65 @@ impl<'a> EntryFields<'a> {
             Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
         }
 
+        fn set_ownerships(
+            dst: &Path,
+            f: Option<&std::fs::File>,
+            uid: u64,
+            gid: u64,
+        ) -> Result<(), TarError> {
+            _set_ownerships(dst, f, uid, gid).map_err(|e| {
+                TarError::new(
+                    &format!(
+                        "failed to set ownerships to uid={}, gid={} for `{}`",
+                        uid,
+                        gid,
+                        dst.display()
+                    ),
+                    e,
+                )
+            })
+        }
+
+        #[cfg(unix)]
+        fn _set_ownerships(
+            dst: &Path,
+            f: Option<&std::fs::File>,
+            uid: u64,
+            gid: u64,
+        ) -> io::Result<()> {
+            use std::convert::TryFrom;
+            use std::ffi::CString;
+            use std::os::unix::prelude::*;
+
+            let uid = libc::uid_t::try_from(uid)
+                .map_err(|_| other(&format!("uid {} is too large", uid)))?;
+            let gid = libc::gid_t::try_from(gid)
+                .map_err(|_| other(&format!("gid {} is too large", gid)))?;
+            let rc = match f {
+                Some(f) => unsafe { libc::fchown(f.as_raw_fd(), uid, gid) },
+                None => {
+                    let path = CString::new(dst.as_os_str().as_bytes())?;
+                    unsafe { libc::lchown(path.as_ptr(), uid, gid) }
+                }
+            };
+            if rc != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        // Windows has no numeric owners to restore
+        #[cfg(not(unix))]
+        #[allow(unused_variables)]
+        fn _set_ownerships(
+            dst: &Path,
+            f: Option<&std::fs::File>,
+            uid: u64,
+            gid: u64,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
         #[cfg(all(unix, feature = "xattr"))]
         fn set_xattrs(me: &mut EntryFields, dst: &Path) -> io::Result<()> {
             use std::ffi::OsStr;
@@ -741,6 +1814,162 @@ impl<'a> EntryFields<'a> {
         fn set_xattrs(_: &mut EntryFields, _: &Path) -> io::Result<()> {
             Ok(())
         }
+
+        #[cfg(target_os = "linux")]
+        fn preallocate(f: &std::fs::File, size: u64) -> io::Result<()> {
+            use std::convert::TryFrom;
+            use std::os::unix::prelude::*;
+
+            let len = match libc::off_t::try_from(size) {
+                Ok(len) => len,
+                Err(_) => return f.set_len(size),
+            };
+            match unsafe { libc::posix_fallocate(f.as_raw_fd(), 0, len) } {
+                0 => Ok(()),
+                // Not all filesystems support reserving space up front
+                libc::EOPNOTSUPP | libc::EINVAL => f.set_len(size),
+                err => {
+                    let err = io::Error::from_raw_os_error(err);
+                    Err(io::Error::new(
+                        err.kind(),
+                        format!("failed to preallocate {} bytes: {}", size, err),
+                    ))
+                }
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        fn preallocate(f: &std::fs::File, size: u64) -> io::Result<()> {
+            f.set_len(size)
+        }
+
+        #[cfg(unix)]
+        fn mknod(dst: &Path, kind: EntryType, major: u32, minor: u32) -> io::Result<()> {
+            use std::ffi::CString;
+            use std::os::unix::prelude::*;
+
+            let path = CString::new(dst.as_os_str().as_bytes())?;
+            let ty = if kind.is_character_special() {
+                libc::S_IFCHR
+            } else if kind.is_block_special() {
+                libc::S_IFBLK
+            } else {
+                libc::S_IFIFO
+            };
+            // The permissions are set afterwards, unaffected by the umask
+            let dev = libc::makedev(major as _, minor as _);
+            if unsafe { libc::mknod(path.as_ptr(), ty | 0o600, dev) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        #[allow(unused_variables)]
+        fn mknod(dst: &Path, kind: EntryType, major: u32, minor: u32) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+        }
+    }
+
+    /// Checks the size of this entry against the configured limit, see
+    /// `Archive::set_max_entry_size`.
+    fn check_size(&self) -> io::Result<()> {
+        match self.max_entry_size {
+            Some(max) if self.size > max => Err(other(&format!(
+                "`{}` is {} bytes, larger than the limit of {} bytes",
+                self.path_lossy(),
+                self.size,
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The most bytes the contents may decompress to, from
+    /// `Archive::set_max_entry_size` and whatever is left of a total limit.
+    fn decoded_limit(&self) -> Option<u64> {
+        match (self.max_entry_size, self.total_size_left) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn check_decoded_len(&self) -> io::Result<()> {
+        match self.decoded_limit() {
+            Some(max) if self.decoded_len > max => Err(other(&format!(
+                "`{}` decompresses to more than the limit of {} bytes",
+                self.path_lossy(),
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the type of this entry against the allowed entry types,
+    /// returning whether it should be unpacked.
+    fn is_type_allowed(&self) -> io::Result<bool> {
+        let kind = self.header.entry_type();
+        match self.allowed_entry_types {
+            Some(ref allowed) if !allowed.contains(kind) => {
+                if self.skip_disallowed_types {
+                    Ok(false)
+                } else {
+                    Err(other(&format!(
+                        "entry type {:?} of `{}` is not allowed",
+                        kind,
+                        self.path_lossy()
+                    )))
+                }
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Computes the mode to unpack with from the archived mode, replacing a
+    /// mode without any permission bits by the configured default, if any,
+    /// and clearing the bits of the configured mask.
+    fn unpack_mode(&self, mode: u32, is_dir: bool) -> u32 {
+        let default = if is_dir {
+            self.default_dir_mode
+        } else {
+            self.default_file_mode
+        };
+        let mode = match default {
+            Some(default) if mode & 0o7777 == 0 => default,
+            _ => mode,
+        };
+        mode & !self.mask
+    }
+
+    /// Computes where a symlink standing in for a hard link should point.
+    ///
+    /// When unpacking into a directory the link name of a hard link is
+    /// relative to the root of the archive, so it's rewritten to be relative
+    /// to the directory the link itself is placed in.
+    fn hardlink_symlink_target(
+        &self,
+        src: &Path,
+        link_src: &Path,
+        target_base: Option<&Path>,
+    ) -> io::Result<PathBuf> {
+        if target_base.is_none() || src.is_absolute() {
+            return Ok(link_src.to_path_buf());
+        }
+        let depth = self
+            .path()?
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count();
+        let mut target = PathBuf::new();
+        for _ in 1..depth {
+            target.push("..");
+        }
+        for part in src.components() {
+            if let Component::Normal(part) = part {
+                target.push(part);
+            }
+        }
+        Ok(target)
     }
 
     fn validate_inside_dst(&self, dst: &Path, file_dst: &Path) -> io::Result<PathBuf> {
@@ -772,16 +2001,234 @@ impl<'a> EntryFields<'a> {
     }
 }
 
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn is_reserved_windows_stem(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name);
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+fn is_invalid_windows_char(c: char) -> bool {
+    (c as u32) < 0x20 || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*')
+}
+
+fn is_valid_windows_name(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    !is_reserved_windows_stem(&name)
+        && !name.ends_with('.')
+        && !name.ends_with(' ')
+        && !name.chars().any(is_invalid_windows_char)
+}
+
+fn sanitize_windows_name(name: &OsStr) -> String {
+    let name = name.to_string_lossy();
+    let trimmed = name.trim_end_matches(&['.', ' '][..]);
+    let mut sanitized = String::new();
+    for c in trimmed.chars() {
+        if is_invalid_windows_char(c) || c == '%' {
+            sanitized.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            sanitized.push(c);
+        }
+    }
+    for c in name[trimmed.len()..].chars() {
+        sanitized.push_str(&format!("%{:02X}", c as u32));
+    }
+    // Every `%` in the result starts an escape, so encoding the last
+    // character of a reserved stem can't collide with another name, unlike
+    // appending something to it
+    if is_reserved_windows_stem(&sanitized) {
+        let stem_len = sanitized.find('.').unwrap_or(sanitized.len());
+        let last = sanitized.as_bytes()[stem_len - 1];
+        sanitized.replace_range(stem_len - 1..stem_len, &format!("%{:02X}", last));
+    }
+    sanitized
+}
+
 impl<'a> Read for EntryFields<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
-        loop {
-            match self.data.get_mut(0).map(|io| io.read(into)) {
-                Some(Ok(0)) => {
-                    self.data.remove(0);
+        read_data(&mut self.data, into, self.allow_truncated, &self.truncated)
+    }
+}
+
+impl<'a> EntryData<'a> {
+    /// Takes the contents of `entry` so they can be read through a
+    /// decompressor, returning the shared count of raw bytes left.
+    pub fn take(entry: &mut EntryFields<'a>) -> (EntryData<'a>, Rc<Cell<u64>>) {
+        let data = entry.data.drain(..).collect::<Vec<_>>();
+        let remaining = Rc::new(Cell::new(remaining(&data)));
+        let ret = EntryData {
+            data,
+            remaining: remaining.clone(),
+            allow_truncated: entry.allow_truncated,
+            truncated: entry.truncated.clone(),
+        };
+        (ret, remaining)
+    }
+}
+
+impl<'a> Read for EntryData<'a> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        let n = read_data(&mut self.data, into, self.allow_truncated, &self.truncated)?;
+        self.remaining.set(self.remaining.get() - n as u64);
+        Ok(n)
+    }
+}
+
+/// Looks up the uid of the user called `name` on this system.
+#[cfg(unix)]
+fn lookup_uid(name: &[u8]) -> Option<u64> {
+    use std::ffi::CString;
+    use std::{mem, ptr};
+
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1024 * 1024 {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        return Some(pwd.pw_uid.into());
+    }
+}
+
+/// Looks up the gid of the group called `name` on this system.
+#[cfg(unix)]
+fn lookup_gid(name: &[u8]) -> Option<u64> {
+    use std::ffi::CString;
+    use std::{mem, ptr};
+
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut grp: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1024 * 1024 {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        return Some(grp.gr_gid.into());
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_uid(_name: &[u8]) -> Option<u64> {
+    None
+}
+
+#[cfg(not(unix))]
+fn lookup_gid(_name: &[u8]) -> Option<u64> {
+    None
+}
+
+/// Opens `dst` to resolve the paths of entries beneath it, see
+/// `Archive::set_secure_extraction`.
+pub fn open_root(dst: &Path) -> io::Result<Option<beneath::Root>> {
+    beneath::Root::open(dst)
+        .map_err(|e| TarError::new(&format!("failed to open `{}`", dst.display()), e).into())
+}
+
+/// Returns whether `err` means that a hard link can't be created at all, as
+/// opposed to failing for a reason which a fallback wouldn't fix.
+#[cfg(unix)]
+fn hardlink_unsupported(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => {
+            code == libc::EPERM
+                || code == libc::EOPNOTSUPP
+                || code == libc::EXDEV
+                || code == libc::EMLINK
+        }
+        None => false,
+    }
+}
+
+#[cfg(windows)]
+fn hardlink_unsupported(err: &io::Error) -> bool {
+    // ERROR_INVALID_FUNCTION, ERROR_NOT_SAME_DEVICE, ERROR_NOT_SUPPORTED and
+    // ERROR_TOO_MANY_LINKS
+    matches!(
+        err.raw_os_error(),
+        Some(1) | Some(17) | Some(50) | Some(1142)
+    )
+}
+
+#[cfg(not(any(unix, windows)))]
+fn hardlink_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+}
+
+fn remaining(data: &[EntryIo<'_>]) -> u64 {
+    data.iter()
+        .map(|io| match *io {
+            EntryIo::Pad(ref io) => io.limit(),
+            EntryIo::Data(ref io) => io.limit(),
+            EntryIo::Decoded(_, ref remaining) => remaining.get(),
+        })
+        .sum()
+}
+
+fn read_data(
+    data: &mut Vec<EntryIo<'_>>,
+    into: &mut [u8],
+    allow_truncated: bool,
+    truncated: &Cell<bool>,
+) -> io::Result<usize> {
+    if into.is_empty() {
+        return Ok(0);
+    }
+    loop {
+        match data.get_mut(0).map(|io| io.read(into)) {
+            // The archive ended before the contents did, so there's nothing
+            // more to read for the rest of the entry either.
+            Some(Ok(0)) if matches!(data[0], EntryIo::Data(ref io) if io.limit() > 0) => {
+                if !allow_truncated {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "archive ended before the contents of the entry did",
+                    ));
                 }
-                Some(r) => return r,
-                None => return Ok(0),
+                truncated.set(true);
+                data.clear();
             }
+            Some(Ok(0)) => {
+                data.remove(0);
+            }
+            Some(r) => return r,
+            None => return Ok(0),
         }
     }
 }
@@ -791,6 +2238,37 @@ impl<'a> Read for EntryIo<'a> {
         match *self {
             EntryIo::Pad(ref mut io) => io.read(into),
             EntryIo::Data(ref mut io) => io.read(into),
+            EntryIo::Decoded(ref mut io, _) => io.read(into),
+        }
+    }
+}
+
+/// Writes to `inner`, feeding everything written to `hasher` as well.
+struct HashWriter<'h, W> {
+    inner: W,
+    hasher: Option<&'h mut Box<dyn EntryHasher>>,
+}
+
+impl<'h, W: Write> Write for HashWriter<'h, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..n]);
         }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Feeds `len` zeros to `hasher`, for the holes of sparse files.
+fn hash_zeros(hasher: &mut dyn EntryHasher, mut len: u64) {
+    let zeros = [0; 8 * 1024];
+    while len > 0 {
+        let n = cmp::min(len, zeros.len() as u64) as usize;
+        hasher.update(&zeros[..n]);
+        len -= n as u64;
     }
 }
@@ -47,6 +47,10 @@ impl EntryType {
     ///
     /// Note that the other named constructors of entry type may be more
     /// appropriate to create a file type from.
+    ///
+    /// Both `b'\0'` and `b'0'` map to `Regular`, so `as_byte` doesn't always
+    /// return the byte an entry type was created from. The exact byte of a
+    /// header is available through `Header::as_old`.
     pub fn new(byte: u8) -> EntryType {
         match byte {
             b'\x00' | b'0' => EntryType::Regular,
@@ -181,6 +185,18 @@ impl EntryType {
         self == &EntryType::GNULongLink
     }
 
+    /// Returns whether this type represents a GNU multi-volume continuation,
+    /// the part of a file which was split off into the next volume.
+    pub fn is_gnu_multivolume(&self) -> bool {
+        self.as_byte() == b'M'
+    }
+
+    /// Returns whether this type represents a GNU incremental dump directory,
+    /// which lists the contents of a directory at the time of the backup.
+    pub fn is_gnu_dumpdir(&self) -> bool {
+        self.as_byte() == b'D'
+    }
+
     /// Returns whether this type represents a GNU long name header.
     pub fn is_pax_global_extensions(&self) -> bool {
         self == &EntryType::XGlobalHeader
@@ -191,3 +207,67 @@ impl EntryType {
         self == &EntryType::XHeader
     }
 }
+
+/// The type of file an archive entry describes, in the terms used by
+/// `std::fs::FileType`.
+///
+/// This is returned by `Entry::file_type` and allows code which deals with
+/// both archive entries and files on disk to inspect them the same way.
+/// Entries which are neither regular files, directories, nor symlinks (such as
+/// hard links, devices and FIFOs) report `false` for all three queries, and
+/// the precise type can be found through `entry_type`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileType {
+    entry_type: EntryType,
+}
+
+impl FileType {
+    pub(crate) fn new(entry_type: EntryType) -> FileType {
+        FileType { entry_type }
+    }
+
+    /// Returns whether this file type is a regular file.
+    ///
+    /// Contiguous files and GNU sparse files are considered regular files.
+    pub fn is_file(&self) -> bool {
+        matches!(
+            self.entry_type,
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse
+        )
+    }
+
+    /// Returns whether this file type is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.entry_type.is_dir()
+    }
+
+    /// Returns whether this file type is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.entry_type.is_symlink()
+    }
+
+    /// Returns the archive entry type this file type was derived from.
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+}
+
+/// A set of entry types, keyed by their type flag.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EntryTypeSet([u64; 4]);
+
+impl EntryTypeSet {
+    pub(crate) fn new(types: &[EntryType]) -> EntryTypeSet {
+        let mut set = EntryTypeSet([0; 4]);
+        for ty in types {
+            let byte = ty.as_byte();
+            set.0[byte as usize / 64] |= 1 << (byte % 64);
+        }
+        set
+    }
+
+    pub(crate) fn contains(&self, ty: EntryType) -> bool {
+        let byte = ty.as_byte();
+        self.0[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+}
@@ -33,12 +33,35 @@ pub enum HeaderMode {
 
     /// Only metadata that is directly relevant to the identity of a file will
     /// be included. In particular, ownership and mod/access times are excluded.
+    ///
+    /// The modification time, uid and gid are zero, no user or group names
+    /// are recorded and the mode is normalized to `0o755` or `0o644`. Along
+    /// with `Builder::append_dir_all` adding directory contents in sorted
+    /// order, this makes archives built from the same tree byte-identical
+    /// across machines.
     Deterministic,
 
     #[doc(hidden)]
     __Nonexhaustive,
 }
 
+/// The format of a header, as identified by its magic and version fields.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderFormat {
+    /// The original tar format, or any header whose magic and version don't
+    /// match one of the formats below.
+    Old,
+
+    /// The POSIX UStar format, with magic `"ustar\0"` and version `"00"`.
+    Ustar,
+
+    /// The GNU format, with magic `"ustar "` and version `" \0"`.
+    Gnu,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
 /// Representation of the header of an entry in an archive
 #[repr(C)]
 #[allow(missing_docs)]
@@ -191,6 +214,41 @@ impl Header {
         ustar.magic[..] == b"ustar "[..] && ustar.version[..] == b" \0"[..]
     }
 
+    /// Returns the raw magic field of this header.
+    ///
+    /// This is `"ustar\0"` for UStar headers, `"ustar "` for GNU headers and
+    /// usually all zeros for old headers.
+    pub fn magic(&self) -> &[u8; 6] {
+        let ustar = unsafe { cast::<_, UstarHeader>(self) };
+        &ustar.magic
+    }
+
+    /// Returns the raw version field of this header.
+    ///
+    /// This is `"00"` for UStar headers, `" \0"` for GNU headers and usually
+    /// all zeros for old headers.
+    pub fn version(&self) -> &[u8; 2] {
+        let ustar = unsafe { cast::<_, UstarHeader>(self) };
+        &ustar.version
+    }
+
+    /// Classifies the format of this header based on its magic and version
+    /// fields.
+    ///
+    /// Both fields have to match exactly, as the UStar and GNU formats
+    /// interpret the bytes at the end of the header differently (the UStar
+    /// path prefix overlaps with the GNU atime/ctime fields). Headers that are
+    /// neither are treated as old headers.
+    pub fn format(&self) -> HeaderFormat {
+        if self.is_ustar() {
+            HeaderFormat::Ustar
+        } else if self.is_gnu() {
+            HeaderFormat::Gnu
+        } else {
+            HeaderFormat::Old
+        }
+    }
+
     /// View this archive header as a raw "old" archive header.
     ///
     /// This view will always succeed as all archive header formats will fill
@@ -258,13 +316,47 @@ impl Header {
 
     /// Treats the given byte slice as a header.
     ///
-    /// Panics if the length of the passed slice is not equal to 512.
+    /// Panics if the length of the passed slice is not equal to 512, see
+    /// `from_bytes` for a version returning an error instead.
     pub fn from_byte_slice(bytes: &[u8]) -> &Header {
         assert_eq!(bytes.len(), mem::size_of::<Header>());
         assert_eq!(mem::align_of_val(bytes), mem::align_of::<Header>());
         unsafe { &*(bytes.as_ptr() as *const Header) }
     }
 
+    /// Treats the given byte slice as a header, such as a block read from an
+    /// archive by a custom reader.
+    ///
+    /// Returns an error if the length of the slice is not 512. Headers are
+    /// plain bytes with no alignment requirements, so any slice of the right
+    /// length can be viewed as one. Nothing about the contents is validated,
+    /// which can be done with the accessors of the header, like comparing
+    /// `cksum` to a freshly computed checksum.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::Header;
+    ///
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(3);
+    /// header.set_cksum();
+    /// let block = header.as_bytes().to_vec();
+    ///
+    /// let parsed = Header::from_bytes(&block).unwrap();
+    /// assert_eq!(parsed.size().unwrap(), 3);
+    /// assert!(Header::from_bytes(&block[..100]).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<&Header> {
+        if bytes.len() != mem::size_of::<Header>() {
+            return Err(other(&format!(
+                "a header is 512 bytes long, not {} bytes",
+                bytes.len()
+            )));
+        }
+        Ok(Header::from_byte_slice(bytes))
+    }
+
     /// Returns a view into this header as a byte array.
     pub fn as_bytes(&self) -> &[u8; 512] {
         &self.bytes
@@ -287,6 +379,16 @@ impl Header {
 
     /// Sets only the metadata relevant to the given HeaderMode in this header
     /// from the metadata argument provided.
+    ///
+    /// The entry type is inferred from the file type, the size is taken from
+    /// the metadata for regular files and set to zero otherwise, and the
+    /// device numbers are cleared. With `HeaderMode::Complete` the mtime,
+    /// uid, gid and mode come from the metadata, while
+    /// `HeaderMode::Deterministic` zeroes the mtime and ownership and only
+    /// keeps whether the file is executable. On Windows, which has no file
+    /// modes or owners, the mode is approximated from the read-only attribute
+    /// and the ownership is always zero. The path and link name aren't
+    /// touched.
     pub fn set_metadata_in_mode(&mut self, meta: &fs::Metadata, mode: HeaderMode) {
         self.fill_from(meta, mode);
     }
@@ -358,6 +460,41 @@ impl Header {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
+    /// Rewrites the mode, uid, gid, size and mtime fields into octal if they
+    /// were written in decimal or hexadecimal by a non-conformant writer.
+    ///
+    /// Fields which are valid or can't be interpreted are left untouched. The
+    /// checksum is recomputed if anything changed, and whether anything did
+    /// is returned.
+    pub(crate) fn repair_numeric_fields(&mut self) -> bool {
+        let old = self.as_old_mut();
+        let mut changed = false;
+        for field in [
+            &mut old.mode[..],
+            &mut old.uid[..],
+            &mut old.gid[..],
+            &mut old.size[..],
+            &mut old.mtime[..],
+        ]
+        .iter_mut()
+        {
+            if field[0] & 0x80 != 0 || octal_from(field).is_ok() {
+                continue;
+            }
+            if let Some(n) = lenient_number_from(field) {
+                for b in field.iter_mut() {
+                    *b = 0;
+                }
+                num_field_wrapper_into(field, n);
+                changed = true;
+            }
+        }
+        if changed {
+            self.set_cksum();
+        }
+        changed
+    }
+
     /// Sets the path name for this header.
     ///
     /// This function will set the pathname listed in this header, encoding it
@@ -687,6 +824,27 @@ impl Header {
         octal_into(&mut self.as_old_mut().cksum, cksum);
     }
 
+    /// Compares this header with `other`, ignoring the checksum field.
+    ///
+    /// The checksum is derived from the rest of the header, but writers
+    /// format it differently, for example with a trailing space or nul byte,
+    /// or with different amounts of leading zeros. This returns whether the
+    /// two headers have the same raw bytes everywhere but the checksum field,
+    /// regardless of whether either checksum is valid.
+    pub fn eq_ignoring_checksum(&self, other: &Header) -> bool {
+        self.bytes[..148] == other.bytes[..148] && self.bytes[156..] == other.bytes[156..]
+    }
+
+    /// Returns whether the checksum field doesn't match the rest of the
+    /// header, for example because a field was changed after `set_cksum` was
+    /// last called.
+    pub(crate) fn cksum_is_stale(&self) -> bool {
+        match self.cksum() {
+            Ok(cksum) => cksum != self.calculate_cksum(),
+            Err(_) => true,
+        }
+    }
+
     fn calculate_cksum(&self) -> u32 {
         let old = self.as_old();
         let start = old as *const _ as usize;
@@ -865,6 +1023,140 @@ impl Header {
     }
 }
 
+/// A builder for constructing a `Header` with chained method calls.
+///
+/// Each method sets a single field of the header. Any errors encountered while
+/// setting fields, such as a path which is too long or a value which doesn't
+/// fit in its field, are deferred until `build` is called, which returns the
+/// first of them. Otherwise `build` returns the header with its checksum set,
+/// ready to be passed to `Builder::append`.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{EntryType, HeaderBuilder};
+///
+/// let header = HeaderBuilder::new_gnu()
+///     .path("foo/bar.txt")
+///     .mode(0o644)
+///     .size(4)
+///     .entry_type(EntryType::Regular)
+///     .build()
+///     .unwrap();
+/// assert_eq!(header.size().unwrap(), 4);
+/// ```
+pub struct HeaderBuilder {
+    header: Header,
+    err: Option<io::Error>,
+}
+
+impl HeaderBuilder {
+    /// Creates a new builder for a blank GNU header.
+    pub fn new_gnu() -> HeaderBuilder {
+        HeaderBuilder::from_header(Header::new_gnu())
+    }
+
+    /// Creates a new builder for a blank UStar header.
+    pub fn new_ustar() -> HeaderBuilder {
+        HeaderBuilder::from_header(Header::new_ustar())
+    }
+
+    /// Creates a new builder for a blank old header.
+    pub fn new_old() -> HeaderBuilder {
+        HeaderBuilder::from_header(Header::new_old())
+    }
+
+    /// Creates a new builder which starts out with the fields of `header`.
+    pub fn from_header(header: Header) -> HeaderBuilder {
+        HeaderBuilder { header, err: None }
+    }
+
+    /// Sets the path of the header, see `Header::set_path`.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> HeaderBuilder {
+        let res = self.header.set_path(path);
+        self.check(res)
+    }
+
+    /// Sets the link name of the header, see `Header::set_link_name`.
+    pub fn link_name<P: AsRef<Path>>(mut self, link_name: P) -> HeaderBuilder {
+        let res = self.header.set_link_name(link_name);
+        self.check(res)
+    }
+
+    /// Sets the mode bits of the header.
+    ///
+    /// The mode field can only hold values up to `0o7777777`.
+    pub fn mode(mut self, mode: u32) -> HeaderBuilder {
+        if mode > 0o7777777 {
+            let err = other(&format!("mode {:o} is too large for the header", mode));
+            return self.check(Err(err));
+        }
+        self.header.set_mode(mode);
+        self
+    }
+
+    /// Sets the owner's user ID of the header.
+    pub fn uid(mut self, uid: u64) -> HeaderBuilder {
+        self.header.set_uid(uid);
+        self
+    }
+
+    /// Sets the owner's group ID of the header.
+    pub fn gid(mut self, gid: u64) -> HeaderBuilder {
+        self.header.set_gid(gid);
+        self
+    }
+
+    /// Sets the size of the header.
+    pub fn size(mut self, size: u64) -> HeaderBuilder {
+        self.header.set_size(size);
+        self
+    }
+
+    /// Sets the last modification time of the header.
+    pub fn mtime(mut self, mtime: u64) -> HeaderBuilder {
+        self.header.set_mtime(mtime);
+        self
+    }
+
+    /// Sets the type of file described by the header.
+    pub fn entry_type(mut self, ty: EntryType) -> HeaderBuilder {
+        self.header.set_entry_type(ty);
+        self
+    }
+
+    /// Sets the user name of the owner, see `Header::set_username`.
+    pub fn username(mut self, name: &str) -> HeaderBuilder {
+        let res = self.header.set_username(name);
+        self.check(res)
+    }
+
+    /// Sets the group name of the owner, see `Header::set_groupname`.
+    pub fn groupname(mut self, name: &str) -> HeaderBuilder {
+        let res = self.header.set_groupname(name);
+        self.check(res)
+    }
+
+    /// Finishes building the header, computing its checksum.
+    ///
+    /// Returns the first error which happened while setting fields, if any.
+    pub fn build(self) -> io::Result<Header> {
+        if let Some(err) = self.err {
+            return Err(err);
+        }
+        let mut header = self.header;
+        header.set_cksum();
+        Ok(header)
+    }
+
+    fn check(mut self, res: io::Result<()>) -> HeaderBuilder {
+        if let Err(err) = res {
+            self.err.get_or_insert(err);
+        }
+        self
+    }
+}
+
 struct DebugAsOctal<T>(T);
 
 impl<T: fmt::Octal> fmt::Debug for DebugAsOctal<T> {
@@ -959,47 +1251,59 @@ impl UstarHeader {
         // components where it can fit in name/prefix. To do that we peel off
         // enough until the path fits in `prefix`, then we try to put both
         // halves into their destination.
+        //
+        // Both fields are filled in on the side so the header is left as it
+        // was if the path doesn't fit.
         let bytes = path2bytes(path)?;
-        let (maxnamelen, maxprefixlen) = (self.name.len(), self.prefix.len());
+        let mut name = [0; 100];
+        let mut prefix = [0; 155];
+        let (maxnamelen, maxprefixlen) = (name.len(), prefix.len());
         if bytes.len() <= maxnamelen {
-            copy_path_into(&mut self.name, path, false).map_err(|err| {
+            copy_path_into(&mut name, path, false).map_err(|err| {
                 io::Error::new(
                     err.kind(),
                     format!("{} when setting path for {}", err, self.path_lossy()),
                 )
             })?;
         } else {
-            let mut prefix = path;
+            let cannot_split = || {
+                other(&format!(
+                    "path cannot be split to be inserted into archive, a GNU or \
+                     PAX long name is needed instead: {}",
+                    path.display()
+                ))
+            };
+            let mut head = path;
             let mut prefixlen;
             loop {
-                match prefix.parent() {
-                    Some(parent) => prefix = parent,
-                    None => {
-                        return Err(other(&format!(
-                            "path cannot be split to be inserted into archive: {}",
-                            path.display()
-                        )));
-                    }
+                match head.parent() {
+                    Some(parent) => head = parent,
+                    None => return Err(cannot_split()),
                 }
-                prefixlen = path2bytes(prefix)?.len();
+                prefixlen = path2bytes(head)?.len();
                 if prefixlen <= maxprefixlen {
                     break;
                 }
             }
-            copy_path_into(&mut self.prefix, prefix, false).map_err(|err| {
+            if bytes.len() - prefixlen - 1 > maxnamelen {
+                return Err(cannot_split());
+            }
+            copy_path_into(&mut prefix, head, false).map_err(|err| {
                 io::Error::new(
                     err.kind(),
                     format!("{} when setting path for {}", err, self.path_lossy()),
                 )
             })?;
-            let path = bytes2path(Cow::Borrowed(&bytes[prefixlen + 1..]))?;
-            copy_path_into(&mut self.name, &path, false).map_err(|err| {
+            let tail = bytes2path(Cow::Borrowed(&bytes[prefixlen + 1..]))?;
+            copy_path_into(&mut name, &tail, false).map_err(|err| {
                 io::Error::new(
                     err.kind(),
                     format!("{} when setting path for {}", err, self.path_lossy()),
                 )
             })?;
         }
+        self.name = name;
+        self.prefix = prefix;
         Ok(())
     }
 
@@ -1184,7 +1488,9 @@ impl GnuHeader {
         octal_into(&mut self.dev_minor, minor);
     }
 
-    /// Returns the last modification time in Unix time format
+    /// Returns the last access time in Unix time format
+    ///
+    /// Times before January 1, 1970 are an error, see `atime_signed`.
     pub fn atime(&self) -> io::Result<u64> {
         num_field_wrapper_from(&self.atime).map_err(|err| {
             io::Error::new(
@@ -1194,6 +1500,19 @@ impl GnuHeader {
         })
     }
 
+    /// Returns the last access time in Unix time format, which may be negative
+    ///
+    /// The base-256 encoding used by GNU tar is able to store times before
+    /// January 1, 1970.
+    pub fn atime_signed(&self) -> io::Result<i64> {
+        num_field_wrapper_from_signed(&self.atime).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{} when getting atime for {}", err, self.fullname_lossy()),
+            )
+        })
+    }
+
     /// Encodes the `atime` provided into this header.
     ///
     /// Note that this time is typically a number of seconds passed since
@@ -1202,7 +1521,9 @@ impl GnuHeader {
         num_field_wrapper_into(&mut self.atime, atime);
     }
 
-    /// Returns the last modification time in Unix time format
+    /// Returns the last status change time in Unix time format
+    ///
+    /// Times before January 1, 1970 are an error, see `ctime_signed`.
     pub fn ctime(&self) -> io::Result<u64> {
         num_field_wrapper_from(&self.ctime).map_err(|err| {
             io::Error::new(
@@ -1212,6 +1533,19 @@ impl GnuHeader {
         })
     }
 
+    /// Returns the last status change time in Unix time format, which may be negative
+    ///
+    /// The base-256 encoding used by GNU tar is able to store times before
+    /// January 1, 1970.
+    pub fn ctime_signed(&self) -> io::Result<i64> {
+        num_field_wrapper_from_signed(&self.ctime).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{} when getting ctime for {}", err, self.fullname_lossy()),
+            )
+        })
+    }
+
     /// Encodes the `ctime` provided into this header.
     ///
     /// Note that this time is typically a number of seconds passed since
@@ -1220,10 +1554,33 @@ impl GnuHeader {
         num_field_wrapper_into(&mut self.ctime, ctime);
     }
 
+    /// Returns the offset within the original file at which the contents of
+    /// this entry start, if this is a GNU multi-volume continuation entry.
+    ///
+    /// When GNU tar splits a file across volumes of a multi-volume or
+    /// incremental backup, the next volume starts with a continuation entry
+    /// (type `'M'`) holding the rest of the file. Together with `real_size`,
+    /// the size of the whole file, this allows the pieces to be reassembled.
+    /// `None` is returned for all other entries.
+    pub fn offset(&self) -> io::Result<Option<u64>> {
+        if !self.as_header().entry_type().is_gnu_multivolume() {
+            return Ok(None);
+        }
+        num_field_wrapper_from(&self.offset)
+            .map(Some)
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("{} when getting offset for {}", err, self.fullname_lossy()),
+                )
+            })
+    }
+
     /// Returns the "real size" of the file this header represents.
     ///
     /// This is applicable for sparse files where the returned size here is the
-    /// size of the entire file after the sparse regions have been filled in.
+    /// size of the entire file after the sparse regions have been filled in,
+    /// as well as GNU multi-volume continuation entries, see `offset`.
     pub fn real_size(&self) -> io::Result<u64> {
         octal_from(&self.realsize).map_err(|err| {
             io::Error::new(
@@ -1261,10 +1618,10 @@ impl fmt::Debug for GnuHeader {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut f = f.debug_struct("GnuHeader");
         self.as_header().debug_fields(&mut f);
-        if let Ok(atime) = self.atime() {
+        if let Ok(atime) = self.atime_signed() {
             f.field("atime", &atime);
         }
-        if let Ok(ctime) = self.ctime() {
+        if let Ok(ctime) = self.ctime_signed() {
             f.field("ctime", &ctime);
         }
         f.field("is_extended", &self.is_extended())
@@ -1339,14 +1696,12 @@ impl GnuExtSparseHeader {
 
     /// Returns a view into this header as a byte array.
     pub fn as_bytes(&self) -> &[u8; 512] {
-        debug_assert_eq!(mem::size_of_val(self), 512);
-        unsafe { mem::transmute(self) }
+        unsafe { cast(self) }
     }
 
     /// Returns a view into this header as a byte array.
     pub fn as_mut_bytes(&mut self) -> &mut [u8; 512] {
-        debug_assert_eq!(mem::size_of_val(self), 512);
-        unsafe { mem::transmute(self) }
+        unsafe { cast_mut(self) }
     }
 
     /// Returns a slice of the underlying sparse headers.
@@ -1380,12 +1735,30 @@ fn octal_from(slice: &[u8]) -> io::Result<u64> {
             )));
         }
     };
-    match u64::from_str_radix(num.trim(), 8) {
+    let num = num.trim();
+    match u64::from_str_radix(num, 8) {
         Ok(n) => Ok(n),
+        Err(_) if !num.is_empty() && num.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            Err(other(&format!("non-octal digit in numeric field: {}", num)))
+        }
         Err(_) => Err(other(&format!("numeric field was not a number: {}", num))),
     }
 }
 
+// Interprets a numeric field that isn't valid octal by guessing the radix a
+// non-conformant writer used: hexadecimal if there's a `0x` prefix or any of
+// the digits `a-f`, otherwise decimal.
+fn lenient_number_from(slice: &[u8]) -> Option<u64> {
+    let num = str::from_utf8(truncate(slice)).ok()?.trim();
+    if let Some(hex) = num.strip_prefix("0x").or_else(|| num.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if num.bytes().any(|b| b.is_ascii_alphabetic()) {
+        u64::from_str_radix(num, 16).ok()
+    } else {
+        num.parse().ok()
+    }
+}
+
 fn octal_into<T: fmt::Octal>(dst: &mut [u8], val: T) {
     let o = format!("{:o}", val);
     let value = o.bytes().rev().chain(repeat(b'0'));
@@ -1408,12 +1781,26 @@ fn num_field_wrapper_into(dst: &mut [u8], src: u64) {
 // extension) or octal (standard encoding).
 fn num_field_wrapper_from(src: &[u8]) -> io::Result<u64> {
     if src[0] & 0x80 != 0 {
-        Ok(numeric_extended_from(src))
+        numeric_extended_from(src)
     } else {
         octal_from(src)
     }
 }
 
+// Same as `num_field_wrapper_from`, but for fields which may hold negative
+// values such as timestamps.
+fn num_field_wrapper_from_signed(src: &[u8]) -> io::Result<i64> {
+    let n = if src[0] & 0x80 != 0 {
+        numeric_extended_from_signed(src)
+    } else {
+        i128::from(octal_from(src)?)
+    };
+    if n < i128::from(i64::MIN) || n > i128::from(i64::MAX) {
+        return Err(other("numeric field is out of range"));
+    }
+    Ok(n as i64)
+}
+
 // When writing numeric fields with is the extended form, the high bit of the
 // first byte is set to 1 and the remainder of the field is treated as binary
 // instead of octal ascii.
@@ -1430,21 +1817,40 @@ fn numeric_extended_into(dst: &mut [u8], src: u64) {
     dst[0] |= 0x80;
 }
 
-fn numeric_extended_from(src: &[u8]) -> u64 {
-    let mut dst: u64 = 0;
-    let mut b_to_skip = 1;
+fn numeric_extended_from(src: &[u8]) -> io::Result<u64> {
     if src.len() == 8 {
         // read first byte without extension flag bit
-        dst = (src[0] ^ 0x80) as u64;
-    } else {
-        // only read last 8 bytes
-        b_to_skip = src.len() - 8;
+        return Ok(src
+            .iter()
+            .skip(1)
+            .fold((src[0] ^ 0x80) as u64, |dst, byte| {
+                (dst << 8) | *byte as u64
+            }));
+    }
+    // Only the last 8 bytes fit in a u64, everything before them has to be
+    // zero apart from the extension flag bit itself.
+    if src[0] & 0x40 != 0 {
+        return Err(other("negative value in unsigned numeric field"));
     }
-    for byte in src.iter().skip(b_to_skip) {
-        dst <<= 8;
-        dst |= *byte as u64;
+    let (high, low) = src.split_at(src.len() - 8);
+    if high[0] & 0x7f != 0 || high[1..].iter().any(|b| *b != 0) {
+        return Err(other("numeric field overflows a 64-bit integer"));
     }
-    dst
+    Ok(low.iter().fold(0, |dst, byte| (dst << 8) | *byte as u64))
+}
+
+// Decodes a base-256 field in two's complement form. The high bit of the
+// first byte only marks the extended form, the next bit is the sign.
+fn numeric_extended_from_signed(src: &[u8]) -> i128 {
+    let first = i128::from(src[0] & 0x7f);
+    let first = if src[0] & 0x40 != 0 {
+        first - 0x80
+    } else {
+        first
+    };
+    src[1..]
+        .iter()
+        .fold(first, |dst, byte| (dst << 8) | i128::from(*byte))
 }
 
 fn truncate(slice: &[u8]) -> &[u8] {
@@ -5,6 +5,16 @@
 //! an archive is never required to be fully resident in memory, and all objects
 //! provide largely a streaming interface to read bytes from.
 //!
+//! Compression isn't handled by this library, so compressed archives need to
+//! be wrapped in a decompressor before being handed to `Archive`, see
+//! `detect_format`. Note that gzip files may consist of several gzip members
+//! one after another, as produced by parallel compressors such as `pigz` or by
+//! concatenating `.tar.gz` files. Decoders which stop at the end of the first
+//! member, like `GzDecoder` from `flate2`, silently cut such archives short,
+//! so a multi-member decoder such as `flate2`'s `MultiGzDecoder` should be
+//! used instead. With the `flate2` feature enabled, `Archive::new_gz` and
+//! `Builder::new_gz` take care of this for `.tar.gz` files.
+//!
 //! [1]: http://en.wikipedia.org/wiki/Tar_%28computing%29
 
 // More docs about the detailed tar format can also be found here:
@@ -23,16 +33,26 @@
 
 use std::io::{Error, ErrorKind};
 
-pub use crate::archive::{Archive, Entries};
-pub use crate::builder::Builder;
-pub use crate::entry::{Entry, Unpacked};
-pub use crate::entry_type::EntryType;
+pub use crate::archive::{
+    unpack_single, Archive, ChecksumPolicy, Entries, EntryHasher, EntryIndex, HardlinkFallback,
+    List, ListEntry, ParentDirPolicy, PathEncoding, UnknownTypePolicy, UnpackAction,
+    WindowsNamePolicy,
+};
+pub use crate::builder::{pack_single, Builder, EntryWriter};
+pub use crate::compression::{detect_format, CompressionFormat};
+pub use crate::entry::{Entry, EntryMetadata, Unpacked};
+pub use crate::entry_type::{EntryType, FileType};
 pub use crate::header::GnuExtSparseHeader;
-pub use crate::header::{GnuHeader, GnuSparseHeader, Header, HeaderMode, OldHeader, UstarHeader};
+pub use crate::header::{
+    GnuHeader, GnuSparseHeader, Header, HeaderBuilder, HeaderFormat, HeaderMode, OldHeader,
+    UstarHeader,
+};
 pub use crate::pax::{PaxExtension, PaxExtensions};
 
 mod archive;
+mod beneath;
 mod builder;
+mod compression;
 mod entry;
 mod entry_type;
 mod error;
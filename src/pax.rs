@@ -1,5 +1,4 @@
 use std::io;
-use std::slice;
 use std::str;
 
 use crate::other;
@@ -9,7 +8,7 @@ use crate::other;
 /// This iterator yields structures which can themselves be parsed into
 /// key/value pairs.
 pub struct PaxExtensions<'entry> {
-    data: slice::Split<'entry, u8, fn(&u8) -> bool>,
+    data: &'entry [u8],
 }
 
 /// A key/value pair corresponding to a pax extension.
@@ -19,71 +18,115 @@ pub struct PaxExtension<'entry> {
 }
 
 pub fn pax_extensions(a: &[u8]) -> PaxExtensions {
-    fn is_newline(a: &u8) -> bool {
-        *a == b'\n'
-    }
-    PaxExtensions {
-        data: a.split(is_newline),
-    }
+    PaxExtensions { data: a }
 }
 
-pub fn pax_extensions_size(a: &[u8]) -> Option<u64> {
+pub fn pax_extensions_size(a: &[u8]) -> io::Result<Option<u64>> {
     for extension in pax_extensions(a) {
         let current_extension = match extension {
             Ok(ext) => ext,
-            Err(_) => return None,
+            Err(_) => return Ok(None),
         };
         if current_extension.key() != Ok("size") {
             continue;
         }
+        return current_extension.value_u64().map(Some);
+    }
+    Ok(None)
+}
 
-        let value = match current_extension.value() {
-            Ok(value) => value,
-            Err(_) => return None,
-        };
-        let size = match value.parse::<u64>() {
-            Ok(size) => size,
-            Err(_) => return None,
-        };
-        return Some(size);
+/// Parses the value of a numeric pax record, such as `size`, `mtime` or
+/// `GNU.sparse.realsize`, which is a decimal number of any length.
+pub fn pax_number(value: &[u8]) -> io::Result<u64> {
+    if value.is_empty() {
+        return Err(other("empty numeric pax value"));
+    }
+    let mut n: u64 = 0;
+    for b in value {
+        if !b.is_ascii_digit() {
+            return Err(other(&format!(
+                "numeric pax value `{}` is not a decimal number",
+                String::from_utf8_lossy(value)
+            )));
+        }
+        n = n
+            .checked_mul(10)
+            .and_then(|n| n.checked_add(u64::from(b - b'0')))
+            .ok_or_else(|| {
+                other(&format!(
+                    "numeric pax value `{}` overflows a 64-bit integer",
+                    String::from_utf8_lossy(value)
+                ))
+            })?;
     }
-    None
+    Ok(n)
+}
+
+/// Parses the fractional part of a pax time record such as `mtime`, the
+/// digits following the decimal point, into nanoseconds.
+///
+/// Digits beyond nanosecond precision are ignored.
+pub fn pax_nanos(fraction: &[u8]) -> io::Result<u32> {
+    if !fraction.iter().all(|b| b.is_ascii_digit()) {
+        return Err(other(&format!(
+            "fractional pax time `{}` is not a decimal number",
+            String::from_utf8_lossy(fraction)
+        )));
+    }
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = digits.iter().fold(0, |n, b| n * 10 + u32::from(b - b'0'));
+    Ok(nanos * 10u32.pow(9 - digits.len() as u32))
 }
 
 impl<'entry> Iterator for PaxExtensions<'entry> {
     type Item = io::Result<PaxExtension<'entry>>;
 
     fn next(&mut self) -> Option<io::Result<PaxExtension<'entry>>> {
-        let line = match self.data.next() {
-            Some(line) if line.is_empty() => return None,
-            Some(line) => line,
-            None => return None,
-        };
+        if self.data.is_empty() || self.data[0] == b'\n' {
+            return None;
+        }
+
+        // Each record is `"%d %s=%s\n", length, key, value` where the length
+        // covers the whole record. Values may contain both `=` and newlines,
+        // so the length is what delimits records, and only the first `=`
+        // separates the key from the value.
+        let data = self.data;
+        let extension = data
+            .iter()
+            .position(|b| *b == b' ')
+            .and_then(|i| {
+                str::from_utf8(&data[..i])
+                    .ok()
+                    .and_then(|len| len.parse::<usize>().ok().map(|j| (i + 1, j)))
+            })
+            .and_then(|(kvstart, reported_len)| {
+                if reported_len > kvstart
+                    && reported_len <= data.len()
+                    && data[reported_len - 1] == b'\n'
+                {
+                    let kv = &data[kvstart..reported_len - 1];
+                    kv.iter()
+                        .position(|b| *b == b'=')
+                        .map(|equals| (kv, equals, reported_len))
+                } else {
+                    None
+                }
+            })
+            .map(|(kv, equals, reported_len)| {
+                self.data = &data[reported_len..];
+                PaxExtension {
+                    key: &kv[..equals],
+                    value: &kv[equals + 1..],
+                }
+            })
+            .ok_or_else(|| other("malformed pax extension"));
 
-        Some(
-            line.iter()
-                .position(|b| *b == b' ')
-                .and_then(|i| {
-                    str::from_utf8(&line[..i])
-                        .ok()
-                        .and_then(|len| len.parse::<usize>().ok().map(|j| (i + 1, j)))
-                })
-                .and_then(|(kvstart, reported_len)| {
-                    if line.len() + 1 == reported_len {
-                        line[kvstart..]
-                            .iter()
-                            .position(|b| *b == b'=')
-                            .map(|equals| (kvstart, equals))
-                    } else {
-                        None
-                    }
-                })
-                .map(|(kvstart, equals)| PaxExtension {
-                    key: &line[kvstart..kvstart + equals],
-                    value: &line[kvstart + equals + 1..],
-                })
-                .ok_or_else(|| other("malformed pax extension")),
-        )
+        // The length of the next record can't be trusted after a malformed
+        // one, so stop iterating.
+        if extension.is_err() {
+            self.data = &[];
+        }
+        Some(extension)
     }
 }
 
@@ -111,4 +154,14 @@ impl<'entry> PaxExtension<'entry> {
     pub fn value_bytes(&self) -> &'entry [u8] {
         self.value
     }
+
+    /// Returns the value of this key/value pair parsed as an unsigned
+    /// decimal number, as used by records like `size` and
+    /// `GNU.sparse.realsize`.
+    ///
+    /// Values of any length are accepted as long as they fit in a `u64`, an
+    /// error is returned for values which don't or which aren't numbers.
+    pub fn value_u64(&self) -> io::Result<u64> {
+        pax_number(self.value)
+    }
 }
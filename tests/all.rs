@@ -6,12 +6,16 @@ extern crate xattr;
 
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::{self, Cursor};
+use std::io::{self, Cursor, SeekFrom};
 use std::iter::repeat;
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 
 use filetime::FileTime;
-use tar::{Archive, Builder, EntryType, Header};
+use tar::{
+    Archive, Builder, ChecksumPolicy, CompressionFormat, EntryHasher, EntryType, Header,
+    ParentDirPolicy, UnpackAction,
+};
 use tempfile::{Builder as TempBuilder, TempDir};
 
 macro_rules! t {
@@ -225,6 +229,32 @@ fn reading_entries() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+fn reads_stop_at_entry_size() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+    t!(ar.append_data(&mut header, "b", &b"bar"[..]));
+    let data = t!(ar.into_inner());
+
+    // A reader which can't be seeked, so the iterator relies on the entries
+    // not having read past their contents
+    let mut ar = Archive::new(io::Read::chain(&data[..], io::empty()));
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    let mut buf = [0; 4096];
+    assert_eq!(t!(a.read(&mut buf)), 3);
+    assert_eq!(&buf[..3], b"foo");
+    assert_eq!(t!(a.read(&mut buf)), 0);
+    let mut b = t!(entries.next().unwrap());
+    let mut out = Vec::new();
+    assert_eq!(t!(io::copy(&mut b, &mut out)), 3);
+    assert_eq!(out, b"bar");
+    assert!(entries.next().is_none());
+}
+
 fn check_dirtree(td: &TempDir) {
     let dir_a = td.path().join("a");
     let dir_b = td.path().join("a/b");
@@ -313,6 +343,43 @@ fn extracting_duplicate_link_succeed() {
     t!(ar.unpack(td.path()));
 }
 
+#[test]
+#[cfg(unix)]
+fn extracting_duplicates_skipped() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o700);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d", io::empty()));
+    for name in &["d/f", "g"] {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(7);
+        t!(ar.append_data(&mut header, name, &b"archive"[..]));
+    }
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "l", "g"));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir(td.path().join("d")));
+    t!(fs::write(td.path().join("d/f"), "local"));
+    t!(std::os::unix::fs::symlink("d/f", td.path().join("l")));
+    let mode = t!(fs::metadata(td.path().join("d"))).permissions();
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_overwrite(false);
+    ar.set_skip_existing(true);
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read_to_string(td.path().join("d/f"))), "local");
+    assert_eq!(t!(fs::read_link(td.path().join("l"))), Path::new("d/f"));
+    assert_eq!(t!(fs::metadata(td.path().join("d"))).permissions(), mode);
+    assert_eq!(t!(fs::read_to_string(td.path().join("g"))), "archive");
+}
+
 #[test]
 #[cfg(all(unix, feature = "xattr"))]
 fn xattrs() {
@@ -394,6 +461,29 @@ fn writing_directories_recursively() {
         .unwrap_or(false));
 }
 
+#[test]
+fn directory_names_end_in_one_slash() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir(td.path().join("sub")));
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir("foo", td.path()));
+    t!(ar.append_dir("bar/", td.path()));
+    t!(ar.append_dir("baz//", td.path()));
+    t!(ar.append_dir_all("all", td.path()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    let names = t!(ar.entries())
+        .map(|entry| {
+            let entry = t!(entry);
+            assert_eq!(entry.header().entry_type(), EntryType::Directory);
+            String::from_utf8(entry.path_bytes().into_owned()).unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["foo/", "bar/", "baz/", "all/", "all/sub/"]);
+}
+
 #[test]
 fn append_dir_all_blank_dest() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -436,6 +526,69 @@ fn append_dir_all_does_not_work_on_non_directory() {
     assert!(result.is_err());
 }
 
+#[test]
+fn append_dir_all_sorted() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    for name in &["c", "a", "d/y", "d/x", "b"] {
+        let path = td.path().join(name);
+        t!(fs::create_dir_all(path.parent().unwrap()));
+        t!(fs::write(path, name));
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    ar.mode(tar::HeaderMode::Deterministic);
+    t!(ar.append_dir_all("root", td.path()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let names = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    let expected = [
+        "root/", "root/a", "root/b", "root/c", "root/d/", "root/d/x", "root/d/y",
+    ];
+    assert_eq!(
+        names,
+        expected.iter().map(PathBuf::from).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn deterministic_archives_are_reproducible() {
+    let build = |td: &TempDir| {
+        let mut ar = Builder::new(Vec::new());
+        ar.mode(tar::HeaderMode::Deterministic);
+        t!(ar.append_dir_all("root", td.path()));
+        t!(ar.into_inner())
+    };
+
+    let td1 = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let td2 = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    for name in &["b", "a", "d/c"] {
+        for td in &[&td1, &td2] {
+            let path = td.path().join(name);
+            t!(fs::create_dir_all(path.parent().unwrap()));
+            t!(fs::write(&path, name));
+        }
+    }
+    let old = FileTime::from_unix_time(1_000_000, 0);
+    t!(filetime::set_file_times(td2.path().join("a"), old, old));
+
+    let data = build(&td1);
+    assert_eq!(data, build(&td2));
+
+    let mut ar = Archive::new(&data[..]);
+    for entry in t!(ar.entries()) {
+        let entry = t!(entry);
+        let header = entry.header();
+        assert_eq!(t!(header.mtime()), 0);
+        assert_eq!(t!(header.uid()), 0);
+        assert_eq!(t!(header.gid()), 0);
+        assert_eq!(header.username_bytes(), Some(&b""[..]));
+        assert_eq!(header.groupname_bytes(), Some(&b""[..]));
+    }
+}
+
 #[test]
 fn extracting_duplicate_dirs() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -487,17 +640,28 @@ fn handling_incorrect_file_size() {
     header.set_metadata(&t!(file.metadata()));
     header.set_size(2048); // past the end of file null blocks
     header.set_cksum();
-    t!(ar.append(&header, &mut file));
+    // The missing contents are filled in with zeros, so the error leaves the
+    // archive readable
+    assert!(ar.append(&header, &mut file).is_err());
+    let mut header = Header::new_old();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "after", &b"a"[..]));
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len() % 512, 0);
 
     // Extracting
-    let rdr = Cursor::new(t!(ar.into_inner()));
-    let mut ar = Archive::new(rdr);
-    assert!(ar.unpack(td.path()).is_err());
+    let mut ar = Archive::new(Cursor::new(&data));
+    let dst = td.path().join("dst");
+    t!(ar.unpack(&dst));
+    assert_eq!(t!(fs::read(dst.join("somepath"))), [0; 2048]);
+    assert_eq!(t!(fs::read(dst.join("after"))), b"a");
 
     // Iterating
-    let rdr = Cursor::new(ar.into_inner().into_inner());
-    let mut ar = Archive::new(rdr);
-    assert!(t!(ar.entries()).any(|fr| fr.is_err()));
+    let mut ar = Archive::new(Cursor::new(&data));
+    let entries = t!(ar.entries())
+        .map(|e| t!(t!(e).contents()))
+        .collect::<Vec<_>>();
+    assert_eq!(entries, [vec![0; 2048], b"a".to_vec()]);
 }
 
 #[test]
@@ -645,6 +809,58 @@ fn file_times() {
     assert_eq!(atime.nanoseconds(), 0);
 }
 
+#[test]
+fn dir_times() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_mtime(1_000_000);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "dir", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_mtime(2_000_000);
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "dir/file", &b"x"[..]));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = td.path().join("preserved");
+    t!(Archive::new(&data[..]).unpack(&dst));
+    let mtime = |path: &Path| FileTime::from_last_modification_time(&t!(fs::metadata(path)));
+    assert_eq!(mtime(&dst.join("dir")).unix_seconds(), 1_000_000);
+    assert_eq!(mtime(&dst.join("dir/file")).unix_seconds(), 2_000_000);
+
+    let dst = td.path().join("not_preserved");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_mtime(false);
+    t!(ar.unpack(&dst));
+    assert!(mtime(&dst.join("dir")).unix_seconds() > 2_000_000);
+    assert!(mtime(&dst.join("dir/file")).unix_seconds() > 2_000_000);
+
+    let dst = td.path().join("secure");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_secure_extraction(true);
+    t!(ar.unpack(&dst));
+    assert_eq!(mtime(&dst.join("dir")).unix_seconds(), 1_000_000);
+
+    // The times of a symlink in the directory's place are set rather than
+    // those of its target
+    #[cfg(unix)]
+    {
+        let outside = td.path().join("outside");
+        t!(fs::create_dir(&outside));
+        let before = mtime(&outside);
+        let dst = td.path().join("symlinked");
+        t!(fs::create_dir(&dst));
+        t!(std::os::unix::fs::symlink(&outside, dst.join("dir")));
+        let mut ar = Archive::new(&data[..]);
+        t!(t!(t!(ar.entries()).next().unwrap()).unpack_in(&dst));
+        assert_eq!(mtime(&outside), before);
+    }
+}
+
 #[test]
 fn backslash_treated_well() {
     // Insert a file into an archive with a backslash
@@ -654,9 +870,9 @@ fn backslash_treated_well() {
     let mut ar = Archive::new(Cursor::new(t!(ar.into_inner())));
     let f = t!(t!(ar.entries()).next().unwrap());
     if cfg!(unix) {
-        assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar"));
+        assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar/"));
     } else {
-        assert_eq!(t!(f.header().path()).to_str(), Some("foo/bar"));
+        assert_eq!(t!(f.header().path()).to_str(), Some("foo/bar/"));
     }
 
     // Unpack an archive with a backslash in the name
@@ -741,6 +957,220 @@ fn pax_size() {
     assert_eq!(entry.size(), 4);
 }
 
+#[test]
+fn unpack_to_map() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    t!(ar.append_bytes(&mut header, "./a/b", b"foo"));
+    t!(ar.append_bytes(&mut header, "c", b"first"));
+    t!(ar.append_bytes(&mut header, "c", b"second"));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    t!(ar.append_bytes(&mut header, "d", b""));
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(ar.append_link(&mut header, "e", "c"));
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(header.set_path("x"));
+    header.as_old_mut().name[..4].copy_from_slice(b"../x");
+    header.set_cksum();
+    t!(ar.append(&header, &b"bad"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let files = t!(ar.unpack_to_map(None));
+    let paths = files
+        .keys()
+        .map(|p| p.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["a/b", "c"]);
+    assert_eq!(files[Path::new("a/b")], b"foo");
+    assert_eq!(files[Path::new("c")], b"second");
+
+    let mut ar = Archive::new(&data[..]);
+    assert_eq!(t!(ar.unpack_to_map(Some(14))).len(), 2);
+    let mut ar = Archive::new(&data[..]);
+    let err = ar.unpack_to_map(Some(13)).unwrap_err();
+    assert!(err.to_string().contains("13 bytes"), "{}", err);
+}
+
+#[test]
+fn append_bytes() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    // A stale size is replaced by the length of the contents
+    header.set_size(1000);
+    t!(ar.append_bytes(&mut header, "a", b"hello"));
+    t!(ar.append_bytes(&mut header, "b", &[]));
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len(), 512 * 5);
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(a.size(), 5);
+    assert_eq!(t!(a.contents()), b"hello");
+    let b = t!(entries.next().unwrap());
+    assert_eq!(t!(b.path()), Path::new("b"));
+    assert_eq!(b.size(), 0);
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn append_writer() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    t!(header.set_path("a"));
+    header.set_size(6);
+    let mut entry = t!(ar.append_writer(&mut header));
+    t!(entry.write_all(b"foo"));
+    assert_eq!(entry.remaining(), 3);
+    assert!(entry.write_all(b"barbaz").is_err());
+    // The extra contents are left out, but finishing still reports them
+    let err = entry.finish().unwrap_err();
+    assert!(err.to_string().contains("more than the 6 bytes"), "{}", err);
+
+    // Missing contents are an error, but leave the archive readable
+    t!(header.set_path("b"));
+    header.set_size(4);
+    let mut entry = t!(ar.append_writer(&mut header));
+    t!(entry.write_all(b"ab"));
+    let err = entry.finish().unwrap_err();
+    assert!(err.to_string().contains("only 2 of the 4 bytes"), "{}", err);
+
+    t!(header.set_path("c"));
+    header.set_size(1);
+    t!(ar.append_writer(&mut header)).write_all(b"c").unwrap();
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len(), 512 * 8);
+
+    let mut ar = Archive::new(&data[..]);
+    let contents = t!(ar.entries())
+        .map(|e| t!(t!(e).contents()))
+        .collect::<Vec<_>>();
+    assert_eq!(contents, [&b"foobar"[..], b"ab\0\0", b"c"]);
+}
+
+#[test]
+fn append_pax() {
+    let path = "a/".repeat(60) + "foo";
+    // The length of this record is just past 100 once it counts itself
+    let comment = "c".repeat(88);
+
+    let mut header = Header::new_ustar();
+    t!(header.set_path("foo"));
+    header.set_size(3);
+    header.set_cksum();
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax(
+        &header,
+        &[("path", path.as_bytes()), ("comment", comment.as_bytes())],
+        &b"bar"[..],
+    ));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert_eq!(t!(entry.path()), Path::new(&path));
+    let records = t!(entry.pax_extensions())
+        .unwrap()
+        .map(|r| t!(r))
+        .collect::<Vec<_>>();
+    assert_eq!(records[1].key(), Ok("comment"));
+    assert_eq!(records[1].value(), Ok(&comment[..]));
+    assert!(data[512..].starts_with(b"133 path="));
+    assert!(data[512 + 133..].starts_with(b"101 comment="));
+    let mut contents = String::new();
+    t!(entry.read_to_string(&mut contents));
+    assert_eq!(contents, "bar");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn subsecond_mtimes() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("foo");
+    t!(fs::write(&path, "foo"));
+    let mtime = FileTime::from_unix_time(1_700_000_000, 123_456_789);
+    t!(filetime::set_file_times(&path, mtime, mtime));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.set_subsecond_mtimes(true);
+    t!(ar.append_path_with_name(&path, "foo"));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    let expected = std::time::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 123_456_789);
+    assert_eq!(t!(entry.modified()), expected);
+    assert_eq!(t!(entry.header().mtime()), 1_700_000_000);
+
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack(td.path().join("out")));
+    let meta = t!(fs::metadata(td.path().join("out/foo")));
+    assert_eq!(FileTime::from_last_modification_time(&meta), mtime);
+
+    // Fractions with fewer digits are scaled up, and entries without one
+    // don't get an extended header
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_extensions(vec![("mtime", &b"1700000000.5"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "bar", &[][..]));
+    ar.set_subsecond_mtimes(true);
+    let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+    t!(filetime::set_file_times(&path, mtime, mtime));
+    t!(ar.append_path_with_name(&path, "foo"));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let bar = t!(entries.next().unwrap());
+    let expected = std::time::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 500_000_000);
+    assert_eq!(t!(bar.modified()), expected);
+    let mut foo = t!(entries.next().unwrap());
+    assert!(t!(foo.pax_extensions()).is_none());
+}
+
+#[test]
+fn pax_numeric_values() {
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.realsize", &b"10737418240"[..]),
+        ("max", &b"18446744073709551615"[..]),
+        ("too_big", &b"18446744073709551616"[..]),
+        ("not_a_number", &b"12a"[..]),
+    ]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "sparse", &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let records = t!(entry.pax_extensions())
+        .unwrap()
+        .map(|r| t!(r))
+        .collect::<Vec<_>>();
+    assert_eq!(t!(records[0].value_u64()), 10737418240);
+    assert_eq!(t!(records[1].value_u64()), u64::MAX);
+    assert!(records[2].value_u64().is_err());
+    assert!(records[3].value_u64().is_err());
+
+    // A size which doesn't fit is an error rather than wrapping around
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_extensions(vec![("size", &b"99999999999999999999"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "big", &[][..]));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    assert!(t!(ar.entries()).next().unwrap().is_err());
+}
+
 #[test]
 fn pax_simple() {
     let mut ar = Archive::new(tar!("pax.tar"));
@@ -761,6 +1191,23 @@ fn pax_simple() {
     assert_eq!(third.value(), Ok("1453146164.953123768"));
 }
 
+#[test]
+fn pax_special_values() {
+    let mut ar = Archive::new(tar!("pax_special_values.tar"));
+    let mut entries = t!(ar.entries());
+
+    let mut first = t!(entries.next().unwrap());
+    let mut attributes = t!(first.pax_extensions()).unwrap();
+    let first = t!(attributes.next().unwrap());
+    let second = t!(attributes.next().unwrap());
+    assert!(attributes.next().is_none());
+
+    assert_eq!(first.key(), Ok("comment"));
+    assert_eq!(first.value(), Ok("a=b\nc"));
+    assert_eq!(second.key(), Ok("SCHILY.xattr.user.key"));
+    assert_eq!(second.value(), Ok("x=y=z"));
+}
+
 #[test]
 fn pax_path() {
     let mut ar = Archive::new(tar!("pax2.tar"));
@@ -770,6 +1217,102 @@ fn pax_path() {
     assert!(first.path().unwrap().ends_with("aaaaaaaaaaaaaaa"));
 }
 
+#[test]
+fn pax_global_extensions() {
+    let mut ar = Builder::new(Vec::new());
+    let records = b"19 comment=release\n19 mtime=1000000.5\n";
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XGlobalHeader);
+    t!(header.set_path("pax_global_header"));
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    t!(ar.append(&header, &records[..]));
+
+    let mut header = Header::new_ustar();
+    header.set_mode(0o644);
+    header.set_mtime(5);
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    t!(ar.append_pax_extensions(vec![("comment", &b"override"[..]), ("linkpath", &b"a"[..]),]));
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(header.set_link_name("ignored"));
+    t!(ar.append_data(&mut header, "b", &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(&*a.path_bytes(), b"a");
+    let records = t!(a.pax_extensions()).unwrap();
+    let comment = records
+        .map(|r| t!(r))
+        .filter(|r| r.key() == Ok("comment"))
+        .last()
+        .unwrap();
+    assert_eq!(comment.value(), Ok("release"));
+
+    let mut b = t!(entries.next().unwrap());
+    assert_eq!(&*b.link_name_bytes().unwrap(), b"a");
+    let records = t!(b.pax_extensions()).unwrap();
+    let comment = records
+        .map(|r| t!(r))
+        .filter(|r| r.key() == Ok("comment"))
+        .last()
+        .unwrap();
+    assert_eq!(comment.value(), Ok("override"));
+    assert!(entries.next().is_none());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack(td.path()));
+    let meta = t!(fs::metadata(td.path().join("a")));
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(mtime.unix_seconds(), 1_000_000);
+}
+
+#[test]
+fn entry_metadata() {
+    let mut ar = Archive::new(tar!("pax.tar"));
+    let mut entries = t!(ar.entries());
+    let mut first = t!(entries.next().unwrap());
+
+    {
+        let metadata = first.metadata();
+        assert_eq!(metadata.size(), first.size());
+        assert_eq!(metadata.position(), 0);
+        assert_eq!(metadata.raw_header_position(), first.raw_header_position());
+        assert_eq!(metadata.raw_file_position(), first.raw_file_position());
+        assert!(metadata.long_pathname().is_none());
+        let mut attributes = metadata.pax_extensions().unwrap();
+        assert_eq!(t!(attributes.next().unwrap()).key(), Ok("mtime"));
+    }
+
+    let mut buf = [0; 2];
+    t!(first.read_exact(&mut buf));
+    assert_eq!(first.metadata().position(), 2);
+
+    let mut b = Builder::new(Vec::<u8>::new());
+    let mut h = Header::new_gnu();
+    t!(h.set_path("././@LongLink"));
+    h.set_size(4);
+    h.set_entry_type(EntryType::new(b'L'));
+    h.set_cksum();
+    t!(b.append(&h, "foo\0".as_bytes()));
+    let mut h = Header::new_gnu();
+    t!(h.set_path("bar"));
+    h.set_size(0);
+    h.set_cksum();
+    t!(b.append(&h, io::empty()));
+
+    let contents = t!(b.into_inner());
+    let mut a = Archive::new(&contents[..]);
+    let e = t!(t!(a.entries()).next().unwrap());
+    assert_eq!(e.metadata().long_pathname(), Some(&b"foo\0"[..]));
+    assert_eq!(e.metadata().header().path_bytes(), &b"bar"[..]);
+}
+
 #[test]
 fn long_name_trailing_nul() {
     let mut b = Builder::new(Vec::<u8>::new());
@@ -820,6 +1363,46 @@ fn long_linkname_trailing_nul() {
     assert_eq!(&*e.link_name_bytes().unwrap(), b"foo");
 }
 
+#[test]
+fn long_name_and_link_stay_aligned() {
+    let long_name = "n".repeat(150);
+    let long_link = "l".repeat(200);
+    let mut b = Builder::new(Vec::<u8>::new());
+    for &(kind, name) in &[(b'L', &long_name), (b'K', &long_link)] {
+        let mut h = Header::new_gnu();
+        t!(h.set_path("././@LongLink"));
+        h.set_size(name.len() as u64 + 1);
+        h.set_entry_type(EntryType::new(kind));
+        h.set_cksum();
+        t!(b.append(&h, format!("{}\0", name).as_bytes()));
+    }
+    let mut h = Header::new_gnu();
+    t!(h.set_path("short"));
+    t!(h.set_link_name("short"));
+    h.set_size(0);
+    h.set_entry_type(EntryType::Symlink);
+    h.set_cksum();
+    t!(b.append(&h, &[][..]));
+    let mut h = Header::new_gnu();
+    h.set_size(5);
+    t!(b.append_data(&mut h, "after", &b"after"[..]));
+
+    let contents = t!(b.into_inner());
+    let mut a = Archive::new(&contents[..]);
+    let mut entries = t!(a.entries());
+    let e = t!(entries.next().unwrap());
+    assert_eq!(&*e.path_bytes(), long_name.as_bytes());
+    assert_eq!(&*e.link_name_bytes().unwrap(), long_link.as_bytes());
+    assert_eq!(e.raw_header_position(), 4 * 512);
+    let mut e = t!(entries.next().unwrap());
+    assert_eq!(&*e.path_bytes(), b"after");
+    assert_eq!(e.raw_header_position(), 5 * 512);
+    let mut s = String::new();
+    t!(e.read_to_string(&mut s));
+    assert_eq!(s, "after");
+    assert!(entries.next().is_none());
+}
+
 #[test]
 fn encoded_long_name_has_trailing_nul() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -893,6 +1476,86 @@ fn reading_sparse() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+fn seek_in_entries() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(10);
+    t!(ar.append_data(&mut header, "a", &b"0123456789"[..]));
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "b", &b"abc"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(&data));
+    let mut entries = t!(ar.entries_with_seek());
+    let mut a = t!(entries.next().unwrap());
+    let mut buf = [0; 2];
+    assert_eq!(t!(a.seek(SeekFrom::Start(3))), 3);
+    t!(a.read_exact(&mut buf));
+    assert_eq!(&buf, b"34");
+    assert_eq!(t!(a.seek(SeekFrom::Current(-4))), 1);
+    t!(a.read_exact(&mut buf));
+    assert_eq!(&buf, b"12");
+    assert_eq!(t!(a.seek(SeekFrom::End(-2))), 8);
+    t!(a.read_exact(&mut buf));
+    assert_eq!(&buf, b"89");
+    assert_eq!(t!(a.seek(SeekFrom::Start(100))), 10);
+    assert_eq!(t!(a.read(&mut buf)), 0);
+    let err = a.seek(SeekFrom::Current(-11)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    t!(a.seek(SeekFrom::Start(5)));
+
+    // Seeking back doesn't disturb reading the following entries
+    let mut b = t!(entries.next().unwrap());
+    let mut s = String::new();
+    t!(b.read_to_string(&mut s));
+    assert_eq!(s, "abc");
+    assert!(entries.next().is_none());
+
+    // Entries of a streaming iteration can't be seeked
+    let mut ar = Archive::new(Cursor::new(&data));
+    let mut a = t!(t!(ar.entries()).next().unwrap());
+    assert!(a.seek(SeekFrom::Start(1)).is_err());
+
+    // Sparse entries read back holes as zeros
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    for entry in t!(ar.entries_with_seek()) {
+        let mut entry = t!(entry);
+        let mut full = Vec::new();
+        t!(entry.read_to_end(&mut full));
+        for &pos in [0, 1, 511, 512, 4095, 4096, 7700, 11780, 45060].iter() {
+            let pos = pos.min(full.len());
+            assert_eq!(t!(entry.seek(SeekFrom::Start(pos as u64))), pos as u64);
+            let mut part = vec![0; 600.min(full.len() - pos)];
+            t!(entry.read_exact(&mut part));
+            assert_eq!(part, &full[pos..pos + part.len()]);
+        }
+    }
+}
+
+#[test]
+fn sparse_map() {
+    let rdr = Cursor::new(tar!("sparse.tar"));
+    let mut ar = Archive::new(rdr);
+    let maps = t!(ar.entries())
+        .map(|entry| {
+            let entry = t!(entry);
+            assert!(entry.is_sparse());
+            entry.sparse_map().unwrap().to_vec()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(maps[0], [(0, 512)]);
+    assert_eq!(maps[1], [(7680, 425)]);
+    assert_eq!(maps[2].len(), 6);
+    assert_eq!(maps[2][5], (45056, 5));
+    assert_eq!(maps[3], [(4096, 512), (11776, 512)]);
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(!entry.is_sparse());
+    assert!(entry.sparse_map().is_none());
+}
+
 #[test]
 fn extract_sparse() {
     let rdr = Cursor::new(tar!("sparse.tar"));
@@ -976,6 +1639,38 @@ fn path_separators() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+#[cfg(unix)]
+fn append_dir_all_symlink_loop() {
+    use std::os::unix::fs::symlink;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let root = td.path().join("root");
+    t!(fs::create_dir_all(root.join("a/b")));
+    t!(fs::write(root.join("a/b/file"), b"x"));
+    t!(symlink("../b", root.join("a/b/up")));
+
+    let mut ar = Builder::new(Vec::new());
+    let err = ar.append_dir_all("root", &root).unwrap_err();
+    assert!(err.to_string().contains("symlink loop"), "{}", err);
+
+    // Without following, the symlink itself is archived
+    let mut ar = Builder::new(Vec::new());
+    ar.follow_symlinks(false);
+    t!(ar.append_dir_all("root", &root));
+
+    // Symlinks to other directories are still followed
+    t!(fs::remove_file(root.join("a/b/up")));
+    t!(fs::create_dir(td.path().join("other")));
+    t!(fs::write(td.path().join("other/file"), b"y"));
+    t!(symlink("../../../other", root.join("a/b/other")));
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir_all("root", &root));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    assert!(t!(ar.entries()).any(|e| t!(t!(e).path()) == Path::new("root/a/b/other/file")));
+}
+
 #[test]
 #[cfg(unix)]
 fn append_path_symlink() {
@@ -1122,3 +1817,1852 @@ fn unpack_path_larger_than_windows_max_path() {
     // should unpack path greater than windows MAX_PATH length of 260 characters
     assert!(ar.unpack(td.path()).is_ok());
 }
+
+#[test]
+fn detect_compression_format() {
+    assert_eq!(tar::detect_format(tar!("simple.tar")), None);
+    assert_eq!(tar::detect_format(&[]), None);
+    assert_eq!(
+        tar::detect_format(&[0x1f, 0x8b, 0x08, 0x00]),
+        Some(CompressionFormat::Gzip)
+    );
+    assert_eq!(
+        tar::detect_format(b"BZh91AY&SY"),
+        Some(CompressionFormat::Bzip2)
+    );
+    assert_eq!(
+        tar::detect_format(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00]),
+        Some(CompressionFormat::Xz)
+    );
+    assert_eq!(
+        tar::detect_format(&[0x28, 0xb5, 0x2f, 0xfd, 0x04]),
+        Some(CompressionFormat::Zstd)
+    );
+}
+
+#[test]
+fn append_entry_merges_archives() {
+    let long_name = "abcd/".repeat(30) + "file";
+
+    let mut a = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(a.append_data(&mut header, "a.txt", &b"foo"[..]));
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(a.append_data(&mut header, &long_name, &b"bar"[..]));
+    let a = t!(a.into_inner());
+
+    let mut b = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(b.append_data(&mut header, "b.txt", &b"baz"[..]));
+    let b = t!(b.into_inner());
+
+    let mut merged = Builder::new(Vec::new());
+    for src in [&a, &b].iter() {
+        let mut ar = Archive::new(&src[..]);
+        for entry in t!(ar.entries()) {
+            t!(merged.append_entry(t!(entry)));
+        }
+    }
+    let merged = t!(merged.into_inner());
+
+    let mut ar = Archive::new(&merged[..]);
+    let mut entries = t!(ar.entries());
+    for &(path, contents) in [("a.txt", "foo"), (&long_name[..], "bar"), ("b.txt", "baz")].iter() {
+        let mut entry = t!(entries.next().unwrap());
+        assert_eq!(&*entry.path_bytes(), path.as_bytes());
+        let mut s = String::new();
+        t!(entry.read_to_string(&mut s));
+        assert_eq!(s, contents);
+    }
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn append_archive_copies_verbatim() {
+    let long_name = "abcd/".repeat(30) + "file";
+
+    let mut a = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(a.append_data(&mut header, &long_name, &b"foo"[..]));
+    let mut header = Header::new_ustar();
+    header.set_size(3);
+    t!(header.set_path("b.txt"));
+    header.set_cksum();
+    t!(a.append_pax(&header, &[("path", b"pax.txt")], &b"bar"[..]));
+    let a = t!(a.into_inner());
+
+    let mut b = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(b.append_data(&mut header, "c.txt", &b"baz"[..]));
+    let b = t!(b.into_inner());
+
+    let mut merged = Builder::new(Vec::new());
+    t!(merged.append_archive(&mut Archive::new(&a[..])));
+    t!(merged.append_archive(&mut Archive::new(&b[..])));
+    let merged = t!(merged.into_inner());
+
+    let mut expected = a[..a.len() - 1024].to_vec();
+    expected.extend_from_slice(&b[..b.len() - 1024]);
+    expected.extend_from_slice(&[0; 1024]);
+    assert_eq!(merged, expected);
+
+    // An extension member without an entry following it isn't copied
+    let mut c = Builder::new(Vec::new());
+    t!(c.append_pax_extensions(vec![("path", &b"x"[..])]));
+    let c = t!(c.into_inner());
+    let mut merged = Builder::new(Vec::new());
+    assert!(merged.append_archive(&mut Archive::new(&c[..])).is_err());
+    assert!(merged.get_ref().is_empty());
+}
+
+#[test]
+fn long_name_without_long_names_errors() {
+    let name = "a".repeat(101);
+    let mut ar = Builder::new(Vec::new());
+    ar.long_names(false);
+
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    let err = ar
+        .append_data(&mut header, &name, io::empty())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains(&name), "{}", err);
+    assert!(err.contains("101 bytes"), "{}", err);
+
+    // Nothing should have been written for the rejected entry
+    assert!(ar.get_ref().is_empty());
+
+    ar.long_names(true);
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, &name, io::empty()));
+    let bytes = t!(ar.into_inner());
+    let mut ar = Archive::new(&bytes[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(&*entry.path_bytes(), name.as_bytes());
+}
+
+#[test]
+fn append_data_normalizes_path() {
+    let mut ar = Builder::new(Vec::new());
+    for path in ["./foo/../bar/baz", "a/./b/", "c/d/.."].iter() {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    for path in ["../foo", "foo/../../bar"].iter() {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        assert!(ar.append_data(&mut header, path, io::empty()).is_err());
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&bytes[..]);
+    let names = t!(ar.entries())
+        .map(|e| String::from_utf8(t!(e).path_bytes().into_owned()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["bar/baz", "a/b/", "c"]);
+}
+
+#[test]
+fn read_errors_propagate() {
+    // A reader which yields `data` up to `fail_at` and then times out, after
+    // first being interrupted once.
+    struct Stalled<'a> {
+        data: &'a [u8],
+        fail_at: usize,
+        interrupted: bool,
+        calls: usize,
+    }
+
+    impl<'a> Read for Stalled<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls += 1;
+            assert!(self.calls < 100, "reader is being retried forever");
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+            let n = buf.len().min(self.fail_at).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.fail_at -= n;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "stalled"));
+            }
+            Ok(n)
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1000);
+    t!(ar.append_data(&mut header, "a", &[1; 1000][..]));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    let bytes = t!(ar.into_inner());
+
+    let stalled = |fail_at| Stalled {
+        data: &bytes,
+        fail_at,
+        interrupted: false,
+        calls: 0,
+    };
+
+    // Stall while reading a header
+    let mut ar = Archive::new(stalled(100));
+    let mut entries = t!(ar.entries());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(entries.next().is_none());
+
+    // Stall while reading the contents of an entry
+    let mut ar = Archive::new(stalled(800));
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    let err = a.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    // Stall while skipping over the contents of an entry
+    let mut ar = Archive::new(stalled(800));
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn entries_after_partial_read() {
+    let mut ar = Archive::new(Cursor::new(tar!("simple.tar")));
+    {
+        let mut entries = t!(ar.entries());
+        t!(entries.next().unwrap());
+    }
+    let err = ar.entries().err().unwrap().to_string();
+    assert!(err.contains("partially read"), "{}", err);
+
+    // Rewinding the reader allows iterating again
+    let mut rdr = ar.into_inner();
+    rdr.set_position(0);
+    let mut ar = Archive::new(rdr);
+    assert_eq!(t!(ar.entries()).count(), 3);
+}
+
+#[test]
+fn scan_stops_early() {
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    let mut seen = Vec::new();
+    let mut contents = String::new();
+    t!(ar.scan(|entry| {
+        seen.push(entry.path()?.into_owned());
+        if entry.path()?.ends_with("b") {
+            entry.read_to_string(&mut contents)?;
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    }));
+    assert_eq!(seen, [Path::new("a"), Path::new("b")]);
+    assert_eq!(contents, "b\nb\nb\nb\nb\nb\nb\nb\nb\nb\nb\n");
+
+    // Errors from the callback are propagated
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    let err = ar
+        .scan(|_| Err(io::Error::new(io::ErrorKind::Other, "stop")))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "stop");
+}
+
+#[test]
+fn list_seeks_past_contents() {
+    struct CountingReader<R> {
+        inner: R,
+        read: u64,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(10 * 1024);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "big", &[0; 10 * 1024][..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(header.set_link_name("big"));
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "link", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let reader = CountingReader {
+        inner: Cursor::new(&data[..]),
+        read: 0,
+    };
+    let mut ar = Archive::new(reader);
+    let entries = t!(t!(ar.list()).collect::<io::Result<Vec<_>>>());
+    assert_eq!(entries.len(), 2);
+    assert_eq!(t!(entries[0].path()), Path::new("big"));
+    assert_eq!(entries[0].size(), 10 * 1024);
+    assert_eq!(entries[0].header().entry_type(), EntryType::Regular);
+    assert_eq!(t!(entries[0].link_name()), None);
+    assert_eq!(t!(entries[1].path()), Path::new("link"));
+    assert_eq!(t!(entries[1].link_name()).unwrap(), Path::new("big"));
+
+    // Only the two headers and the first end of archive block were read
+    assert_eq!(ar.into_inner().read, 3 * 512);
+}
+
+#[test]
+fn append_data_size_mismatch() {
+    fn append(strict: bool, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut ar = Builder::new(Vec::new());
+        ar.set_strict_size(strict);
+        let mut header = Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        ar.append_data(&mut header, "foo", data)?;
+        ar.into_inner()
+    }
+
+    fn contents(data: &[u8]) -> Vec<u8> {
+        let mut ar = Archive::new(data);
+        let mut entry = t!(t!(ar.entries()).next().unwrap());
+        let mut contents = Vec::new();
+        t!(entry.read_to_end(&mut contents));
+        contents
+    }
+
+    // Exact
+    assert_eq!(contents(&t!(append(false, b"abcd"))), b"abcd");
+    assert_eq!(contents(&t!(append(true, b"abcd"))), b"abcd");
+
+    // Short readers are always an error
+    assert!(append(false, b"abc").is_err());
+    assert!(append(true, b"abc").is_err());
+
+    // Over-long readers are truncated unless in strict mode
+    assert_eq!(contents(&t!(append(false, b"abcdef"))), b"abcd");
+    let err = append(true, b"abcdef").unwrap_err();
+    assert!(err.to_string().contains("longer than the 4 bytes"));
+}
+
+#[test]
+fn append_streaming_unknown_size() {
+    struct UnknownSize<R>(R);
+
+    impl<R: Read> Read for UnknownSize<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    let contents = (0..1500).map(|i| i as u8).collect::<Vec<_>>();
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    t!(ar.append_streaming(&mut header, "foo", UnknownSize(&contents[..])));
+    assert_eq!(t!(header.size()), 1500);
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "bar", &b"bar"[..]));
+    let data = t!(ar.into_inner()).into_inner();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut foo = t!(entries.next().unwrap());
+    assert_eq!(t!(foo.path()), Path::new("foo"));
+    assert_eq!(foo.size(), 1500);
+    let mut buf = Vec::new();
+    t!(foo.read_to_end(&mut buf));
+    assert_eq!(buf, contents);
+    let mut bar = t!(entries.next().unwrap());
+    assert_eq!(t!(bar.path()), Path::new("bar"));
+    buf.clear();
+    t!(bar.read_to_end(&mut buf));
+    assert_eq!(buf, b"bar");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn unpadded_final_entry() {
+    // Iterating without reading the contents
+    let mut ar = Archive::new(tar!("unpadded_final_entry.tar"));
+    let paths = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [Path::new("a"), Path::new("b")]);
+
+    // Reading the contents
+    let mut ar = Archive::new(tar!("unpadded_final_entry.tar"));
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    let mut s = String::new();
+    t!(a.read_to_string(&mut s));
+    assert_eq!(s, "a\n".repeat(300));
+    let mut b = t!(entries.next().unwrap());
+    s.clear();
+    t!(b.read_to_string(&mut s));
+    assert_eq!(s, "hello\n");
+    assert!(entries.next().is_none());
+
+    // Seeking over the contents
+    let mut ar = Archive::new(Cursor::new(tar!("unpadded_final_entry.tar")));
+    assert_eq!(
+        t!(ar.list()).map(|e| t!(e).size()).collect::<Vec<_>>(),
+        [600, 6]
+    );
+
+    // Truncated data, rather than padding, is still an error
+    let data = tar!("unpadded_final_entry.tar");
+    let mut ar = Archive::new(&data[..data.len() - 1]);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    t!(entries.next().unwrap());
+    assert!(entries.next().unwrap().is_err());
+}
+
+#[test]
+fn pax_reserved_namespaces_round_trip() {
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_extensions(vec![
+        ("security.selinux", &b"system_u:object_r:etc_t:s0\0"[..]),
+        ("realtime.note", &b"a=b\nc"[..]),
+    ]));
+    let mut header = Header::new_ustar();
+    header.set_size(3);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "foo", &b"foo"[..]));
+    let data = t!(ar.into_inner());
+
+    fn check(data: &[u8]) {
+        let mut ar = Archive::new(data);
+        let mut entries = t!(ar.entries());
+        let mut entry = t!(entries.next().unwrap());
+        assert_eq!(t!(entry.path()), Path::new("foo"));
+        let mut attributes = t!(entry.pax_extensions()).unwrap();
+        let first = t!(attributes.next().unwrap());
+        let second = t!(attributes.next().unwrap());
+        assert!(attributes.next().is_none());
+        assert_eq!(first.key(), Ok("security.selinux"));
+        assert_eq!(first.value_bytes(), b"system_u:object_r:etc_t:s0\0");
+        assert_eq!(second.key(), Ok("realtime.note"));
+        assert_eq!(second.value(), Ok("a=b\nc"));
+        assert!(entries.next().is_none());
+    }
+    check(&data);
+
+    // Repacking preserves the records
+    let mut ar = Archive::new(&data[..]);
+    let mut repacked = Builder::new(Vec::new());
+    for entry in t!(ar.entries()) {
+        t!(repacked.append_entry(t!(entry)));
+    }
+    check(&t!(repacked.into_inner()));
+}
+
+#[test]
+fn bytes_remaining() {
+    let data = tar!("reading_files.tar");
+    let mut ar = Archive::new(Cursor::new(data));
+    assert_eq!(t!(ar.bytes_remaining()), data.len() as u64);
+
+    let mut list = t!(ar.list());
+    let mut remaining = vec![t!(list.bytes_remaining())];
+    while let Some(entry) = list.next() {
+        t!(entry);
+        remaining.push(t!(list.bytes_remaining()));
+    }
+    assert!(remaining.windows(2).all(|w| w[0] > w[1]));
+
+    // Reading the end of archive marker moved the reader further along
+    let at_end = t!(ar.bytes_remaining());
+    assert!(at_end < *remaining.last().unwrap());
+    assert_eq!(ar.into_inner().position(), data.len() as u64 - at_end);
+}
+
+#[test]
+fn register_decompressor() {
+    struct Xor<R>(R);
+
+    impl<R: Read> Read for Xor<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buf)?;
+            for b in &mut buf[..n] {
+                *b ^= 0xff;
+            }
+            Ok(n)
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    header.set_cksum();
+    let mut compressed = Vec::new();
+    t!(Xor(&b"hello"[..]).read_to_end(&mut compressed));
+    t!(ar.append_pax_extensions(vec![("SCHILY.compression", &b"xor"[..])]));
+    t!(ar.append_data(&mut header, "compressed", &compressed[..]));
+    t!(ar.append_pax_extensions(vec![("SCHILY.compression", &b"unknown"[..])]));
+    t!(ar.append_data(&mut header, "unknown", &b"hello"[..]));
+    t!(ar.append_data(&mut header, "plain", &b"hello"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.register_decompressor("xor", |raw| Box::new(Xor(raw)));
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        assert_eq!(entry.size(), 5);
+        let mut s = String::new();
+        t!(entry.read_to_string(&mut s));
+        assert_eq!(s, "hello");
+        assert_eq!(entry.metadata().position(), 5);
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.register_decompressor("xor", |raw| Box::new(Xor(raw)));
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("compressed"))), b"hello");
+}
+
+#[test]
+fn decompressed_size_limits() {
+    // Turns every byte into 100 copies of it
+    struct Expand<R>(R, Vec<u8>);
+
+    impl<R: Read> Read for Expand<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.1.is_empty() {
+                let mut b = [0];
+                if self.0.read(&mut b)? == 0 {
+                    return Ok(0);
+                }
+                self.1 = vec![b[0]; 100];
+            }
+            let n = buf.len().min(self.1.len());
+            buf[..n].copy_from_slice(&self.1[..n]);
+            self.1.drain(..n);
+            Ok(n)
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    header.set_cksum();
+    t!(ar.append_pax_extensions(vec![("SCHILY.compression", &b"expand"[..])]));
+    t!(ar.append_data(&mut header, "big", &b"hello"[..]));
+    let data = t!(ar.into_inner());
+    let archive = |f: &dyn Fn(&mut Archive<&[u8]>)| {
+        let mut ar = Archive::new(&data[..]);
+        ar.register_decompressor("expand", |raw| Box::new(Expand(raw, Vec::new())));
+        f(&mut ar);
+        ar
+    };
+
+    let mut ar = archive(&|ar| ar.set_max_entry_size(100));
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(entry.contents().is_err());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    assert!(archive(&|ar| ar.set_max_entry_size(100))
+        .unpack(td.path())
+        .is_err());
+    assert!(t!(fs::metadata(td.path().join("big"))).len() <= 101);
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    assert!(archive(&|ar| ar.set_max_total_size(100))
+        .unpack(td.path())
+        .is_err());
+
+    assert!(archive(&|_| {}).unpack_to_map(Some(100)).is_err());
+    let files = t!(archive(&|_| {}).unpack_to_map(Some(500)));
+    assert_eq!(files[Path::new("big")].len(), 500);
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(archive(&|ar| {
+        ar.set_max_entry_size(500);
+        ar.set_max_total_size(500);
+    })
+    .unpack(td.path()));
+    assert_eq!(t!(fs::metadata(td.path().join("big"))).len(), 500);
+}
+
+#[test]
+fn progress_with_declared_length() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1024);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "a", &[0; 1024][..]));
+    t!(ar.append_data(&mut header, "b", &[0; 1024][..]));
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len(), 4096);
+
+    let mut ar = Archive::new(Cursor::new(&data));
+    assert_eq!(ar.total_len(), None);
+    assert!(t!(t!(ar.entries()).next().unwrap()).progress().is_none());
+
+    let mut ar = Archive::new_with_length(Cursor::new(&data), data.len() as u64);
+    assert_eq!(ar.total_len(), Some(4096));
+    let progress = t!(ar.entries())
+        .map(|e| t!(e).progress().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(progress, [0.375, 0.75]);
+}
+
+#[test]
+fn append_entry_preserves_typeflag() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_old();
+    header.as_old_mut().linkflag = [0];
+    header.set_size(3);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "foo", &b"foo"[..]));
+    let original = t!(ar.into_inner());
+    assert_eq!(original[156], 0);
+
+    fn repack(data: &[u8], preserve: bool) -> Vec<u8> {
+        let mut ar = Archive::new(data);
+        let mut repacked = Builder::new(Vec::new());
+        repacked.set_preserve_typeflag(preserve);
+        for entry in t!(ar.entries()) {
+            t!(repacked.append_entry(t!(entry)));
+        }
+        t!(repacked.into_inner())
+    }
+
+    assert!(repack(&original, true) == original);
+
+    let normalized = repack(&original, false);
+    assert_eq!(normalized[156], b'0');
+    let mut ar = Archive::new(&normalized[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(entry.header().entry_type(), EntryType::Regular);
+}
+
+#[test]
+fn verify_checksums() {
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    t!(ar.verify_checksums());
+
+    let mut ar = Archive::new(Cursor::new(tar!("pax.tar")));
+    t!(ar.verify_checksums());
+
+    // Corrupt header
+    let mut data = tar!("reading_files.tar").to_vec();
+    let second = 512 + ((t!(Header::from_byte_slice(&data[..512]).entry_size()) + 511) & !511);
+    data[second as usize] ^= 1;
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let err = ar.verify_checksums().unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"));
+
+    // Missing or incomplete terminator
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "foo", &b"foo"[..]));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(Cursor::new(&data[..1024]));
+    let err = ar.verify_checksums().unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("missing its end-of-archive marker"));
+    let mut ar = Archive::new(Cursor::new(&data[..1536]));
+    let err = ar.verify_checksums().unwrap_err();
+    assert!(err.to_string().contains("incomplete end-of-archive marker"));
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    t!(ar.verify_checksums());
+
+    // Checksums computed over signed bytes are accepted too
+    let mut data = data;
+    data[100] = 0xff;
+    let signed = data[..512]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if i >= 148 && i < 156 {
+                32
+            } else {
+                *b as i8 as i32
+            }
+        })
+        .sum::<i32>();
+    data[148..156].copy_from_slice(format!("{:06o}\0 ", signed).as_bytes());
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    t!(ar.verify_checksums());
+}
+
+#[test]
+fn set_owner() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir(td.path().join("dir")));
+    t!(t!(File::create(td.path().join("dir/file"))).write_all(b"foo"));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.set_owner(0, 0, "root", "wheel");
+    t!(ar.append_dir_all("dir", td.path().join("dir")));
+    t!(ar.append_file("file", &mut t!(File::open(td.path().join("dir/file")))));
+    ar.clear_owner();
+    t!(ar.append_path_with_name(td.path().join("dir/file"), "unchanged"));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = 0;
+    for entry in t!(ar.entries()) {
+        let entry = t!(entry);
+        let header = entry.header();
+        if t!(entry.path()) == Path::new("unchanged") {
+            #[cfg(unix)]
+            {
+                use std::os::unix::prelude::*;
+                let meta = t!(fs::metadata(td.path().join("dir/file")));
+                assert_eq!(t!(header.uid()), meta.uid() as u64);
+                assert_eq!(t!(header.gid()), meta.gid() as u64);
+            }
+        } else {
+            assert_eq!(t!(header.uid()), 0);
+            assert_eq!(t!(header.gid()), 0);
+            assert_eq!(t!(header.username()), Some("root"));
+            assert_eq!(t!(header.groupname()), Some("wheel"));
+        }
+        entries += 1;
+    }
+    assert_eq!(entries, 4);
+}
+
+#[test]
+fn content_dedup() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(t!(File::create(td.path().join("a"))).write_all(b"same contents"));
+    t!(t!(File::create(td.path().join("b"))).write_all(b"same contents"));
+    t!(t!(File::create(td.path().join("c"))).write_all(b"other contents"));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.set_content_dedup(true);
+    t!(ar.append_path_with_name(td.path().join("a"), "a"));
+    t!(ar.append_path_with_name(td.path().join("b"), "b"));
+    t!(ar.append_file("c", &mut t!(File::open(td.path().join("c")))));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let a = t!(entries.next().unwrap());
+    assert_eq!(a.header().entry_type(), EntryType::Regular);
+    assert_eq!(a.size(), 13);
+    let b = t!(entries.next().unwrap());
+    assert_eq!(b.header().entry_type(), EntryType::Link);
+    assert_eq!(b.size(), 0);
+    assert_eq!(t!(b.link_name()).unwrap(), Path::new("a"));
+    let c = t!(entries.next().unwrap());
+    assert_eq!(c.header().entry_type(), EntryType::Regular);
+    assert!(entries.next().is_none());
+
+    let dst = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(Archive::new(&data[..]).unpack(dst.path()));
+    assert_eq!(t!(fs::read(dst.path().join("b"))), b"same contents");
+
+    // A file which failed to be appended isn't linked to
+    let mut ar = Builder::new(Vec::new());
+    ar.set_content_dedup(true);
+    ar.long_names(false);
+    let long = "a".repeat(150);
+    assert!(ar
+        .append_path_with_name(td.path().join("a"), &long)
+        .is_err());
+    t!(ar.append_path_with_name(td.path().join("b"), "b"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let b = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(b.header().entry_type(), EntryType::Regular);
+    assert_eq!(b.size(), 13);
+}
+
+#[test]
+fn max_entries() {
+    let mut ar = Builder::new(Vec::new());
+    for i in 0..3 {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        // Long names add an extension entry which isn't counted
+        let name = format!("{}/{}", "a".repeat(100), i);
+        t!(ar.append_data(&mut header, name, io::empty()));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_entries(3);
+    assert_eq!(t!(ar.entries()).map(|e| t!(e)).count(), 3);
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_entries(2);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    t!(entries.next().unwrap());
+    match entries.next().unwrap() {
+        Ok(_) => panic!("expected the entry limit to be exceeded"),
+        Err(e) => assert!(e.to_string().contains("more than 2 entries")),
+    }
+    assert!(entries.next().is_none());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_entries(2);
+    assert!(ar.unpack(td.path()).is_err());
+}
+
+#[test]
+fn lenient_numeric_fields() {
+    let data = tar!("decimal_size.tar");
+
+    let mut ar = Archive::new(data);
+    let mut entries = t!(ar.entries());
+    match entries.next().unwrap() {
+        Ok(_) => panic!("expected a decimal size field to be rejected"),
+        Err(e) => assert!(e.to_string().contains("non-octal digit")),
+    }
+
+    let mut ar = Archive::new(data);
+    ar.set_lenient_numeric_fields(true);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert_eq!(t!(entry.header().size()), 19);
+    let mut s = String::new();
+    t!(entry.read_to_string(&mut s));
+    assert_eq!(s, "nineteen bytes here");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn pack_and_unpack_single() {
+    let mut data = Vec::new();
+    t!(tar::pack_single(&mut data, "dir/file.txt", b"contents"));
+    let (path, contents) = t!(tar::unpack_single(&data[..]));
+    assert_eq!(path, Path::new("dir/file.txt"));
+    assert_eq!(contents, b"contents");
+
+    // Long names go through an extension entry
+    let long = format!("{}/file", "a".repeat(200));
+    let mut data = Vec::new();
+    t!(tar::pack_single(&mut data, &long, b""));
+    let (path, contents) = t!(tar::unpack_single(&data[..]));
+    assert_eq!(path, Path::new(&long));
+    assert!(contents.is_empty());
+
+    assert!(tar::unpack_single(&[0; 1024][..]).is_err());
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a", io::empty()));
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    let data = t!(ar.into_inner());
+    assert!(tar::unpack_single(&data[..]).is_err());
+}
+
+#[test]
+fn skip_leading_garbage() {
+    let mut data = b"\xef\xbb\xbf".to_vec();
+    data.extend_from_slice(&b"log line\n".repeat(33));
+    assert_eq!(data.len(), 300);
+    data.extend_from_slice(tar!("reading_files.tar"));
+
+    let mut ar = Archive::new(&data[..]);
+    assert!(t!(ar.entries()).next().unwrap().is_err());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_skip_leading_garbage(true);
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(&*a.header().path_bytes(), b"a");
+    assert_eq!(a.raw_header_position(), 300);
+    assert_eq!(a.raw_file_position(), 300 + 512);
+    let mut s = String::new();
+    t!(a.read_to_string(&mut s));
+    assert_eq!(s, "a\na\na\na\na\na\na\na\na\na\na\n");
+    let mut b = t!(entries.next().unwrap());
+    assert_eq!(&*b.header().path_bytes(), b"b");
+    s.truncate(0);
+    t!(b.read_to_string(&mut s));
+    assert_eq!(s, "b\nb\nb\nb\nb\nb\nb\nb\nb\nb\nb\n");
+    assert!(entries.next().is_none());
+
+    // Archives without any garbage are unaffected
+    let mut ar = Archive::new(tar!("reading_files.tar"));
+    ar.set_skip_leading_garbage(true);
+    assert_eq!(t!(ar.entries()).count(), 2);
+}
+
+#[test]
+fn data_slice() {
+    let data = tar!("reading_files.tar");
+
+    let mut ar = Archive::from_slice(data);
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let slice = entry.data_slice().unwrap();
+        let pos = entry.raw_file_position() as usize;
+        assert_eq!(slice.as_ptr(), data[pos..].as_ptr());
+        assert_eq!(slice.len() as u64, entry.size());
+        let mut contents = Vec::new();
+        t!(entry.read_to_end(&mut contents));
+        assert_eq!(slice, &contents[..]);
+    }
+
+    let mut ar = Archive::new(data);
+    for entry in t!(ar.entries()) {
+        assert!(t!(entry).data_slice().is_none());
+    }
+}
+
+#[test]
+fn from_slice_skips_unread_entries() {
+    let mut ar = Builder::new(Vec::new());
+    for (name, len) in &[("a", 1000), ("b", 3), ("c", 600)] {
+        let mut header = Header::new_gnu();
+        header.set_size(*len as u64);
+        t!(ar.append_data(&mut header, name, &name.repeat(*len).into_bytes()[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::from_slice(&data);
+    {
+        let mut entries = t!(ar.entries());
+        t!(entries.next().unwrap());
+        let mut b = t!(entries.next().unwrap());
+        assert_eq!(b.raw_file_position(), 4 * 512);
+        let mut contents = String::new();
+        t!(b.read_to_string(&mut contents));
+        assert_eq!(contents, "bbb");
+        let c = t!(entries.next().unwrap());
+        assert_eq!(c.data_slice().unwrap(), &b"c".repeat(600)[..]);
+        assert!(entries.next().is_none());
+    }
+    // The slice is read in place, so the reader itself is never advanced
+    assert_eq!(ar.into_inner().len(), data.len());
+
+    let mut ar = Archive::from_slice(&data[..2 * 512]);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    assert!(entries.next().unwrap().is_err());
+}
+
+#[test]
+fn builder_from_listing() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("file"), "file"));
+    let order = [
+        PathBuf::from("a"),
+        PathBuf::from("dir/file"),
+        PathBuf::from("b"),
+    ];
+
+    let mut ar = Builder::from_listing(Vec::new(), &order);
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "./a", io::empty()));
+    let err = ar
+        .append_data(&mut header, "b", io::empty())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("listing expects `dir/file`"), "{}", err);
+    t!(ar.append_path_with_name(td.path().join("file"), "dir/file"));
+    assert!(ar.finish().is_err());
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    assert!(ar.append_data(&mut header, "c", io::empty()).is_err());
+    let data = t!(ar.into_inner());
+
+    // Nothing was written for the rejected entries
+    let mut ar = Archive::new(&data[..]);
+    let paths = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, order);
+}
+
+#[test]
+fn builder_from_listing_failed_append() {
+    let long = PathBuf::from("a/".repeat(60) + "b");
+    let order = [long.clone(), PathBuf::from("c")];
+
+    let mut ar = Builder::from_listing(Vec::new(), &order);
+    ar.long_names(false);
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    // The path is next in the listing but can't be written, which leaves the
+    // listing where it was
+    assert!(ar.append_data(&mut header, &long, io::empty()).is_err());
+    let err = ar
+        .append_data(&mut header, "c", io::empty())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("listing expects"), "{}", err);
+    ar.long_names(true);
+    t!(ar.append_data(&mut header, &long, io::empty()));
+    t!(ar.append_data(&mut header, "c", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let paths = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, order);
+}
+
+#[test]
+fn allow_truncated() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "a", &b"hello"[..]));
+    header.set_size(1000);
+    t!(ar.append_data(&mut header, "b", &[b'b'; 1000][..]));
+    let mut data = t!(ar.into_inner());
+    data.truncate(3 * 512 + 300);
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack(td.path()).is_err());
+
+    // Reading the contents fails as well rather than coming up short
+    let mut ar = Archive::new(&data[..]);
+    let mut b = t!(t!(ar.entries()).nth(1).unwrap());
+    let err = b.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(!b.was_truncated());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_allow_truncated(true);
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    let mut contents = Vec::new();
+    t!(a.read_to_end(&mut contents));
+    assert_eq!(contents, b"hello");
+    assert!(!a.was_truncated());
+    let mut b = t!(entries.next().unwrap());
+    contents.truncate(0);
+    t!(b.read_to_end(&mut contents));
+    assert_eq!(contents, [b'b'; 300]);
+    assert!(b.was_truncated());
+    assert!(entries.next().is_none());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_allow_truncated(true);
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("a"))), b"hello");
+    assert_eq!(t!(fs::read(td.path().join("b"))), [b'b'; 300]);
+}
+
+#[test]
+fn truncated_archive_errors() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "a", &b"hello"[..]));
+    header.set_size(1000);
+    t!(ar.append_data(&mut header, "b", &[b'b'; 1000][..]));
+    let data = t!(ar.into_inner());
+
+    // Ending in the contents of an entry names the entry and how much of it
+    // was left
+    let truncated = &data[..3 * 512 + 300];
+    let mut ar = Archive::new(truncated);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    t!(entries.next().unwrap());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = format!("{:?}", err);
+    assert!(err.contains("`b`"), "{}", err);
+    assert!(err.contains("300 of 1000 bytes"), "{}", err);
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(truncated);
+    let err = format!("{:?}", ar.unpack(td.path()).unwrap_err());
+    assert!(err.contains("300 of 1000 bytes"), "{}", err);
+
+    // Long names are used rather than the name in the header
+    let long = "l".repeat(150);
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_data(&mut header, &long, &[b'b'; 1000][..]));
+    let long_data = t!(ar.into_inner());
+    let mut ar = Archive::new(&long_data[..3 * 512 + 300]);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    let err = format!("{:?}", entries.next().unwrap().err().unwrap());
+    assert!(err.contains(&format!("`{}`", long)), "{}", err);
+
+    // As does ending in the middle of a header
+    let truncated = &data[..2 * 512 + 100];
+    let mut ar = Archive::new(truncated);
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    let err = format!("{:?}", entries.next().unwrap().err().unwrap());
+    assert!(err.contains("offset 1024"), "{}", err);
+    assert!(err.contains("100 of 512 bytes"), "{}", err);
+}
+
+#[test]
+fn max_entry_and_total_size() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    t!(ar.append_bytes(&mut header, "a", &[b'a'; 10]));
+    t!(ar.append_bytes(&mut header, "b", &[b'b'; 20]));
+    t!(ar.append_bytes(&mut header, "c", &[b'c'; 30]));
+    let data = t!(ar.into_inner());
+
+    // Entries over the limit are never written
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_entry_size(20);
+    let err = format!("{:?}", ar.unpack(td.path()).unwrap_err());
+    assert!(err.contains("`c` is 30 bytes"), "{}", err);
+    assert!(td.path().join("b").exists());
+    assert!(!td.path().join("c").exists());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_entry_size(20);
+    let mut entries = t!(ar.entries());
+    assert_eq!(t!(t!(entries.next().unwrap()).contents()).len(), 10);
+    t!(entries.next().unwrap());
+    assert!(t!(entries.next().unwrap()).contents().is_err());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_total_size(59);
+    let err = ar.unpack(td.path()).unwrap_err();
+    assert!(err.to_string().contains("59 bytes"), "{}", err);
+    assert!(td.path().join("b").exists());
+    assert!(!td.path().join("c").exists());
+
+    // Skipped entries don't count
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_max_total_size(40);
+    ar.set_unpack_filter(|path, _| {
+        if path == Path::new("b") {
+            tar::UnpackAction::Skip
+        } else {
+            tar::UnpackAction::Extract
+        }
+    });
+    t!(ar.unpack(td.path()));
+    assert!(td.path().join("c").exists());
+}
+
+#[test]
+fn path_encoding() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.as_old_mut().name[..4].copy_from_slice(b"a\xe9.b");
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_path_encoding(tar::PathEncoding::Lossy);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.path()), Path::new("a\u{FFFD}.b"));
+    assert_eq!(&*entry.path_bytes(), b"a\xe9.b");
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_path_encoding(tar::PathEncoding::Utf8);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(entry.path().is_err());
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_path_encoding(tar::PathEncoding::Utf8);
+    assert!(ar.unpack(td.path()).is_err());
+
+    // Raw bytes are kept by default where paths can hold them
+    if cfg!(unix) {
+        let mut ar = Archive::new(&data[..]);
+        let entry = t!(t!(ar.entries()).next().unwrap());
+        assert_eq!(t!(entry.path()).to_str(), None);
+    }
+}
+
+#[test]
+fn entry_contents() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "a", &b"hello"[..]));
+    header.set_size(1000);
+    t!(ar.append_data(&mut header, "b", &[b'b'; 1000][..]));
+    let mut data = t!(ar.into_inner());
+    data.truncate(3 * 512 + 300);
+
+    let mut ar = Archive::new_with_length(&data[..], data.len() as u64);
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(t!(a.contents()), b"hello");
+    let mut b = t!(entries.next().unwrap());
+    let err = b.contents().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(err.to_string().contains("`b`"), "{}", err);
+}
+
+#[test]
+fn entry_contents_forged_size() {
+    // Just a header claiming 16 TiB of contents, read from a reader whose
+    // length isn't known
+    let mut header = Header::new_gnu();
+    t!(header.set_path("huge"));
+    header.set_size(1 << 44);
+    header.set_cksum();
+    let data = header.as_bytes().to_vec();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let err = entry.contents().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack_to_map(None).is_err());
+}
+
+#[test]
+fn repack_with_raw_headers() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+    let mut original = t!(ar.into_inner());
+    // Stash some vendor bytes in the padding at the end of the header
+    original[505..512].copy_from_slice(b"vendor!");
+    let cksum = original[..148]
+        .iter()
+        .chain(&original[156..512])
+        .fold(8 * 32, |a, b| a + *b as u32);
+    original[148..156].copy_from_slice(format!("{:06o}\0 ", cksum).as_bytes());
+
+    let mut ar = Archive::new(&original[..]);
+    let mut copy = Builder::new(Vec::new());
+    for entry in t!(ar.entries()) {
+        let entry = t!(entry);
+        let raw = *entry.header_raw_bytes();
+        t!(copy.append_raw_header(&raw, entry));
+    }
+    assert_eq!(t!(copy.into_inner()), original);
+
+    // Repaired headers still give back the bytes which were read
+    let data = tar!("decimal_size.tar");
+    let mut ar = Archive::new(data);
+    ar.set_lenient_numeric_fields(true);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(&entry.header_raw_bytes()[..], &data[..512]);
+    assert!(entry.header().as_bytes()[..] != data[..512]);
+}
+
+#[test]
+fn append_recomputes_stale_checksum() {
+    let mut header = Header::new_gnu();
+    t!(header.set_path("foo"));
+    header.set_size(3);
+    header.set_cksum();
+    // Changed after the checksum was computed
+    header.set_mode(0o600);
+    header.set_mtime(1_000_000);
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append(&header, &b"foo"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    t!(ar.verify_checksums());
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.header().mode()), 0o600);
+    assert_eq!(t!(entry.header().mtime()), 1_000_000);
+}
+
+#[test]
+fn resume_unpack() {
+    let mut ar = Builder::new(Vec::new());
+    for name in &["a", "b", "c", "d"] {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(1);
+        t!(ar.append_data(&mut header, name, name.as_bytes()));
+    }
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = td.path().join("dst");
+    let marker = td.path().join("marker");
+    // A non-empty directory in the way of `c` interrupts the extraction
+    t!(fs::create_dir_all(dst.join("c/blocker")));
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_resume_marker(&marker);
+    assert!(ar.unpack(&dst).is_err());
+    assert_eq!(t!(fs::read_to_string(&marker)), "2\n");
+    assert!(!dst.join("d").exists());
+
+    // Entries which were already unpacked aren't unpacked again
+    t!(fs::write(dst.join("a"), "changed"));
+    t!(fs::remove_dir_all(dst.join("c")));
+    let mut ar = Archive::new(&data[..]);
+    ar.set_resume_marker(&marker);
+    t!(ar.unpack(&dst));
+    assert!(!marker.exists());
+    assert_eq!(t!(fs::read_to_string(dst.join("a"))), "changed");
+    assert_eq!(t!(fs::read_to_string(dst.join("c"))), "c");
+    assert_eq!(t!(fs::read_to_string(dst.join("d"))), "d");
+
+    let dst = td.path().join("dst2");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_resume_from(3);
+    t!(ar.unpack(&dst));
+    assert!(!dst.join("c").exists());
+    assert!(dst.join("d").exists());
+}
+
+#[test]
+fn unpack_filter() {
+    let mut ar = Builder::new(Vec::new());
+    for name in &["keep", "node_modules/dep", "old/name", "escape"] {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(1);
+        t!(ar.append_data(&mut header, name, &b"x"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_filter(|path, entry| {
+        assert_eq!(entry.size(), 1);
+        if path.starts_with("node_modules") {
+            UnpackAction::Skip
+        } else if path == Path::new("old/name") {
+            UnpackAction::RenameTo(PathBuf::from("new/name"))
+        } else if path == Path::new("escape") {
+            UnpackAction::RenameTo(PathBuf::from("../escaped"))
+        } else {
+            UnpackAction::Extract
+        }
+    });
+    t!(ar.unpack(td.path()));
+
+    assert!(td.path().join("keep").is_file());
+    assert!(!td.path().join("node_modules").exists());
+    assert!(!td.path().join("old").exists());
+    assert_eq!(t!(fs::read(td.path().join("new/name"))), b"x");
+    assert!(!td.path().join("escape").exists());
+    assert!(!td.path().parent().unwrap().join("escaped").exists());
+}
+
+#[test]
+fn unpack_hasher() {
+    use std::sync::{Arc, Mutex};
+
+    // Uses the contents themselves as the digest
+    #[derive(Default)]
+    struct Collect(Vec<u8>);
+
+    impl EntryHasher for Collect {
+        fn update(&mut self, data: &[u8]) {
+            self.0.extend_from_slice(data);
+        }
+
+        fn finish(self: Box<Self>) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let digests = Arc::new(Mutex::new(Vec::new()));
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    let d = digests.clone();
+    ar.set_unpack_hasher(Collect::default, move |path, digest| {
+        d.lock().unwrap().push((path.to_path_buf(), digest));
+    });
+    ar.set_unpack_filter(|path, _| {
+        if path == Path::new("sparse_end.txt") {
+            UnpackAction::Skip
+        } else {
+            UnpackAction::Extract
+        }
+    });
+    t!(ar.unpack(td.path()));
+
+    let digests = digests.lock().unwrap();
+    let names = digests
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["sparse_begin.txt", "sparse_ext.txt", "sparse.txt"]);
+    for (path, digest) in digests.iter() {
+        assert!(path.starts_with(td.path()));
+        assert_eq!(*digest, t!(fs::read(path)));
+    }
+}
+
+#[test]
+fn parentdir_policy() {
+    let mut data = Vec::new();
+    {
+        let mut ar = Builder::new(&mut data);
+        for path in &["ok", "some/../../evil"] {
+            let mut header = Header::new_gnu();
+            t!(header.set_path("x"));
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_mode(0o644);
+            header.set_size(1);
+            header.set_cksum();
+            t!(ar.append(&header, &b"x"[..]));
+        }
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = td.path().join("skip");
+    t!(Archive::new(&data[..]).unpack(&dst));
+    assert!(dst.join("ok").is_file());
+    assert!(!dst.join("some").exists());
+    assert!(!td.path().join("evil").exists());
+
+    let dst = td.path().join("error");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_parentdir_policy(ParentDirPolicy::Error);
+    let err = ar.unpack(&dst).unwrap_err();
+    assert!(err.to_string().contains("some/../../evil"), "{}", err);
+    assert!(!td.path().join("evil").exists());
+
+    // Reading into memory follows the same policy
+    let mut ar = Archive::new(&data[..]);
+    ar.set_parentdir_policy(ParentDirPolicy::Error);
+    let err = ar.unpack_to_map(None).unwrap_err();
+    assert!(err.to_string().contains("some/../../evil"), "{}", err);
+    let mut ar = Archive::new(&data[..]);
+    ar.set_parentdir_policy(ParentDirPolicy::Strip);
+    let files = t!(ar.unpack_to_map(None));
+    assert_eq!(files[Path::new("some/evil")], b"x");
+
+    let dst = td.path().join("strip");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_parentdir_policy(ParentDirPolicy::Strip);
+    t!(ar.unpack(&dst));
+    assert!(dst.join("ok").is_file());
+    assert_eq!(t!(fs::read(dst.join("some/evil"))), b"x");
+    assert!(!td.path().join("evil").exists());
+}
+
+#[test]
+fn entry_by_path() {
+    let mut ar = Builder::new(Vec::new());
+    for name in &["a", "b/c", "d"] {
+        let mut header = Header::new_gnu();
+        header.set_size(600);
+        let contents = vec![name.as_bytes()[0]; 600];
+        t!(ar.append_data(&mut header, name, &contents[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let mut entry = t!(ar.entry_by_path("./b/c")).unwrap();
+    assert_eq!(&*t!(entry.path()), Path::new("b/c"));
+    let mut contents = Vec::new();
+    t!(entry.read_to_end(&mut contents));
+    assert_eq!(contents, vec![b'b'; 600]);
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    assert!(t!(ar.entry_by_path("e")).is_none());
+}
+
+#[test]
+fn index_and_entry_at() {
+    let long = "long/".repeat(30) + "name";
+    let mut ar = Builder::new(Vec::new());
+    for (name, size) in &[("a", 600), (&long[..], 10), ("b", 0)] {
+        let mut header = Header::new_gnu();
+        header.set_size(*size as u64);
+        let contents = vec![name.as_bytes()[0]; *size];
+        t!(ar.append_data(&mut header, name, &contents[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let index = t!(ar.index());
+    assert_eq!(index.len(), 3);
+    assert_eq!(index[0].path_bytes(), b"a");
+    assert_eq!(index[0].header_offset(), 0);
+    assert_eq!(index[0].data_offset(), 512);
+    assert_eq!(index[0].size(), 600);
+    assert_eq!(&*t!(index[1].path()), Path::new(&long));
+    assert_eq!(index[1].header_offset(), 5 * 512);
+    assert_eq!(index[1].data_offset(), 6 * 512);
+    assert_eq!(index[2].header_offset(), 7 * 512);
+
+    for item in index.iter().rev() {
+        let mut entry = t!(ar.entry_at(item));
+        assert_eq!(t!(entry.path()), t!(item.path()));
+        let mut contents = Vec::new();
+        t!(entry.read_to_end(&mut contents));
+        assert_eq!(contents.len() as u64, item.size());
+        assert!(contents.iter().all(|b| *b == item.path_bytes()[0]));
+    }
+
+    // Indexing again starts from the beginning
+    assert_eq!(t!(ar.index()).len(), 3);
+}
+
+#[test]
+#[cfg(feature = "flate2")]
+fn gzip_round_trip() {
+    let mut ar = Builder::new_gz(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "a", &b"hello"[..]));
+    let data = t!(t!(ar.into_inner()).finish());
+    assert_eq!(tar::detect_format(&data), Some(CompressionFormat::Gzip));
+
+    let mut ar = Archive::new_gz(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert_eq!(&*t!(entry.path()), Path::new("a"));
+    let mut contents = String::new();
+    t!(entry.read_to_string(&mut contents));
+    assert_eq!(contents, "hello");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+#[cfg(feature = "flate2")]
+fn gzip_multiple_members() {
+    // simple.tar compressed as two gzip members, the first holding only the
+    // entry `a`, like pigz or concatenating `.tar.gz` files produces
+    let mut ar = Archive::new_gz(tar!("multi_member.tar.gz"));
+    let paths = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [Path::new("a"), Path::new("b"), Path::new("c")]);
+}
+
+#[test]
+fn checksum_policy() {
+    let mut ar = Builder::new(Vec::new());
+    for name in &["a", "b"] {
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        t!(ar.append_data(&mut header, name, name.as_bytes()));
+    }
+    let mut data = t!(ar.into_inner());
+    // Damage the mtime of the first header without fixing up its checksum
+    data[136] = b'7';
+
+    let mut ar = Archive::new(&data[..]);
+    assert!(t!(ar.entries()).next().unwrap().is_err());
+
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen2 = seen.clone();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_checksum_policy(ChecksumPolicy::Warn);
+    ar.set_checksum_mismatch_handler(move |pos, header| {
+        seen2
+            .lock()
+            .unwrap()
+            .push((pos, header.path_bytes().into_owned()));
+    });
+    let bad = t!(ar.entries())
+        .map(|e| t!(e).has_bad_checksum())
+        .collect::<Vec<_>>();
+    assert_eq!(bad, [true, false]);
+    assert_eq!(*seen.lock().unwrap(), [(0, b"a".to_vec())]);
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_checksum_policy(ChecksumPolicy::Ignore);
+    assert_eq!(t!(ar.entries()).count(), 2);
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    ar.set_checksum_policy(ChecksumPolicy::Ignore);
+    assert!(ar.verify_checksums().is_err());
+}
+
+#[test]
+fn ignore_zeros_between_members() {
+    let member = |name: &str| {
+        let mut ar = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        t!(ar.append_data(&mut header, name, &b"x"[..]));
+        t!(ar.into_inner())
+    };
+    let mut data = member("a");
+    // Extra padding after the end-of-archive marker of the first member
+    data.extend(vec![0; 20 * 512]);
+    data.extend(member("b"));
+    data.extend(vec![0; 3 * 512]);
+
+    let names = |ar: &mut Archive<&[u8]>| {
+        t!(ar.entries())
+            .map(|e| t!(t!(e).path()).into_owned())
+            .collect::<Vec<_>>()
+    };
+    let mut ar = Archive::new(&data[..]);
+    assert_eq!(names(&mut ar), [Path::new("a")]);
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_ignore_zeros(true);
+    assert_eq!(names(&mut ar), [Path::new("a"), Path::new("b")]);
+}
+
+#[test]
+fn into_inner_writes_trailer() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    assert_eq!(ar.get_ref().len(), 2 * 512);
+    t!(ar.get_mut().flush());
+
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len(), 4 * 512);
+    assert!(data[2 * 512..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn abort_and_panic_skip_trailer() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    assert_eq!(ar.abort().len(), 2 * 512);
+
+    let data = std::sync::Mutex::new(Vec::new());
+    let result = std::panic::catch_unwind(|| {
+        let mut guard = data.lock().unwrap();
+        let mut ar = Builder::new(&mut *guard);
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        ar.append_data(&mut header, "a", &b"a"[..]).unwrap();
+        panic!("failed to build the archive");
+    });
+    assert!(result.is_err());
+    let data = data.into_inner().unwrap_or_else(|e| e.into_inner());
+    assert_eq!(data.len(), 2 * 512);
+
+    // Dropping the builder only finishes the archive when asked to
+    let mut data = Vec::new();
+    let mut ar = Builder::new(&mut data);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    drop(ar);
+    assert_eq!(data.len(), 2 * 512);
+
+    let mut data = Vec::new();
+    let mut ar = Builder::new(&mut data);
+    ar.set_finish_on_drop(true);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    drop(ar);
+    assert_eq!(data.len(), 4 * 512);
+}
+
+#[test]
+fn finish_twice_and_append_after_finish() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    t!(ar.finish());
+    let len = ar.get_ref().len();
+    assert_eq!(len, 4 * 512);
+    t!(ar.finish());
+    assert_eq!(ar.get_ref().len(), len);
+
+    assert!(ar.append_data(&mut header, "b", &b"b"[..]).is_err());
+    assert!(ar.append(&header, &b"b"[..]).is_err());
+    assert!(ar.append_dir("dir", ".").is_err());
+    assert!(ar
+        .append_pax_extensions(vec![("comment", &b"x"[..])])
+        .is_err());
+    assert_eq!(t!(ar.into_inner()).len(), len);
+}
+
+#[test]
+fn entries_raw() {
+    let long_path = "p".repeat(150);
+    let long_target = "t/".repeat(100);
+
+    let mut ar = Builder::new(Vec::new());
+    let global = b"20 comment=globally\n";
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XGlobalHeader);
+    header.set_size(global.len() as u64);
+    t!(ar.append_data(&mut header, "pax_global_header", &global[..]));
+    t!(ar.append_pax_extensions(vec![("path", &b"renamed"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, &long_path, &long_target));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = Vec::new();
+    for entry in t!(ar.entries_raw()) {
+        let mut entry = t!(entry);
+        let contents = t!(entry.contents());
+        entries.push((
+            entry.header().entry_type(),
+            entry.path_bytes().into_owned(),
+            contents,
+        ));
+    }
+    let types = entries.iter().map(|e| e.0).collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [
+            EntryType::XGlobalHeader,
+            EntryType::XHeader,
+            EntryType::Regular,
+            EntryType::GNULongName,
+            EntryType::GNULongLink,
+            EntryType::Symlink,
+        ]
+    );
+    assert_eq!(entries[0].2, global);
+    assert_eq!(entries[1].2, b"16 path=renamed\n");
+    // Nothing the extension entries describe is applied
+    assert_eq!(entries[2].1, b"a");
+    assert_eq!(entries[3].2, format!("{}\0", long_path).as_bytes());
+    assert_eq!(entries[4].2, format!("{}\0", long_target).as_bytes());
+    assert_ne!(entries[5].1, long_path.as_bytes());
+
+    // The same archive read normally only has the two entries
+    let mut ar = Archive::new(&data[..]);
+    let paths = t!(ar.entries())
+        .map(|e| t!(e).path_bytes().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [b"renamed".to_vec(), long_path.into_bytes()]);
+}
+
+#[test]
+fn append_link_with_long_names() {
+    let long_path = "p".repeat(150);
+    let long_target = "t/".repeat(100);
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, &long_path, &long_target));
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "after", &b"a"[..]));
+    let data = t!(ar.into_inner());
+
+    // Each extension entry is a header followed by its padded name
+    let mut ar = Archive::from_slice(&data);
+    let entries = t!(t!(ar.entries()).raw(true).collect::<Result<Vec<_>, _>>());
+    assert_eq!(entries.len(), 4);
+    assert!(entries[0].header().entry_type().is_gnu_longname());
+    assert_eq!(t!(entries[0].header().entry_size()), 151);
+    assert!(entries[1].header().entry_type().is_gnu_longlink());
+    assert_eq!(t!(entries[1].header().entry_size()), 201);
+    assert_eq!(entries[1].raw_header_position(), 2 * 512);
+    assert_eq!(entries[2].raw_header_position(), 4 * 512);
+    let name = entries[1].data_slice().unwrap();
+    assert_eq!(name, format!("{}\0", long_target).as_bytes());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let link = t!(entries.next().unwrap());
+    assert_eq!(&*link.path_bytes(), long_path.as_bytes());
+    assert_eq!(&*link.link_name_bytes().unwrap(), long_target.as_bytes());
+    assert_eq!(&*t!(entries.next().unwrap()).path_bytes(), b"after");
+
+    let mut ar = Builder::new(Vec::new());
+    ar.long_names(false);
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    assert!(ar.append_link(&mut header, "link", &long_target).is_err());
+}
+
+#[test]
+fn append_link_failure() {
+    let long_path = "p".repeat(150);
+
+    // The long name entry isn't written when the target turns out to be
+    // invalid
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    assert!(ar.append_link(&mut header, &long_path, "").is_err());
+    let data = t!(ar.into_inner());
+    assert_eq!(data, vec![0; 1024]);
+
+    // A stale size in the header isn't written out for the link
+    let mut ar = Builder::new(Vec::new());
+    header.set_size(100);
+    t!(ar.append_link(&mut header, "link", "target"));
+    let data = t!(ar.into_inner());
+    assert_eq!(data.len(), 512 * 3);
+    let mut ar = Archive::new(&data[..]);
+    let link = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(link.header().size()), 0);
+}
+
+#[test]
+fn rewrite_entry_headers() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mtime(123456);
+    header.set_uid(1);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+    let original = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&original[..]);
+    let mut normalized = Builder::new(Vec::new());
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let raw = *entry.header_raw_bytes();
+        entry.header_mut().set_mtime(0);
+        entry.header_mut().set_uid(1000);
+        assert_eq!(entry.header_raw_bytes(), &raw);
+        let header = entry.header().clone();
+        t!(normalized.append(&header, &mut entry));
+    }
+    let data = t!(normalized.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.header().mtime()), 0);
+    assert_eq!(t!(entry.header().uid()), 1000);
+    let mut contents = String::new();
+    t!(entry.read_to_string(&mut contents));
+    assert_eq!(contents, "foo");
+}
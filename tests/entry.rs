@@ -3,6 +3,7 @@ extern crate tempfile;
 
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use tempfile::Builder;
 
@@ -98,6 +99,65 @@ fn relative_hardlink() {
     t!(td.path().join("bar").metadata());
 }
 
+#[test]
+fn hardlink_before_target() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(header.set_path("bar"));
+    t!(header.set_link_name("foo"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("foo"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let err = ar.unpack(td.path()).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("hasn't been unpacked"),
+        "{:?}",
+        err
+    );
+    assert!(!td.path().join("bar").exists());
+}
+
+#[test]
+fn unpack_in_path() {
+    let mut ar = tar::Builder::new(Vec::new());
+    for path in &["./a/b", "a/../../c"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        t!(header.set_path("x"));
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    let mut entries = t!(ar.entries());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut entry = t!(entries.next().unwrap());
+    let path = t!(entry.unpack_in_path(td.path())).unwrap();
+    assert_eq!(path, td.path().join("a/b"));
+    assert!(path.is_file());
+
+    let mut entry = t!(entries.next().unwrap());
+    assert!(t!(entry.unpack_in_path(td.path())).is_none());
+}
+
 #[test]
 fn absolute_link_deref_error() {
     let mut ar = tar::Builder::new(Vec::new());
@@ -180,6 +240,91 @@ fn directory_maintains_permissions() {
     assert_eq!(md.permissions().mode(), 0o40777);
 }
 
+#[test]
+#[cfg(unix)]
+fn archived_modes_applied_on_unpack() {
+    use ::std::os::unix::fs::PermissionsExt;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, kind, mode) in &[
+        ("dir", tar::EntryType::Directory, 0o750),
+        ("dir/file", tar::EntryType::Regular, 0o640),
+        ("dir/setuid", tar::EntryType::Regular, 0o4755),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(kind);
+        t!(header.set_path(path));
+        header.set_mode(mode);
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mode = |path: &Path| t!(path.metadata()).permissions().mode() & 0o7777;
+
+    // Permission bits are applied by default, special bits only when
+    // permissions are preserved
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    t!(ar.unpack(td.path()));
+    assert_eq!(mode(&td.path().join("dir")), 0o750);
+    assert_eq!(mode(&td.path().join("dir/file")), 0o640);
+    assert_eq!(mode(&td.path().join("dir/setuid")), 0o755);
+
+    // The mask applies on top of preserved permissions
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_permissions(true);
+    ar.set_mask(0o7027);
+    t!(ar.unpack(td.path()));
+    assert_eq!(mode(&td.path().join("dir")), 0o750);
+    assert_eq!(mode(&td.path().join("dir/file")), 0o640);
+    assert_eq!(mode(&td.path().join("dir/setuid")), 0o750);
+}
+
+#[test]
+#[cfg(unix)]
+fn default_modes_for_mode_zero() {
+    use ::std::os::unix::fs::PermissionsExt;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, kind, mode) in &[
+        ("dir", tar::EntryType::Directory, 0),
+        ("dir/zero", tar::EntryType::Regular, 0),
+        ("dir/private", tar::EntryType::Regular, 0o600),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(kind);
+        t!(header.set_path(path));
+        header.set_mode(mode);
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mode = |path: &Path| t!(path.metadata()).permissions().mode() & 0o7777;
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_permissions(true);
+    ar.set_default_file_mode(0o644);
+    ar.set_default_dir_mode(0o755);
+    t!(ar.unpack(td.path()));
+    assert_eq!(mode(&td.path().join("dir")), 0o755);
+    assert_eq!(mode(&td.path().join("dir/zero")), 0o644);
+    assert_eq!(mode(&td.path().join("dir/private")), 0o600);
+
+    // Without defaults the archived mode is applied as is
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_permissions(true);
+    ar.set_default_dir_mode(0o755);
+    t!(ar.unpack(td.path()));
+    assert_eq!(mode(&td.path().join("dir/zero")), 0);
+}
+
 #[test]
 #[cfg(not(windows))] // dangling symlinks have weird permissions
 fn modify_link_just_created() {
@@ -347,3 +492,612 @@ fn modify_symlink_just_created() {
     t!(t!(File::open(&test)).read_to_end(&mut contents));
     assert_eq!(contents.len(), 0);
 }
+
+#[test]
+fn file_type() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let kinds = [
+        ("file", tar::EntryType::Regular),
+        ("dir/", tar::EntryType::Directory),
+        ("link", tar::EntryType::Symlink),
+        ("hard", tar::EntryType::Link),
+        ("fifo", tar::EntryType::Fifo),
+        ("char", tar::EntryType::Char),
+    ];
+    for &(path, kind) in kinds.iter() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(kind);
+        t!(header.set_path(path));
+        if kind.is_symlink() || kind.is_hard_link() {
+            t!(header.set_link_name("file"));
+        }
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+
+    // Old-style archives mark directories with a trailing slash.
+    let mut header = tar::Header::new_old();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("olddir/"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    let types = t!(ar.entries())
+        .map(|e| {
+            let ft = t!(e).file_type();
+            (ft.is_file(), ft.is_dir(), ft.is_symlink())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [
+            (true, false, false),
+            (false, true, false),
+            (false, false, true),
+            (false, false, false),
+            (false, false, false),
+            (false, false, false),
+            (false, true, false),
+        ]
+    );
+}
+
+#[test]
+fn unknown_type_policy() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Fifo);
+    t!(header.set_path("fifo"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_entry_type(tar::EntryType::new(b'Z'));
+    t!(header.set_path("unknown"));
+    header.set_cksum();
+    t!(ar.append(&header, &b"foo"[..]));
+
+    let bytes = t!(ar.into_inner());
+
+    // By default such entries are skipped
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen2 = seen.clone();
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_unknown_type_handler(move |pos, header| {
+        seen2.lock().unwrap().push((pos, header.entry_type()));
+    });
+    t!(ar.unpack(td.path()));
+    assert!(td.path().join("fifo").symlink_metadata().is_err());
+    assert!(td.path().join("unknown").symlink_metadata().is_err());
+    assert_eq!(*seen.lock().unwrap(), [(512, tar::EntryType::new(b'Z'))]);
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_unknown_type_policy(tar::UnknownTypePolicy::Error);
+    assert!(ar.unpack(td.path()).is_err());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_unknown_type_policy(tar::UnknownTypePolicy::ExtractAsFile);
+    t!(ar.unpack(td.path()));
+    assert!(t!(td.path().join("fifo").metadata()).is_file());
+    let mut contents = String::new();
+    t!(t!(File::open(td.path().join("unknown"))).read_to_string(&mut contents));
+    assert_eq!(contents, "foo");
+}
+
+#[test]
+#[cfg(unix)]
+fn preserve_ownerships() {
+    use std::os::unix::prelude::*;
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let meta = t!(td.path().metadata());
+    let (uid, gid) = (meta.uid() as u64, meta.gid() as u64);
+
+    let archive = |uid: u64, gid: u64, uname: &str| {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_uid(uid);
+        header.set_gid(gid);
+        t!(header.set_username(uname));
+        t!(header.set_groupname(uname));
+        t!(ar.append_data(&mut header, "foo", &b"foo"[..]));
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Directory);
+        t!(ar.append_data(&mut header, "bar", &[][..]));
+        t!(ar.into_inner())
+    };
+
+    // Names which don't exist fall back to the numeric ids, and anyone can
+    // give their own files to themselves
+    let bytes = archive(uid, gid, "tar-rs-no-such-user");
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    t!(ar.unpack(td.path().join("a")));
+    for path in &["a/foo", "a/bar"] {
+        let meta = t!(td.path().join(path).symlink_metadata());
+        assert_eq!((meta.uid() as u64, meta.gid() as u64), (uid, gid));
+    }
+
+    // Giving files away requires root
+    if uid != 0 {
+        return;
+    }
+    let bytes = archive(12345, 23456, "root");
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    t!(ar.unpack(td.path().join("b")));
+    let meta = t!(td.path().join("b/foo").metadata());
+    assert_eq!((meta.uid(), meta.gid()), (12345, 23456));
+
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    t!(ar.unpack(td.path().join("c")));
+    let meta = t!(td.path().join("c/foo").metadata());
+    assert_eq!((meta.uid(), meta.gid()), (0, 0));
+}
+
+#[test]
+#[cfg(unix)]
+fn unpack_special_files() {
+    use std::os::unix::prelude::*;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Fifo);
+    header.set_mode(0o640);
+    header.set_mtime(1_000_000_000);
+    t!(header.set_path("fifo"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+    let bytes = t!(ar.into_inner());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_unpack_special_files(true);
+    t!(ar.unpack(td.path()));
+    let meta = t!(td.path().join("fifo").symlink_metadata());
+    assert!(meta.file_type().is_fifo());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    assert_eq!(meta.mtime(), 1_000_000_000);
+
+    // Unpacking again replaces the existing FIFO
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_unpack_special_files(true);
+    t!(ar.unpack(td.path()));
+    assert!(t!(td.path().join("fifo").symlink_metadata())
+        .file_type()
+        .is_fifo());
+}
+
+#[test]
+#[cfg(unix)] // hard linking a directory always fails on unix
+fn hardlink_fallback() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Directory);
+    t!(header.set_path("dir"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(header.set_path("sub/link"));
+    t!(header.set_link_name("dir"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let bytes = t!(ar.into_inner());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    assert!(ar.unpack(td.path()).is_err());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_hardlink_fallback(tar::HardlinkFallback::Symlink);
+    t!(ar.unpack(td.path()));
+    let link = td.path().join("sub/link");
+    assert_eq!(t!(link.read_link()), std::path::Path::new("../dir"));
+    assert!(t!(link.metadata()).is_dir());
+}
+
+#[test]
+#[cfg(unix)]
+fn hardlink_fallback_copy() {
+    use std::os::unix::prelude::*;
+
+    // Hard links can't span filesystems, so this needs a second one
+    let src_dir = match Builder::new().prefix("tar").tempdir_in("/dev/shm") {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    if t!(src_dir.path().metadata()).dev() == t!(td.path().metadata()).dev() {
+        return;
+    }
+    let src = src_dir.path().join("src");
+    t!(std::fs::write(&src, b"foo"));
+
+    let mut ar = tar::Builder::new(Vec::new());
+    for (path, target) in [
+        ("link", src.clone()),
+        ("missing", src_dir.path().join("nope")),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Link);
+        t!(header.set_path(path));
+        t!(header.set_link_name(&target));
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_hardlink_fallback(tar::HardlinkFallback::Copy);
+    let mut entries = t!(ar.entries());
+    let mut link = t!(entries.next().unwrap());
+    t!(link.unpack(td.path().join("link")));
+    let meta = t!(td.path().join("link").symlink_metadata());
+    assert!(meta.file_type().is_file());
+    assert_eq!(meta.nlink(), 1);
+    assert_eq!(t!(std::fs::read(td.path().join("link"))), b"foo");
+
+    // A missing target isn't papered over with a dangling symlink
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_hardlink_fallback(tar::HardlinkFallback::Symlink);
+    let mut missing = t!(t!(ar.entries()).nth(1).unwrap());
+    let err = missing.unpack(td.path().join("missing")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(td.path().join("missing").symlink_metadata().is_err());
+}
+
+#[test]
+#[cfg(unix)] // making symlinks on windows is hard
+fn relative_symlink_with_parent_dir_target() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("lib/libfoo.so.1"));
+    header.set_cksum();
+    t!(ar.append(&header, &b"foo"[..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(header.set_path("bin/libfoo.so"));
+    t!(header.set_link_name("../lib/libfoo.so.1"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    t!(ar.unpack(td.path()));
+
+    let link = td.path().join("bin/libfoo.so");
+    assert_eq!(
+        t!(link.read_link()),
+        std::path::Path::new("../lib/libfoo.so.1")
+    );
+    let mut contents = String::new();
+    t!(t!(File::open(&link)).read_to_string(&mut contents));
+    assert_eq!(contents, "foo");
+}
+
+#[test]
+fn unpack_preallocate() {
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(2048);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("foo"));
+    header.set_cksum();
+    t!(ar.append(&header, &[1; 2048][..]));
+
+    // Cut the archive off partway through the contents so the file size on
+    // disk shows whether it was sized before the data was written.
+    let bytes = t!(ar.into_inner());
+    let bytes = &bytes[..512 + 100];
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(bytes);
+    assert!(ar.unpack(td.path().join("a")).is_err());
+    assert_eq!(t!(td.path().join("a/foo").metadata()).len(), 100);
+
+    let mut ar = tar::Archive::new(bytes);
+    ar.set_unpack_preallocate(true);
+    assert!(ar.unpack(td.path().join("b")).is_err());
+    assert_eq!(t!(td.path().join("b/foo").metadata()).len(), 2048);
+}
+
+#[test]
+#[cfg(windows)]
+fn windows_reserved_names() {
+    fn archive(path: &str) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_entry_type(tar::EntryType::Regular);
+        let name = &mut header.as_old_mut().name;
+        name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        t!(ar.append(&header, &b"foo"[..]));
+        t!(ar.into_inner())
+    }
+
+    let cases = [
+        ("CON", "CO%4E"),
+        ("prn.txt", "pr%6E.txt"),
+        ("Aux", "Au%78"),
+        ("NUL.tar.gz", "NU%4C.tar.gz"),
+        ("COM1", "COM%31"),
+        ("COM9", "COM%39"),
+        ("LPT1", "LPT%31"),
+        ("lpt9.log", "lpt%39.log"),
+        ("foo.", "foo%2E"),
+        ("foo ", "foo%20"),
+        ("a:b", "a%3Ab"),
+        ("what?*", "what%3F%2A"),
+        ("dir/CON/file", "dir/CO%4E/file"),
+    ];
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    for &(name, sanitized) in cases.iter() {
+        let bytes = archive(name);
+
+        let mut ar = tar::Archive::new(&bytes[..]);
+        assert!(ar.unpack(td.path().join("error")).is_err(), "{}", name);
+
+        let mut ar = tar::Archive::new(&bytes[..]);
+        ar.set_windows_name_policy(tar::WindowsNamePolicy::Skip);
+        t!(ar.unpack(td.path().join("skip")));
+
+        let mut ar = tar::Archive::new(&bytes[..]);
+        ar.set_windows_name_policy(tar::WindowsNamePolicy::Sanitize);
+        t!(ar.unpack(td.path().join("sanitize")));
+        let mut s = String::new();
+        t!(t!(File::open(td.path().join("sanitize").join(sanitized))).read_to_string(&mut s));
+        assert_eq!(s, "foo");
+    }
+    assert!(t!(std::fs::read_dir(td.path().join("skip")))
+        .next()
+        .is_none());
+
+    // Names which look like they were sanitized already don't collide with
+    // the sanitized names
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, data) in [("a?", "1"), ("a%3F", "2"), ("CON", "3"), ("CON_", "4")].iter() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        t!(ar.append(&header, data.as_bytes()));
+    }
+    let bytes = t!(ar.into_inner());
+    let dst = td.path().join("collisions");
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_windows_name_policy(tar::WindowsNamePolicy::Sanitize);
+    t!(ar.unpack(&dst));
+    for &(path, data) in [
+        ("a%3F", "1"),
+        ("a%253F", "2"),
+        ("CO%4E", "3"),
+        ("CON_", "4"),
+    ]
+    .iter()
+    {
+        assert_eq!(t!(std::fs::read_to_string(dst.join(path))), data);
+    }
+}
+
+#[test]
+fn sanitized_relative_path() {
+    let mut ar = tar::Builder::new(Vec::new());
+    for path in ["///test", "a/../b", "././c", "d//e/./f", "/", ".."].iter() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, format!("{}/./g", "x".repeat(200)), &[][..]));
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    let paths = t!(ar.entries())
+        .map(|entry| t!(entry).sanitized_relative_path())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            Some(PathBuf::from("test")),
+            None,
+            Some(PathBuf::from("c")),
+            Some(Path::new("d").join("e").join("f")),
+            Some(PathBuf::new()),
+            None,
+            Some(Path::new(&"x".repeat(200)).join("g")),
+        ]
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn secure_extraction() {
+    fn archive(entries: &[(&str, tar::EntryType, &str)]) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        for &(path, kind, link_or_data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            t!(header.set_path(path));
+            let data = if kind == tar::EntryType::Regular {
+                link_or_data.as_bytes()
+            } else if kind == tar::EntryType::Directory {
+                header.set_mode(0o777);
+                &[][..]
+            } else {
+                t!(header.set_link_name(link_or_data));
+                &[][..]
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            t!(ar.append(&header, data));
+        }
+        t!(ar.into_inner())
+    }
+
+    fn unpack(bytes: &[u8], dst: &Path) -> std::io::Result<()> {
+        let mut ar = tar::Archive::new(bytes);
+        ar.set_secure_extraction(true);
+        ar.unpack(dst)
+    }
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let dst = td.path().join("dst");
+    let outside = td.path().join("outside");
+    t!(std::fs::create_dir(&dst));
+    t!(std::fs::create_dir(&outside));
+    t!(std::fs::write(outside.join("secret"), "secret"));
+
+    let outside_meta = t!(std::fs::metadata(&outside));
+
+    use tar::EntryType::{Directory, Link, Regular, Symlink};
+    let escapes = [
+        archive(&[("up", Symlink, "../outside"), ("up/pwned", Regular, "x")]),
+        archive(&[
+            ("abs", Symlink, outside.to_str().unwrap()),
+            ("abs/pwned", Regular, "x"),
+        ]),
+        archive(&[("up", Symlink, "../outside"), ("hl", Link, "up/secret")]),
+        archive(&[("ln", Symlink, "../outside/secret"), ("hl", Link, "ln")]),
+        // A directory entry over a symlink mustn't change the symlink's target
+        archive(&[("d", Symlink, "../outside"), ("d/", Directory, "")]),
+        archive(&[
+            ("d", Symlink, outside.to_str().unwrap()),
+            ("d/", Directory, ""),
+        ]),
+    ];
+    for bytes in escapes.iter() {
+        assert!(unpack(bytes, &dst).is_err());
+        let meta = t!(std::fs::metadata(&outside));
+        assert_eq!(meta.permissions(), outside_meta.permissions());
+        assert_eq!(t!(meta.modified()), t!(outside_meta.modified()));
+        assert!(!outside.join("pwned").exists());
+        assert_eq!(t!(std::fs::read_dir(&outside)).count(), 1);
+        t!(std::fs::remove_dir_all(&dst));
+        t!(std::fs::create_dir(&dst));
+    }
+
+    // Links which stay inside of the destination keep working
+    let bytes = archive(&[
+        ("a/b/c/file", Regular, "file"),
+        ("lnk", Symlink, "a/b"),
+        ("lnk/c/other", Regular, "other"),
+        ("hl", Link, "lnk/c/file"),
+    ]);
+    t!(unpack(&bytes, &dst));
+    assert_eq!(t!(std::fs::read(dst.join("a/b/c/other"))), b"other");
+    assert_eq!(t!(std::fs::read(dst.join("hl"))), b"file");
+}
+
+#[test]
+fn allowed_entry_types() {
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, kind) in &[
+        ("dir", tar::EntryType::Directory),
+        ("dir/file", tar::EntryType::Regular),
+        ("dir/device", tar::EntryType::Char),
+        ("dir/after", tar::EntryType::Regular),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(kind);
+        t!(header.set_path(path));
+        header.set_mode(0o644);
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+    let allowed = [
+        tar::EntryType::Regular,
+        tar::EntryType::Directory,
+        tar::EntryType::Symlink,
+    ];
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allowed_entry_types(&allowed);
+    let err = ar.unpack(td.path()).unwrap_err();
+    assert!(format!("{:?}", err).contains("not allowed"), "{:?}", err);
+    assert!(td.path().join("dir/file").exists());
+    assert!(!td.path().join("dir/device").exists());
+    assert!(!td.path().join("dir/after").exists());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allowed_entry_types(&allowed);
+    ar.set_skip_disallowed_types(true);
+    t!(ar.unpack(td.path()));
+    assert!(!td.path().join("dir/device").exists());
+    assert!(td.path().join("dir/after").exists());
+}
+
+#[test]
+fn symlinks_and_dirs_unpack_as_such() {
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, kind, link) in &[
+        ("dir", tar::EntryType::Directory, None),
+        ("dir/target", tar::EntryType::Regular, None),
+        ("dir/link", tar::EntryType::Symlink, Some("target")),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(kind);
+        header.set_mode(0o755);
+        t!(header.set_path(path));
+        if let Some(link) = link {
+            t!(header.set_link_name(link));
+        }
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    t!(ar.unpack(td.path()));
+
+    assert!(t!(td.path().join("dir").symlink_metadata()).is_dir());
+    let link = td.path().join("dir/link");
+    assert!(t!(link.symlink_metadata()).file_type().is_symlink());
+    assert_eq!(t!(std::fs::read_link(&link)), Path::new("target"));
+}
@@ -5,7 +5,7 @@ use std::{iter, mem, thread, time};
 
 use tempfile::Builder;
 
-use tar::{GnuHeader, Header, HeaderMode};
+use tar::{EntryType, GnuHeader, Header, HeaderBuilder, HeaderFormat, HeaderMode};
 
 #[test]
 fn default_gnu() {
@@ -34,6 +34,58 @@ fn goto_ustar() {
     assert!(h.as_ustar_mut().is_some());
 }
 
+#[test]
+fn eq_ignoring_checksum() {
+    let mut h1 = Header::new_ustar();
+    t!(h1.set_path("foo"));
+    h1.set_size(3);
+    h1.set_cksum();
+    let cksum = t!(h1.cksum());
+
+    // Same value, but space terminated and without leading zeros
+    let mut h2 = h1.clone();
+    let field = format!("{:o} ", cksum);
+    h2.as_old_mut().cksum = [0; 8];
+    h2.as_old_mut().cksum[..field.len()].copy_from_slice(field.as_bytes());
+    assert!(h1.as_bytes()[..] != h2.as_bytes()[..]);
+    assert_eq!(t!(h2.cksum()), cksum);
+    assert!(h1.eq_ignoring_checksum(&h2));
+
+    h2.set_size(4);
+    assert!(!h1.eq_ignoring_checksum(&h2));
+}
+
+#[test]
+fn magic_and_version() {
+    let h = Header::new_gnu();
+    assert_eq!(h.magic(), b"ustar ");
+    assert_eq!(h.version(), b" \0");
+    assert_eq!(h.format(), HeaderFormat::Gnu);
+
+    let h = Header::new_ustar();
+    assert_eq!(h.magic(), b"ustar\0");
+    assert_eq!(h.version(), b"00");
+    assert_eq!(h.format(), HeaderFormat::Ustar);
+
+    let h = Header::new_old();
+    assert_eq!(h.magic(), &[0; 6]);
+    assert_eq!(h.format(), HeaderFormat::Old);
+
+    // A GNU header whose atime overlaps the UStar prefix must not have that
+    // field interpreted as part of the path.
+    let mut h = Header::new_gnu();
+    t!(h.set_path("foo"));
+    h.as_gnu_mut().unwrap().atime = *b"12345670123\0";
+    assert_eq!(t!(h.path()).to_str(), Some("foo"));
+
+    // Mixing up the UStar magic with the GNU version matches neither format
+    let mut h = Header::new_ustar();
+    h.as_ustar_mut().unwrap().version = *b" \0";
+    assert_eq!(h.format(), HeaderFormat::Old);
+    assert!(h.as_ustar().is_none());
+    assert!(h.as_gnu().is_none());
+}
+
 #[test]
 fn link_name() {
     let mut h = Header::new_gnu();
@@ -172,6 +224,32 @@ fn set_ustar_path_hard() {
     assert_eq!(t!(h.path()), p);
 }
 
+#[test]
+fn set_ustar_path_split() {
+    let mut h = Header::new_ustar();
+    let long = Path::new(&"a".repeat(150)).join("b".repeat(100));
+    t!(h.set_path(&long));
+    assert_eq!(t!(h.path()), long);
+    assert_eq!(
+        h.as_ustar().unwrap().prefix[..150],
+        *"a".repeat(150).as_bytes()
+    );
+
+    // A shorter path replaces the prefix as well
+    t!(h.set_path("c"));
+    assert_eq!(t!(h.path()), Path::new("c"));
+
+    // Paths which can't be split leave the header untouched
+    assert!(h.set_path("d".repeat(101)).is_err());
+    assert!(h.set_path(Path::new("e").join("f".repeat(101))).is_err());
+    assert!(h.set_path(Path::new(&"g".repeat(156)).join("h")).is_err());
+    assert_eq!(t!(h.path()), Path::new("c"));
+
+    assert!(h.set_link_name("i".repeat(101)).is_err());
+    t!(h.set_link_name("i".repeat(100)));
+    assert_eq!(t!(h.link_name()).unwrap(), Path::new(&"i".repeat(100)));
+}
+
 #[test]
 fn set_metadata_deterministic() {
     let td = t!(Builder::new().prefix("tar-rs").tempdir());
@@ -206,6 +284,35 @@ fn set_metadata_deterministic() {
     assert_eq!(t!(one.gid()), t!(two.gid()));
 }
 
+#[test]
+fn set_metadata_complete() {
+    let td = t!(Builder::new().prefix("tar-rs").tempdir());
+    let file = td.path().join("file");
+    t!(fs::write(&file, b"hello"));
+
+    let meta = t!(file.metadata());
+    let mut h = Header::new_gnu();
+    h.set_metadata_in_mode(&meta, HeaderMode::Complete);
+    assert!(h.entry_type().is_file());
+    assert_eq!(t!(h.size()), 5);
+    assert_eq!(t!(h.device_major()), Some(0));
+
+    let meta = t!(td.path().metadata());
+    h.set_metadata_in_mode(&meta, HeaderMode::Complete);
+    assert!(h.entry_type().is_dir());
+    assert_eq!(t!(h.size()), 0);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::prelude::*;
+
+        assert_eq!(t!(h.mode()), meta.mode());
+        assert_eq!(t!(h.uid()), meta.uid() as u64);
+        assert_eq!(t!(h.gid()), meta.gid() as u64);
+        assert_eq!(t!(h.mtime()), meta.mtime() as u64);
+    }
+}
+
 #[test]
 fn extended_numeric_format() {
     let mut h: GnuHeader = unsafe { mem::zeroed() };
@@ -233,6 +340,129 @@ fn extended_numeric_format() {
     assert_eq!(h.as_header().mtime().unwrap(), 0x0123456789abcdef);
 }
 
+#[test]
+fn extended_numeric_format_boundaries() {
+    let mut h: GnuHeader = unsafe { mem::zeroed() };
+
+    // Only the low bytes used, with leading zeros
+    h.size = [0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(t!(h.as_header().entry_size()), 0);
+    h.size = [0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01];
+    assert_eq!(t!(h.as_header().entry_size()), 1);
+    h.size = [0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x12, 0x34];
+    assert_eq!(t!(h.as_header().entry_size()), 0x1234);
+
+    // The largest value which exactly fits in a u64
+    h.size = [
+        0x80, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ];
+    assert_eq!(t!(h.as_header().entry_size()), u64::MAX);
+    h.size = [0x80, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(t!(h.as_header().entry_size()), 1 << 63);
+
+    // Any of the 11 payload bytes beyond the last 8 being set overflows
+    h.size = [0x80, 0, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(h.as_header().entry_size().is_err());
+    h.size = [0x80, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(h.as_header().entry_size().is_err());
+    h.size = [0x81, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01];
+    assert!(h.as_header().entry_size().is_err());
+
+    // Negative values can't be sizes
+    h.size = [0xff; 12];
+    assert!(h.as_header().entry_size().is_err());
+
+    // 8-byte fields use all 63 bits after the flag
+    h.uid = [0x80, 0, 0, 0, 0, 0, 0, 0x01];
+    assert_eq!(t!(h.as_header().uid()), 1);
+    h.uid = [0xff; 8];
+    assert_eq!(t!(h.as_header().uid()), 0x7fffffffffffffff);
+}
+
+#[test]
+fn gnu_times_extended_numeric_format() {
+    let mut h: GnuHeader = unsafe { mem::zeroed() };
+    h.set_atime(1234);
+    assert_eq!(h.atime().unwrap(), 1234);
+    assert_eq!(h.atime_signed().unwrap(), 1234);
+
+    // Times far in the future use the positive base-256 form
+    h.ctime = [0x80, 0, 0, 0, 0, 0, 0, 0x01, 0x23, 0x45, 0x67, 0x89];
+    assert_eq!(h.ctime().unwrap(), 0x0123456789);
+    assert_eq!(h.ctime_signed().unwrap(), 0x0123456789);
+
+    // Times before the epoch use the negative (two's complement) form, which
+    // only the signed accessors can return
+    h.ctime = [0xff; 12];
+    assert_eq!(h.ctime_signed().unwrap(), -1);
+    assert!(h.ctime().is_err());
+    h.atime = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0x0c,
+    ];
+    assert_eq!(h.atime_signed().unwrap(), -500);
+    h.atime = [0xc0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(h.atime_signed().is_err());
+    assert!(h.atime().is_err());
+
+    // Values which don't fit in an i64 are an error rather than wrapping
+    h.atime = [0x80, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(h.atime_signed().is_err());
+}
+
+#[test]
+fn entry_type_from_typeflag() {
+    let types = [
+        (b'0', EntryType::Regular),
+        (b'1', EntryType::Link),
+        (b'2', EntryType::Symlink),
+        (b'3', EntryType::Char),
+        (b'4', EntryType::Block),
+        (b'5', EntryType::Directory),
+        (b'6', EntryType::Fifo),
+        (b'7', EntryType::Continuous),
+        (b'L', EntryType::GNULongName),
+        (b'K', EntryType::GNULongLink),
+        (b'S', EntryType::GNUSparse),
+        (b'x', EntryType::XHeader),
+        (b'g', EntryType::XGlobalHeader),
+    ];
+    let mut h = Header::new_ustar();
+    for &(byte, ty) in types.iter() {
+        h.as_old_mut().linkflag[0] = byte;
+        assert_eq!(h.entry_type(), ty);
+        assert_eq!(ty.as_byte(), byte);
+    }
+
+    // Old archives use a nul typeflag for regular files
+    h.as_old_mut().linkflag[0] = 0;
+    assert_eq!(h.entry_type(), EntryType::Regular);
+    assert!(h.entry_type().is_file());
+
+    // Unknown typeflags are kept as they are
+    h.as_old_mut().linkflag[0] = b'Z';
+    assert_eq!(h.entry_type().as_byte(), b'Z');
+    assert!(!h.entry_type().is_file());
+}
+
+#[test]
+fn gnu_multivolume_offset() {
+    let mut h = Header::new_gnu();
+    h.set_size(100);
+    h.as_gnu_mut().unwrap().offset = *b"00000001000\0";
+    h.as_gnu_mut().unwrap().realsize = *b"00000001144\0";
+    assert_eq!(t!(h.as_gnu().unwrap().offset()), None);
+
+    h.set_entry_type(EntryType::new(b'M'));
+    assert!(h.entry_type().is_gnu_multivolume());
+    let gnu = h.as_gnu().unwrap();
+    assert_eq!(t!(gnu.offset()), Some(512));
+    assert_eq!(t!(gnu.real_size()), 612);
+
+    h.set_entry_type(EntryType::new(b'D'));
+    assert!(h.entry_type().is_gnu_dumpdir());
+    assert_eq!(t!(h.as_gnu().unwrap().offset()), None);
+}
+
 #[test]
 fn byte_slice_conversion() {
     let h = Header::new_gnu();
@@ -240,3 +470,53 @@ fn byte_slice_conversion() {
     let b_conv: &[u8] = Header::from_byte_slice(h.as_bytes()).as_bytes();
     assert_eq!(b, b_conv);
 }
+
+#[test]
+fn header_builder() {
+    let h = t!(HeaderBuilder::new_ustar()
+        .path("foo/bar")
+        .link_name("baz")
+        .mode(0o755)
+        .uid(1)
+        .gid(2)
+        .size(3)
+        .mtime(4)
+        .entry_type(EntryType::Symlink)
+        .username("user")
+        .groupname("group")
+        .build());
+    assert_eq!(t!(h.path()).to_str(), Some("foo/bar"));
+    assert_eq!(t!(h.link_name()).unwrap().to_str(), Some("baz"));
+    assert_eq!(t!(h.mode()), 0o755);
+    assert_eq!(t!(h.uid()), 1);
+    assert_eq!(t!(h.gid()), 2);
+    assert_eq!(t!(h.size()), 3);
+    assert_eq!(t!(h.mtime()), 4);
+    assert_eq!(h.entry_type(), EntryType::Symlink);
+    assert_eq!(h.username(), Ok(Some("user")));
+    assert_eq!(h.groupname(), Ok(Some("group")));
+
+    let mut expected = h.clone();
+    expected.set_cksum();
+    assert_eq!(t!(h.cksum()), t!(expected.cksum()));
+}
+
+#[test]
+fn header_builder_validation() {
+    let long = "a".repeat(101);
+    assert!(HeaderBuilder::new_gnu().path(&long).build().is_err());
+    assert!(HeaderBuilder::new_gnu().path("../foo").build().is_err());
+    assert!(HeaderBuilder::new_gnu().link_name(&long).build().is_err());
+    assert!(HeaderBuilder::new_gnu().mode(0o10000000).build().is_err());
+    assert!(HeaderBuilder::new_gnu().username(&long).build().is_err());
+    assert!(HeaderBuilder::new_old().groupname("group").build().is_err());
+
+    // The first error is reported even if later fields are fine
+    let err = HeaderBuilder::new_gnu()
+        .mode(0o10000000)
+        .path("foo")
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("mode"), "{}", err);
+}